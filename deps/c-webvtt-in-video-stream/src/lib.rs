@@ -1,17 +1,26 @@
 use std::{
+    cell::RefCell,
     error::Error,
-    ffi::{c_char, CStr},
+    ffi::{c_char, c_void, CStr},
     time::Duration,
 };
 use strum_macros::FromRepr;
 use video_bytestream_tools::{
     av1,
+    cea708::CcDataWrite,
     h264::{self, H264ByteStreamWrite, H264NalHeader},
     h265::{self, H265ByteStreamWrite, H265NalHeader},
-    h26x::{NalUnitWrite, RbspWrite},
+    h266::{self, H266ByteStreamWrite, H266NalHeader},
+    h26x::{
+        access_unit::{sei_insertion_offset, NalUnitFraming, VideoCodec},
+        NalUnitWrite, RbspWrite,
+    },
     webvtt::WebvttWrite,
 };
-use webvtt_in_video_stream::{WebvttMuxer, WebvttMuxerBuilder, WebvttString};
+use webvtt_in_video_stream::{
+    CaptionMode, CatchUp, CatchUpPolicy, Discontinuity, HeaderPolicy, MuxOutcome, RollUpRows,
+    WebvttMuxer, WebvttMuxerBuilder, WebvttString,
+};
 
 #[no_mangle]
 pub extern "C" fn webvtt_create_muxer_builder(
@@ -26,11 +35,15 @@ pub extern "C" fn webvtt_create_muxer_builder(
     ))
 }
 
-fn turn_into_webvtt_string(ptr: *const c_char) -> Option<WebvttString> {
+fn turn_into_c_str<'a>(ptr: *const c_char) -> Option<&'a CStr> {
     if ptr.is_null() {
         return None;
     }
-    let c_str = unsafe { CStr::from_ptr(ptr) };
+    Some(unsafe { CStr::from_ptr(ptr) })
+}
+
+fn turn_into_webvtt_string(ptr: *const c_char) -> Option<WebvttString> {
+    let c_str = turn_into_c_str(ptr)?;
     WebvttString::from_string(c_str.to_string_lossy().into_owned()).ok()
 }
 
@@ -67,6 +80,127 @@ pub extern "C" fn webvtt_muxer_builder_add_track(
         .is_ok()
 }
 
+#[derive(FromRepr, Copy, Clone)]
+#[repr(u8)]
+enum WebvttCatchUpPolicy {
+    OneChunkPerPacket,
+    EmitMultipleChunks,
+    CoalesceChunks,
+    SkipChunks,
+}
+
+/// Configure what happens when more than one chunk is due for a packet, `max_chunks` is only
+/// used by `EmitMultipleChunks`.
+#[no_mangle]
+pub extern "C" fn webvtt_muxer_builder_set_catch_up_policy(
+    builder: Option<&mut WebvttMuxerBuilder>,
+    catch_up_policy: u8,
+    max_chunks: u32,
+) -> bool {
+    let Some(builder) = builder else { return false };
+    let Some(catch_up_policy) = WebvttCatchUpPolicy::from_repr(catch_up_policy) else {
+        return false;
+    };
+    builder.set_catch_up_policy(match catch_up_policy {
+        WebvttCatchUpPolicy::OneChunkPerPacket => CatchUpPolicy::OneChunkPerPacket,
+        WebvttCatchUpPolicy::EmitMultipleChunks => CatchUpPolicy::EmitMultiple { max_chunks },
+        WebvttCatchUpPolicy::CoalesceChunks => CatchUpPolicy::Coalesce,
+        WebvttCatchUpPolicy::SkipChunks => CatchUpPolicy::Skip,
+    });
+    true
+}
+
+// the variants end up unscoped in C
+#[allow(clippy::enum_variant_names)]
+#[derive(FromRepr, Copy, Clone)]
+#[repr(u8)]
+enum WebvttCaptionMode {
+    CaptionPopOn,
+    CaptionRollUp2,
+    CaptionRollUp3,
+    CaptionRollUp4,
+}
+
+/// Also emit the cues of `track` as CEA-608/708 captions in ITU-T T.35 SEI or AV1 metadata.
+#[no_mangle]
+pub extern "C" fn webvtt_muxer_builder_set_caption_track(
+    builder: Option<&mut WebvttMuxerBuilder>,
+    track: u8,
+    caption_mode: u8,
+) -> bool {
+    let Some(builder) = builder else { return false };
+    let Some(caption_mode) = WebvttCaptionMode::from_repr(caption_mode) else {
+        return false;
+    };
+    builder.set_caption_track(
+        track,
+        match caption_mode {
+            WebvttCaptionMode::CaptionPopOn => CaptionMode::PopOn,
+            WebvttCaptionMode::CaptionRollUp2 => CaptionMode::RollUp {
+                rows: RollUpRows::Two,
+            },
+            WebvttCaptionMode::CaptionRollUp3 => CaptionMode::RollUp {
+                rows: RollUpRows::Three,
+            },
+            WebvttCaptionMode::CaptionRollUp4 => CaptionMode::RollUp {
+                rows: RollUpRows::Four,
+            },
+        },
+    );
+    true
+}
+
+/// Also emit a timecode with every frame (AV1 timecode metadata or H.265/H.266 `time_code`
+/// SEI), starting at `time_of_day_in_msecs` for the first frame.
+///
+/// H.264 streams and `H265AnnexBSuffixSei` get no timecodes: the H.264 `pic_timing` is
+/// written by the encoder, and `time_code` isn't allowed in suffix SEI.
+#[no_mangle]
+pub extern "C" fn webvtt_muxer_builder_set_timecode_start(
+    builder: Option<&mut WebvttMuxerBuilder>,
+    time_of_day_in_msecs: u64,
+) -> bool {
+    let Some(builder) = builder else { return false };
+    builder.set_timecode_start(Duration::from_millis(time_of_day_in_msecs));
+    true
+}
+
+// the variants end up unscoped in C
+#[allow(clippy::enum_variant_names)]
+#[derive(FromRepr, Copy, Clone)]
+#[repr(u8)]
+enum WebvttHeaderPolicy {
+    HeaderEveryKeyframe,
+    HeaderInterval,
+    HeaderEveryChunk,
+    HeaderOnTrackChanges,
+    HeaderFirstPacketOnly,
+}
+
+/// Configure when the track header is repeated, `interval_in_msecs` is only used by
+/// `HeaderInterval`.
+#[no_mangle]
+pub extern "C" fn webvtt_muxer_builder_set_header_policy(
+    builder: Option<&mut WebvttMuxerBuilder>,
+    header_policy: u8,
+    interval_in_msecs: u32,
+) -> bool {
+    let Some(builder) = builder else { return false };
+    let Some(header_policy) = WebvttHeaderPolicy::from_repr(header_policy) else {
+        return false;
+    };
+    builder.set_header_policy(match header_policy {
+        WebvttHeaderPolicy::HeaderEveryKeyframe => HeaderPolicy::EveryKeyframe,
+        WebvttHeaderPolicy::HeaderInterval => {
+            HeaderPolicy::Interval(Duration::from_millis(interval_in_msecs.into()))
+        }
+        WebvttHeaderPolicy::HeaderEveryChunk => HeaderPolicy::EveryChunk,
+        WebvttHeaderPolicy::HeaderOnTrackChanges => HeaderPolicy::OnTrackChanges,
+        WebvttHeaderPolicy::HeaderFirstPacketOnly => HeaderPolicy::FirstPacketOnly,
+    });
+    true
+}
+
 #[no_mangle]
 pub extern "C" fn webvtt_muxer_builder_create_muxer(
     muxer_builder: Option<Box<WebvttMuxerBuilder>>,
@@ -77,6 +211,40 @@ pub extern "C" fn webvtt_muxer_builder_create_muxer(
 #[no_mangle]
 pub extern "C" fn webvtt_muxer_free(_: Option<Box<WebvttMuxer>>) {}
 
+/// Add a track to a running muxer, the track index is the number of tracks added before.
+#[no_mangle]
+pub extern "C" fn webvtt_muxer_add_track(
+    muxer: Option<&WebvttMuxer>,
+    default: bool,
+    autoselect: bool,
+    forced: bool,
+    name_ptr: *const c_char,
+    language_ptr: *const c_char,
+    assoc_language_ptr: *const c_char,
+    characteristics_ptr: *const c_char,
+) -> bool {
+    let Some(muxer) = muxer else { return false };
+    let Some(name) = turn_into_webvtt_string(name_ptr) else {
+        return false;
+    };
+    let Some(language) = turn_into_webvtt_string(language_ptr) else {
+        return false;
+    };
+    let assoc_language = turn_into_webvtt_string(assoc_language_ptr);
+    let characteristics = turn_into_webvtt_string(characteristics_ptr);
+    muxer
+        .add_track(
+            default,
+            autoselect,
+            forced,
+            name,
+            language,
+            assoc_language,
+            characteristics,
+        )
+        .is_ok()
+}
+
 #[no_mangle]
 pub extern "C" fn webvtt_muxer_add_cue(
     muxer: Option<&WebvttMuxer>,
@@ -99,6 +267,53 @@ pub extern "C" fn webvtt_muxer_add_cue(
         .is_ok()
 }
 
+/// Stop emitting chunks until `webvtt_muxer_resume` is called, e.g. while a recording is paused.
+/// The timestamp is on the same clock as the cue start times passed to `webvtt_muxer_add_cue`.
+#[no_mangle]
+pub extern "C" fn webvtt_muxer_pause(muxer: Option<&WebvttMuxer>, timestamp_in_msecs: u64) -> bool {
+    let Some(muxer) = muxer else { return false };
+    muxer.pause(Duration::from_millis(timestamp_in_msecs));
+    true
+}
+
+/// Resume a paused muxer, cues added afterwards are shifted back by the paused duration.
+#[no_mangle]
+pub extern "C" fn webvtt_muxer_resume(
+    muxer: Option<&WebvttMuxer>,
+    timestamp_in_msecs: u64,
+) -> bool {
+    let Some(muxer) = muxer else { return false };
+    muxer.resume(Duration::from_millis(timestamp_in_msecs));
+    true
+}
+
+#[derive(FromRepr, Copy, Clone)]
+#[repr(u8)]
+enum WebvttDiscontinuity {
+    RebaseTimeline,
+    RestartChunkNumbering,
+}
+
+/// Signal a discontinuity in the video timestamps (encoder restart, timestamp wrap, reconnect).
+/// A fresh header is written with the next packet; pending cues are kept if `retain_cues` is set.
+#[no_mangle]
+pub extern "C" fn webvtt_muxer_signal_discontinuity(
+    muxer: Option<&WebvttMuxer>,
+    discontinuity: u8,
+    retain_cues: bool,
+) -> bool {
+    let Some(muxer) = muxer else { return false };
+    let Some(discontinuity) = WebvttDiscontinuity::from_repr(discontinuity) else {
+        return false;
+    };
+    let discontinuity = match discontinuity {
+        WebvttDiscontinuity::RebaseTimeline => Discontinuity::Rebase,
+        WebvttDiscontinuity::RestartChunkNumbering => Discontinuity::RestartChunkNumbering,
+    };
+    muxer.signal_discontinuity(discontinuity, retain_cues);
+    true
+}
+
 #[derive(FromRepr, Copy, Clone)]
 #[repr(u8)]
 enum CodecFlavor {
@@ -108,6 +323,14 @@ enum CodecFlavor {
     H264AnnexB,
     H265AnnexB,
     AV1OBUs,
+    /// H.265 Annex B with suffix SEI NAL units, which can be appended after the VCL NAL units
+    H265AnnexBSuffixSei,
+    H265Hvcc1,
+    H265Hvcc2,
+    H265Hvcc4,
+    H266AnnexB,
+    /// AV1 in the length delimited format of AV1 Annex B
+    AV1AnnexB,
 }
 
 impl CodecFlavor {
@@ -118,11 +341,65 @@ impl CodecFlavor {
             CodecFlavor::H264Avcc4 => CodecFlavorInternal::H264(CodecFlavorH264::Avcc(4)),
             CodecFlavor::H264AnnexB => CodecFlavorInternal::H264(CodecFlavorH264::AnnexB),
             CodecFlavor::H265AnnexB => CodecFlavorInternal::H265(CodecFlavorH265::AnnexB),
-            CodecFlavor::AV1OBUs => CodecFlavorInternal::AV1,
+            CodecFlavor::AV1OBUs => CodecFlavorInternal::AV1(CodecFlavorAV1::LowOverhead),
+            CodecFlavor::H265AnnexBSuffixSei => {
+                CodecFlavorInternal::H265(CodecFlavorH265::AnnexBSuffixSei)
+            }
+            CodecFlavor::H265Hvcc1 => CodecFlavorInternal::H265(CodecFlavorH265::Hvcc(1)),
+            CodecFlavor::H265Hvcc2 => CodecFlavorInternal::H265(CodecFlavorH265::Hvcc(2)),
+            CodecFlavor::H265Hvcc4 => CodecFlavorInternal::H265(CodecFlavorH265::Hvcc(4)),
+            CodecFlavor::H266AnnexB => CodecFlavorInternal::H266(CodecFlavorH266::AnnexB),
+            CodecFlavor::AV1AnnexB => CodecFlavorInternal::AV1(CodecFlavorAV1::AnnexB),
         }
     }
 }
 
+/// Derive the `CodecFlavor` for an encoder from its codec name (`h264`, `hevc` or `av1`) and
+/// extradata. Extradata starting with a start code (or no extradata) means Annex B, otherwise it
+/// is parsed as `avcC`/`hvcC` to find the NAL unit length size.
+#[no_mangle]
+pub extern "C" fn webvtt_codec_flavor_from_extradata(
+    codec_ptr: *const c_char,
+    extradata: *const u8,
+    extradata_length: usize,
+    codec_flavor: Option<&mut u8>,
+) -> bool {
+    let Some(codec_flavor) = codec_flavor else {
+        return false;
+    };
+    let Some(codec) = turn_into_c_str(codec_ptr) else {
+        return false;
+    };
+    let extradata = turn_into_slice(extradata, extradata_length);
+    let annex_b = extradata.is_empty()
+        || extradata.starts_with(&[0, 0, 1])
+        || extradata.starts_with(&[0, 0, 0, 1]);
+    let flavor = match codec.to_bytes() {
+        b"h264" if annex_b => CodecFlavor::H264AnnexB,
+        b"h264" => match h264::avcc::AVCDecoderConfigurationRecord::parse(extradata) {
+            Ok(record) => match record.length_size {
+                1 => CodecFlavor::H264Avcc1,
+                2 => CodecFlavor::H264Avcc2,
+                _ => CodecFlavor::H264Avcc4,
+            },
+            Err(_) => return false,
+        },
+        b"hevc" if annex_b => CodecFlavor::H265AnnexB,
+        b"hevc" => match h265::hvcc::HEVCDecoderConfigurationRecord::parse(extradata) {
+            Ok(record) => match record.length_size {
+                1 => CodecFlavor::H265Hvcc1,
+                2 => CodecFlavor::H265Hvcc2,
+                _ => CodecFlavor::H265Hvcc4,
+            },
+            Err(_) => return false,
+        },
+        b"av1" => CodecFlavor::AV1OBUs,
+        _ => return false,
+    };
+    *codec_flavor = flavor as u8;
+    true
+}
+
 enum CodecFlavorH264 {
     Avcc(usize),
     AnnexB,
@@ -130,16 +407,347 @@ enum CodecFlavorH264 {
 
 enum CodecFlavorH265 {
     AnnexB,
+    AnnexBSuffixSei,
+    Hvcc(usize),
+}
+
+enum CodecFlavorH266 {
+    AnnexB,
+}
+
+enum CodecFlavorAV1 {
+    LowOverhead,
+    AnnexB,
 }
 
 enum CodecFlavorInternal {
     H264(CodecFlavorH264),
     H265(CodecFlavorH265),
-    AV1,
+    H266(CodecFlavorH266),
+    AV1(CodecFlavorAV1),
 }
 
-pub struct WebvttBuffer(Vec<u8>);
+impl CodecFlavorInternal {
+    /// `None` for bytestreams where the muxed data is appended to the packet
+    fn nal_unit_layout(&self) -> Option<(NalUnitFraming, VideoCodec)> {
+        match self {
+            CodecFlavorInternal::H264(CodecFlavorH264::Avcc(length_size)) => Some((
+                NalUnitFraming::LengthPrefixed {
+                    length_size: *length_size,
+                },
+                VideoCodec::H264,
+            )),
+            CodecFlavorInternal::H264(CodecFlavorH264::AnnexB) => {
+                Some((NalUnitFraming::AnnexB, VideoCodec::H264))
+            }
+            CodecFlavorInternal::H265(CodecFlavorH265::AnnexB) => {
+                Some((NalUnitFraming::AnnexB, VideoCodec::H265))
+            }
+            CodecFlavorInternal::H265(CodecFlavorH265::Hvcc(length_size)) => Some((
+                NalUnitFraming::LengthPrefixed {
+                    length_size: *length_size,
+                },
+                VideoCodec::H265,
+            )),
+            CodecFlavorInternal::H266(CodecFlavorH266::AnnexB) => {
+                Some((NalUnitFraming::AnnexB, VideoCodec::H266))
+            }
+            CodecFlavorInternal::H265(CodecFlavorH265::AnnexBSuffixSei)
+            | CodecFlavorInternal::AV1(_) => None,
+        }
+    }
+}
 
+pub struct WebvttBuffer {
+    data: Vec<u8>,
+    catch_up: CatchUp,
+    expired_chunks: u64,
+}
+
+#[repr(u8)]
+enum WebvttCatchUp {
+    NoCatchUp,
+    ChunksLagging,
+    ChunksEmitted,
+    ChunksCoalesced,
+    ChunksSkipped,
+}
+
+impl WebvttCatchUp {
+    fn from_catch_up(catch_up: Option<CatchUp>) -> Self {
+        match catch_up {
+            None | Some(CatchUp::None) => WebvttCatchUp::NoCatchUp,
+            Some(CatchUp::Lagging { .. }) => WebvttCatchUp::ChunksLagging,
+            Some(CatchUp::Emitted { .. }) => WebvttCatchUp::ChunksEmitted,
+            Some(CatchUp::Coalesced { .. }) => WebvttCatchUp::ChunksCoalesced,
+            Some(CatchUp::Skipped { .. }) => WebvttCatchUp::ChunksSkipped,
+        }
+    }
+}
+
+fn catch_up_chunks(catch_up: Option<CatchUp>) -> u64 {
+    match catch_up {
+        None | Some(CatchUp::None) => 0,
+        Some(CatchUp::Lagging { overdue_chunks }) => overdue_chunks,
+        Some(
+            CatchUp::Emitted { chunks, .. }
+            | CatchUp::Coalesced { chunks }
+            | CatchUp::Skipped { chunks },
+        ) => chunks,
+    }
+}
+
+fn catch_up_overdue_chunks(catch_up: Option<CatchUp>) -> u64 {
+    match catch_up {
+        Some(CatchUp::Lagging { overdue_chunks } | CatchUp::Emitted { overdue_chunks, .. }) => {
+            overdue_chunks
+        }
+        _ => 0,
+    }
+}
+
+/// Whether the caller or the header policy decides when to write the header
+#[derive(Clone, Copy)]
+enum HeaderRequest {
+    AddHeader(bool),
+    Keyframe(bool),
+}
+
+#[allow(clippy::too_many_arguments)]
+fn mux_into_bytestream<'a, W: WebvttWrite + CcDataWrite + 'a>(
+    muxer: &WebvttMuxer,
+    presentation_timestamp: Duration,
+    decode_timestamp: Duration,
+    header: HeaderRequest,
+    buffer: &'a mut Vec<u8>,
+    init: impl Fn(&'a mut Vec<u8>) -> Result<W, Box<dyn Error>>,
+    mux_timecode: impl FnOnce(&mut W) -> std::io::Result<bool>,
+    finish: impl Fn(W) -> Result<(), Box<dyn Error>>,
+) -> Result<MuxOutcome, Box<dyn Error>> {
+    let mut writer = init(buffer)?;
+    let mut outcome = match header {
+        HeaderRequest::AddHeader(add_header) => muxer
+            .try_mux_into_bytestream_with_decode_timestamp(
+                presentation_timestamp,
+                decode_timestamp,
+                add_header,
+                &mut writer,
+            )?,
+        HeaderRequest::Keyframe(keyframe) => muxer.try_mux_into_bytestream_with_header_policy(
+            presentation_timestamp,
+            decode_timestamp,
+            keyframe,
+            &mut writer,
+        )?,
+    };
+    outcome.data_written |= muxer.try_mux_captions(presentation_timestamp, &mut writer)?;
+    outcome.data_written |= mux_timecode(&mut writer)?;
+    if outcome.data_written {
+        finish(writer)?;
+    }
+    Ok(outcome)
+}
+
+fn create_nal_header() -> H264NalHeader {
+    H264NalHeader::from_nal_unit_type_and_nal_ref_idc(h264_reader::nal::UnitType::SEI, 0).unwrap()
+}
+
+fn create_h265_nal_header(nal_unit_type: h265::UnitType) -> H265NalHeader {
+    H265NalHeader::from_nal_unit_type_and_nuh_ids(nal_unit_type, 0, 0).unwrap()
+}
+
+fn create_h266_nal_header() -> H266NalHeader {
+    H266NalHeader::from_nal_unit_type_and_nuh_ids(h266::UnitType::PrefixSeiNut, 0, 0).unwrap()
+}
+
+fn mux_into_vec(
+    muxer: &WebvttMuxer,
+    presentation_timestamp_in_nsecs: u64,
+    decode_timestamp_in_nsecs: u64,
+    header: HeaderRequest,
+    codec_flavor: u8,
+    obu_extension_header: Option<av1::OBUExtensionHeader>,
+    buffer: &mut Vec<u8>,
+) -> Option<MuxOutcome> {
+    let presentation_timestamp = Duration::from_nanos(presentation_timestamp_in_nsecs);
+    let decode_timestamp = Duration::from_nanos(decode_timestamp_in_nsecs);
+    let codec_flavor = CodecFlavor::from_repr(codec_flavor)?;
+    let outcome = match codec_flavor.into_internal() {
+        CodecFlavorInternal::H264(CodecFlavorH264::AnnexB) => mux_into_bytestream(
+            muxer,
+            presentation_timestamp,
+            decode_timestamp,
+            header,
+            buffer,
+            |buffer| {
+                Ok(h264::annex_b::AnnexBWriter::new(buffer)
+                    .start_write_nal_unit()?
+                    .write_nal_header(create_nal_header())?)
+            },
+            // the encoder writes the pic_timing that would carry a timecode itself
+            |_| Ok(false),
+            |write| {
+                write.finish_rbsp()?;
+                Ok(())
+            },
+        )
+        .ok()?,
+        CodecFlavorInternal::H264(CodecFlavorH264::Avcc(length_size)) => mux_into_bytestream(
+            muxer,
+            presentation_timestamp,
+            decode_timestamp,
+            header,
+            buffer,
+            |buffer| {
+                Ok(h264::avcc::AVCCWriter::new(length_size, buffer)?
+                    .start_write_nal_unit()?
+                    .write_nal_header(create_nal_header())?)
+            },
+            // the encoder writes the pic_timing that would carry a timecode itself
+            |_| Ok(false),
+            |write| {
+                write.finish_rbsp()?;
+                Ok(())
+            },
+        )
+        .ok()?,
+
+        CodecFlavorInternal::H265(CodecFlavorH265::Hvcc(length_size)) => mux_into_bytestream(
+            muxer,
+            presentation_timestamp,
+            decode_timestamp,
+            header,
+            buffer,
+            |buffer| {
+                Ok(h265::hvcc::HVCCWriter::new(length_size, buffer)?
+                    .start_write_nal_unit()?
+                    .write_nal_header(create_h265_nal_header(h265::UnitType::PrefixSeiNut))?)
+            },
+            |writer| muxer.try_mux_timecode(presentation_timestamp, writer),
+            |write| {
+                write.finish_rbsp()?;
+                Ok(())
+            },
+        )
+        .ok()?,
+
+        CodecFlavorInternal::H265(
+            flavor @ (CodecFlavorH265::AnnexB | CodecFlavorH265::AnnexBSuffixSei),
+        ) => mux_into_bytestream(
+            muxer,
+            presentation_timestamp,
+            decode_timestamp,
+            header,
+            buffer,
+            |buffer| -> Result<h265::annex_b::AnnexBRbspWriter<_>, Box<dyn Error>> {
+                let nal_unit_type = match flavor {
+                    CodecFlavorH265::AnnexBSuffixSei => h265::UnitType::SuffixSeiNut,
+                    _ => h265::UnitType::PrefixSeiNut,
+                };
+                Ok(h265::annex_b::AnnexBWriter::new(buffer)
+                    .start_write_nal_unit()?
+                    .write_nal_header(create_h265_nal_header(nal_unit_type))?)
+            },
+            |writer| match flavor {
+                // time_code is a prefix SEI message
+                CodecFlavorH265::AnnexBSuffixSei => Ok(false),
+                _ => muxer.try_mux_timecode(presentation_timestamp, writer),
+            },
+            |write| {
+                write.finish_rbsp()?;
+                Ok(())
+            },
+        )
+        .ok()?,
+
+        CodecFlavorInternal::H266(CodecFlavorH266::AnnexB) => mux_into_bytestream(
+            muxer,
+            presentation_timestamp,
+            decode_timestamp,
+            header,
+            buffer,
+            |buffer| -> Result<h266::annex_b::AnnexBRbspWriter<_>, Box<dyn Error>> {
+                Ok(h266::annex_b::AnnexBWriter::new(buffer)
+                    .start_write_nal_unit()?
+                    .write_nal_header(create_h266_nal_header())?)
+            },
+            |writer| muxer.try_mux_timecode(presentation_timestamp, writer),
+            |write| {
+                write.finish_rbsp()?;
+                Ok(())
+            },
+        )
+        .ok()?,
+
+        CodecFlavorInternal::AV1(CodecFlavorAV1::LowOverhead) => mux_into_bytestream(
+            muxer,
+            presentation_timestamp,
+            decode_timestamp,
+            header,
+            buffer,
+            |buffer| {
+                Ok(match obu_extension_header {
+                    Some(header) => av1::OBUWriter::with_extension_header(buffer, header),
+                    None => av1::OBUWriter::new(buffer),
+                })
+            },
+            |writer| muxer.try_mux_timecode(presentation_timestamp, writer),
+            |_write| Ok(()),
+        )
+        .ok()?,
+
+        CodecFlavorInternal::AV1(CodecFlavorAV1::AnnexB) => mux_into_bytestream(
+            muxer,
+            presentation_timestamp,
+            decode_timestamp,
+            header,
+            buffer,
+            |buffer| {
+                Ok(match obu_extension_header {
+                    Some(header) => {
+                        av1::annex_b::AnnexBOBUWriter::with_extension_header(buffer, header)
+                    }
+                    None => av1::annex_b::AnnexBOBUWriter::new(buffer),
+                })
+            },
+            |writer| muxer.try_mux_timecode(presentation_timestamp, writer),
+            |_write| Ok(()),
+        )
+        .ok()?,
+    };
+    Some(outcome)
+}
+
+fn mux_into_buffer(
+    muxer: Option<&WebvttMuxer>,
+    presentation_timestamp_in_nsecs: u64,
+    decode_timestamp_in_nsecs: u64,
+    header: HeaderRequest,
+    codec_flavor: u8,
+) -> Option<Box<WebvttBuffer>> {
+    let mut buffer = vec![];
+    let outcome = mux_into_vec(
+        muxer?,
+        presentation_timestamp_in_nsecs,
+        decode_timestamp_in_nsecs,
+        header,
+        codec_flavor,
+        None,
+        &mut buffer,
+    )?;
+    if !outcome.data_written {
+        return None;
+    }
+    Some(Box::new(WebvttBuffer {
+        data: buffer,
+        catch_up: outcome.catch_up,
+        expired_chunks: outcome.expired_chunks,
+    }))
+}
+
+/// Writes the header if `add_header` is set (and with the first packet, after a discontinuity
+/// and after tracks were added), the header policy only applies to
+/// `webvtt_muxer_try_mux_into_bytestream_with_header_policy`.
 #[no_mangle]
 pub extern "C" fn webvtt_muxer_try_mux_into_bytestream(
     muxer: Option<&WebvttMuxer>,
@@ -147,119 +755,475 @@ pub extern "C" fn webvtt_muxer_try_mux_into_bytestream(
     add_header: bool,
     codec_flavor: u8,
 ) -> Option<Box<WebvttBuffer>> {
-    fn mux_into_bytestream<'a, W: WebvttWrite + 'a>(
-        muxer: &WebvttMuxer,
-        video_timestamp: Duration,
-        add_header: bool,
-        buffer: &'a mut Vec<u8>,
-        init: impl Fn(&'a mut Vec<u8>) -> Result<W, Box<dyn Error>>,
-        finish: impl Fn(W) -> Result<(), Box<dyn Error>>,
-    ) -> Result<bool, Box<dyn Error>> {
-        let mut writer = init(buffer)?;
-        if !muxer.try_mux_into_bytestream(video_timestamp, add_header, &mut writer)? {
-            return Ok(false);
+    mux_into_buffer(
+        muxer,
+        video_timestamp_in_nsecs,
+        video_timestamp_in_nsecs,
+        HeaderRequest::AddHeader(add_header),
+        codec_flavor,
+    )
+}
+
+/// Variant of `webvtt_muxer_try_mux_into_bytestream` for packets that arrive in decode order
+/// (e.g. with B-frames). Chunks are scheduled by the decode timestamp, video offsets are
+/// computed against the presentation timestamp.
+#[no_mangle]
+pub extern "C" fn webvtt_muxer_try_mux_into_bytestream_with_decode_timestamp(
+    muxer: Option<&WebvttMuxer>,
+    presentation_timestamp_in_nsecs: u64,
+    decode_timestamp_in_nsecs: u64,
+    add_header: bool,
+    codec_flavor: u8,
+) -> Option<Box<WebvttBuffer>> {
+    mux_into_buffer(
+        muxer,
+        presentation_timestamp_in_nsecs,
+        decode_timestamp_in_nsecs,
+        HeaderRequest::AddHeader(add_header),
+        codec_flavor,
+    )
+}
+
+/// Variant of `webvtt_muxer_try_mux_into_bytestream_with_decode_timestamp` that writes the
+/// header as the policy set with `webvtt_muxer_builder_set_header_policy` decides, given
+/// whether the packet is a `keyframe`.
+#[no_mangle]
+pub extern "C" fn webvtt_muxer_try_mux_into_bytestream_with_header_policy(
+    muxer: Option<&WebvttMuxer>,
+    presentation_timestamp_in_nsecs: u64,
+    decode_timestamp_in_nsecs: u64,
+    keyframe: bool,
+    codec_flavor: u8,
+) -> Option<Box<WebvttBuffer>> {
+    mux_into_buffer(
+        muxer,
+        presentation_timestamp_in_nsecs,
+        decode_timestamp_in_nsecs,
+        HeaderRequest::Keyframe(keyframe),
+        codec_flavor,
+    )
+}
+
+/// Appends `length` bytes to the caller's buffer and returns a pointer to them, or null if the
+/// buffer can't grow.
+pub type WebvttAppendFn = Option<extern "C" fn(context: *mut c_void, length: usize) -> *mut u8>;
+
+/// Result of muxing into a caller-provided buffer, `catch_up` is a `WebvttCatchUp`, see
+/// `webvtt_buffer_catch_up_chunks` and `webvtt_buffer_catch_up_overdue_chunks` for the counts
+/// and `webvtt_buffer_expired_chunks` for `expired_chunks`.
+/// `append_failed` is set if data was muxed but `append` returned null, the data is then passed
+/// back through `unappended`.
+#[repr(C)]
+pub struct WebvttMuxOutcome {
+    data_written: bool,
+    append_failed: bool,
+    catch_up: u8,
+    catch_up_chunks: u64,
+    catch_up_overdue_chunks: u64,
+    expired_chunks: u64,
+}
+
+impl WebvttMuxOutcome {
+    fn new(data_written: bool, outcome: Option<MuxOutcome>) -> Self {
+        let catch_up = outcome.map(|outcome| outcome.catch_up);
+        Self {
+            data_written,
+            append_failed: false,
+            catch_up: WebvttCatchUp::from_catch_up(catch_up) as u8,
+            catch_up_chunks: catch_up_chunks(catch_up),
+            catch_up_overdue_chunks: catch_up_overdue_chunks(catch_up),
+            expired_chunks: outcome.map_or(0, |outcome| outcome.expired_chunks),
         }
-        finish(writer)?;
-        Ok(true)
     }
+}
 
-    fn create_nal_header() -> H264NalHeader {
-        H264NalHeader::from_nal_unit_type_and_nal_ref_idc(h264_reader::nal::UnitType::SEI, 0)
-            .unwrap()
+thread_local! {
+    static SCRATCH_BUFFER: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Where the muxed data goes in the output packet
+enum PacketLayout<'a> {
+    Insert {
+        packet: &'a [u8],
+        offset: usize,
+        obu_extension_header: Option<av1::OBUExtensionHeader>,
+    },
+    Av1AnnexB(av1::annex_b::TemporalUnit<'a>),
+}
+
+impl<'a> PacketLayout<'a> {
+    /// `None` if the packet can't be parsed
+    fn new(flavor: CodecFlavor, packet: &'a [u8]) -> Option<Self> {
+        let flavor = flavor.into_internal();
+        let (offset, obu_extension_header) = match flavor {
+            CodecFlavorInternal::AV1(CodecFlavorAV1::AnnexB) => {
+                return av1::annex_b::TemporalUnit::parse(packet)
+                    .ok()
+                    .map(PacketLayout::Av1AnnexB);
+            }
+            CodecFlavorInternal::AV1(CodecFlavorAV1::LowOverhead) => {
+                let placement = av1::temporal_unit::metadata_placement(packet).ok()?;
+                (placement.offset, placement.obu_extension_header)
+            }
+            _ => match flavor.nal_unit_layout() {
+                Some((framing, codec)) => {
+                    (sei_insertion_offset(packet, framing, codec).ok()?, None)
+                }
+                None => (packet.len(), None),
+            },
+        };
+        Some(PacketLayout::Insert {
+            packet,
+            offset,
+            obu_extension_header,
+        })
     }
 
-    fn create_h265_nal_header() -> H265NalHeader {
-        H265NalHeader::from_nal_unit_type_and_nuh_ids(h265::UnitType::PrefixSeiNut, 0, 0).unwrap()
+    /// The extension header of the frame OBU, metadata OBUs carry it to apply to the same layer
+    fn obu_extension_header(&self) -> Option<av1::OBUExtensionHeader> {
+        match self {
+            PacketLayout::Insert {
+                obu_extension_header,
+                ..
+            } => *obu_extension_header,
+            PacketLayout::Av1AnnexB(temporal_unit) => {
+                temporal_unit.metadata_placement.obu_extension_header
+            }
+        }
     }
+}
 
-    fn inner(
-        muxer: Option<&WebvttMuxer>,
-        video_timestamp_in_nsecs: u64,
-        add_header: bool,
-        codec_flavor: u8,
-    ) -> Option<Box<WebvttBuffer>> {
-        let muxer = muxer?;
-        let video_timestamp = Duration::from_nanos(video_timestamp_in_nsecs);
-        let codec_flavor = CodecFlavor::from_repr(codec_flavor)?;
-        let mut buffer = vec![];
-        let data_written = match codec_flavor.into_internal() {
-            CodecFlavorInternal::H264(CodecFlavorH264::AnnexB) => mux_into_bytestream(
-                muxer,
-                video_timestamp,
-                add_header,
-                &mut buffer,
-                |buffer| {
-                    Ok(h264::annex_b::AnnexBWriter::new(buffer)
-                        .start_write_nal_unit()?
-                        .write_nal_header(create_nal_header())?)
-                },
-                |write| {
-                    write.finish_rbsp()?;
-                    Ok(())
-                },
-            )
-            .ok()?,
-            CodecFlavorInternal::H264(CodecFlavorH264::Avcc(length_size)) => mux_into_bytestream(
-                muxer,
-                video_timestamp,
-                add_header,
-                &mut buffer,
-                |buffer| {
-                    Ok(h264::avcc::AVCCWriter::new(length_size, buffer)?
-                        .start_write_nal_unit()?
-                        .write_nal_header(create_nal_header())?)
-                },
-                |write| {
-                    write.finish_rbsp()?;
-                    Ok(())
-                },
-            )
-            .ok()?,
+/// Copies `parts` into a single destination from `append`, returns false if it's null
+fn append_parts(
+    append: extern "C" fn(*mut c_void, usize) -> *mut u8,
+    context: *mut c_void,
+    parts: &[&[u8]],
+) -> bool {
+    let length = parts.iter().map(|part| part.len()).sum();
+    let destination = append(context, length);
+    if destination.is_null() {
+        return false;
+    }
+    let mut destination = unsafe { std::slice::from_raw_parts_mut(destination, length) };
+    for part in parts {
+        let (part_destination, rest) = destination.split_at_mut(part.len());
+        part_destination.copy_from_slice(part);
+        destination = rest;
+    }
+    true
+}
 
-            CodecFlavorInternal::H265(CodecFlavorH265::AnnexB) => mux_into_bytestream(
+/// Muxes into a reused per-thread buffer and appends the output packet described by `layout`
+/// with a single call to `append`. Nothing is appended if no data was muxed.
+///
+/// The muxer has moved on once data was muxed, so if it can't be appended it's passed back
+/// through `unappended` instead of being dropped.
+fn mux_into_sink(
+    layout: PacketLayout,
+    append: extern "C" fn(*mut c_void, usize) -> *mut u8,
+    context: *mut c_void,
+    unappended: Option<&mut Option<Box<WebvttBuffer>>>,
+    mux: impl FnOnce(Option<av1::OBUExtensionHeader>, &mut Vec<u8>) -> Option<MuxOutcome>,
+) -> WebvttMuxOutcome {
+    // taken out of the cell so a reentrant call from `append` doesn't panic
+    let mut buffer = SCRATCH_BUFFER.take();
+    buffer.clear();
+    let result = match mux(layout.obu_extension_header(), &mut buffer) {
+        Some(outcome) if outcome.data_written => {
+            let mut sizes = [0; 10];
+            let parts = match layout {
+                PacketLayout::Insert { packet, offset, .. } => {
+                    let (before, after) = packet.split_at(offset);
+                    Ok([&[][..], before, &buffer, after, &[]])
+                }
+                PacketLayout::Av1AnnexB(temporal_unit) => {
+                    temporal_unit.splice_parts(&buffer, &mut sizes)
+                }
+            };
+            let appended = parts.is_ok_and(|parts| append_parts(append, context, &parts));
+            let mut result = WebvttMuxOutcome::new(appended, Some(outcome));
+            if !appended {
+                result.append_failed = true;
+                if let Some(unappended) = unappended {
+                    *unappended = Some(Box::new(WebvttBuffer {
+                        data: std::mem::take(&mut buffer),
+                        catch_up: outcome.catch_up,
+                        expired_chunks: outcome.expired_chunks,
+                    }));
+                }
+            }
+            result
+        }
+        Some(outcome) => WebvttMuxOutcome::new(false, Some(outcome)),
+        None => WebvttMuxOutcome::new(false, None),
+    };
+    SCRATCH_BUFFER.set(buffer);
+    result
+}
+
+fn turn_into_slice<'a>(ptr: *const u8, length: usize) -> &'a [u8] {
+    if ptr.is_null() {
+        return &[];
+    }
+    unsafe { std::slice::from_raw_parts(ptr, length) }
+}
+
+/// Variant of `webvtt_muxer_try_mux_into_bytestream_with_header_policy` that appends to a
+/// caller-provided buffer through `append` instead of allocating a `WebvttBuffer`.
+///
+/// If `append` fails the muxed data is stored in `*unappended` (which must be NULL) so the
+/// caller can insert it itself, free it with `webvtt_buffer_free`. It's dropped if
+/// `unappended` is NULL.
+#[no_mangle]
+pub extern "C" fn webvtt_muxer_try_mux_into_sink(
+    muxer: Option<&WebvttMuxer>,
+    presentation_timestamp_in_nsecs: u64,
+    decode_timestamp_in_nsecs: u64,
+    keyframe: bool,
+    codec_flavor: u8,
+    append: WebvttAppendFn,
+    context: *mut c_void,
+    unappended: Option<&mut Option<Box<WebvttBuffer>>>,
+) -> WebvttMuxOutcome {
+    let (Some(muxer), Some(append)) = (muxer, append) else {
+        return WebvttMuxOutcome::new(false, None);
+    };
+    let layout = PacketLayout::Insert {
+        packet: &[],
+        offset: 0,
+        obu_extension_header: None,
+    };
+    mux_into_sink(
+        layout,
+        append,
+        context,
+        unappended,
+        |obu_extension_header, buffer| {
+            mux_into_vec(
                 muxer,
-                video_timestamp,
-                add_header,
-                &mut buffer,
-                |buffer| -> Result<h265::annex_b::AnnexBRbspWriter<_>, Box<dyn Error>> {
-                    Ok(h265::annex_b::AnnexBWriter::new(buffer)
-                        .start_write_nal_unit()?
-                        .write_nal_header(create_h265_nal_header())?)
-                },
-                |write| {
-                    write.finish_rbsp()?;
-                    Ok(())
-                },
+                presentation_timestamp_in_nsecs,
+                decode_timestamp_in_nsecs,
+                HeaderRequest::Keyframe(keyframe),
+                codec_flavor,
+                obu_extension_header,
+                buffer,
             )
-            .ok()?,
+        },
+    )
+}
 
-            CodecFlavorInternal::AV1 => mux_into_bytestream(
+/// Like `webvtt_muxer_try_mux_into_sink`, but appends the complete output packet, i.e. the
+/// `packet_length` bytes at `packet_data` with the SEI NAL units inserted before the first VCL
+/// NAL unit (after AUD and parameter sets). AV1 metadata OBUs are inserted after the temporal
+/// delimiter and sequence header, before the first frame OBU, for AV1 Annex B in the first frame
+/// unit with the temporal unit and frame unit sizes rewritten. If that frame OBU has an extension
+/// header the metadata OBUs carry it too, so they apply to the same layer.
+/// If no data was muxed or the packet can't be parsed nothing is appended and the original packet
+/// can be used as is. The packet is parsed before muxing, so the muxer keeps its data for the next
+/// packet if it can't be. If `append` fails the muxed data goes to `unappended` without the
+/// packet.
+#[no_mangle]
+pub extern "C" fn webvtt_muxer_try_mux_packet_into_sink(
+    muxer: Option<&WebvttMuxer>,
+    presentation_timestamp_in_nsecs: u64,
+    decode_timestamp_in_nsecs: u64,
+    keyframe: bool,
+    codec_flavor: u8,
+    packet_data: *const u8,
+    packet_length: usize,
+    append: WebvttAppendFn,
+    context: *mut c_void,
+    unappended: Option<&mut Option<Box<WebvttBuffer>>>,
+) -> WebvttMuxOutcome {
+    let (Some(muxer), Some(append)) = (muxer, append) else {
+        return WebvttMuxOutcome::new(false, None);
+    };
+    let Some(flavor) = CodecFlavor::from_repr(codec_flavor) else {
+        return WebvttMuxOutcome::new(false, None);
+    };
+    let packet = turn_into_slice(packet_data, packet_length);
+    // leave the cues for the next packet if it can't be parsed
+    let Some(layout) = PacketLayout::new(flavor, packet) else {
+        return WebvttMuxOutcome::new(false, None);
+    };
+    mux_into_sink(
+        layout,
+        append,
+        context,
+        unappended,
+        |obu_extension_header, buffer| {
+            mux_into_vec(
                 muxer,
-                video_timestamp,
-                add_header,
-                &mut buffer,
-                |buffer| Ok(av1::OBUWriter::new(buffer)),
-                |_write| Ok(()),
+                presentation_timestamp_in_nsecs,
+                decode_timestamp_in_nsecs,
+                HeaderRequest::Keyframe(keyframe),
+                codec_flavor,
+                obu_extension_header,
+                buffer,
             )
-            .ok()?,
-        };
-        if !data_written {
-            return None;
-        }
-        Some(Box::new(WebvttBuffer(buffer)))
-    }
-    inner(muxer, video_timestamp_in_nsecs, add_header, codec_flavor)
+        },
+    )
 }
 
 #[no_mangle]
 pub extern "C" fn webvtt_buffer_data(buffer: Option<&WebvttBuffer>) -> *const u8 {
-    buffer.map(|b| b.0.as_ptr()).unwrap_or(std::ptr::null())
+    buffer.map(|b| b.data.as_ptr()).unwrap_or(std::ptr::null())
 }
 
 #[no_mangle]
 pub extern "C" fn webvtt_buffer_length(buffer: Option<&WebvttBuffer>) -> usize {
-    buffer.map(|b| b.0.len()).unwrap_or(0)
+    buffer.map(|b| b.data.len()).unwrap_or(0)
+}
+
+/// How overdue chunks were handled while muxing this buffer, see `WebvttCatchUp`.
+#[no_mangle]
+pub extern "C" fn webvtt_buffer_catch_up(buffer: Option<&WebvttBuffer>) -> u8 {
+    WebvttCatchUp::from_catch_up(buffer.map(|b| b.catch_up)) as u8
+}
+
+/// Number of chunks lagging, emitted, coalesced or skipped, depending on `webvtt_buffer_catch_up`.
+#[no_mangle]
+pub extern "C" fn webvtt_buffer_catch_up_chunks(buffer: Option<&WebvttBuffer>) -> u64 {
+    catch_up_chunks(buffer.map(|b| b.catch_up))
+}
+
+/// Number of chunks that are still overdue after muxing this buffer, for `ChunksLagging` and
+/// `ChunksEmitted`, zero otherwise.
+#[no_mangle]
+pub extern "C" fn webvtt_buffer_catch_up_overdue_chunks(buffer: Option<&WebvttBuffer>) -> u64 {
+    catch_up_overdue_chunks(buffer.map(|b| b.catch_up))
+}
+
+/// Number of chunks that were dropped while muxing this buffer because they lagged too far
+/// behind the video to be attached to it.
+#[no_mangle]
+pub extern "C" fn webvtt_buffer_expired_chunks(buffer: Option<&WebvttBuffer>) -> u64 {
+    buffer.map(|b| b.expired_chunks).unwrap_or(0)
 }
 
 #[no_mangle]
 pub extern "C" fn webvtt_buffer_free(_: Option<Box<WebvttBuffer>>) {}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        webvtt_buffer_data, webvtt_buffer_length, webvtt_create_muxer_builder,
+        webvtt_muxer_builder_add_track, webvtt_muxer_builder_create_muxer,
+        webvtt_muxer_try_mux_into_bytestream_with_header_policy,
+        webvtt_muxer_try_mux_packet_into_sink, CodecFlavor, WebvttBuffer, WebvttMuxer,
+    };
+    use std::ffi::c_void;
+    use video_bytestream_tools::av1::annex_b::TemporalUnit;
+
+    const AUD: &[u8] = &[0, 0, 0, 1, 0x09, 0xf0];
+    const IDR_SLICE: &[u8] = &[0, 0, 1, 0x65, 0x88, 0x84];
+
+    fn muxer() -> Box<WebvttMuxer> {
+        let mut builder = webvtt_create_muxer_builder(500, 2, 40_000_000);
+        assert!(webvtt_muxer_builder_add_track(
+            Some(&mut builder),
+            true,
+            true,
+            false,
+            c"English".as_ptr(),
+            c"en".as_ptr(),
+            std::ptr::null(),
+            std::ptr::null(),
+        ));
+        webvtt_muxer_builder_create_muxer(Some(builder)).unwrap()
+    }
+
+    /// What a muxer in the same state muxes for the first keyframe, without the packet
+    fn muxed_data(codec_flavor: CodecFlavor) -> Vec<u8> {
+        let buffer = webvtt_muxer_try_mux_into_bytestream_with_header_policy(
+            Some(&muxer()),
+            0,
+            0,
+            true,
+            codec_flavor as u8,
+        )
+        .unwrap();
+        let data = webvtt_buffer_data(Some(&buffer));
+        let length = webvtt_buffer_length(Some(&buffer));
+        unsafe { std::slice::from_raw_parts(data, length) }.to_vec()
+    }
+
+    extern "C" fn append_to_vec(context: *mut c_void, length: usize) -> *mut u8 {
+        let output = unsafe { &mut *context.cast::<Vec<u8>>() };
+        let start = output.len();
+        output.resize(start + length, 0);
+        output[start..].as_mut_ptr()
+    }
+
+    extern "C" fn fail_to_append(_: *mut c_void, _: usize) -> *mut u8 {
+        std::ptr::null_mut()
+    }
+
+    /// Mux the first keyframe into `packet`, returns the appended output, whether appending
+    /// failed and the unappended buffer
+    fn mux_packet(
+        codec_flavor: CodecFlavor,
+        packet: &[u8],
+        append: extern "C" fn(*mut c_void, usize) -> *mut u8,
+    ) -> (Vec<u8>, bool, Option<Box<WebvttBuffer>>) {
+        let muxer = muxer();
+        let mut output = vec![];
+        let mut unappended = None;
+        let outcome = webvtt_muxer_try_mux_packet_into_sink(
+            Some(&muxer),
+            0,
+            0,
+            true,
+            codec_flavor as u8,
+            packet.as_ptr(),
+            packet.len(),
+            Some(append),
+            (&raw mut output).cast(),
+            Some(&mut unappended),
+        );
+        assert!(outcome.data_written != outcome.append_failed);
+        (output, outcome.append_failed, unappended)
+    }
+
+    #[test]
+    fn sink_inserts_sei_before_vcl() {
+        let packet = [AUD, IDR_SLICE].concat();
+        let (output, append_failed, unappended) =
+            mux_packet(CodecFlavor::H264AnnexB, &packet, append_to_vec);
+        assert!(!append_failed);
+        assert!(unappended.is_none());
+        let data = muxed_data(CodecFlavor::H264AnnexB);
+        assert!(!data.is_empty());
+        assert!(output == [AUD, &data, IDR_SLICE].concat());
+    }
+
+    #[test]
+    fn sink_splices_av1_annex_b() {
+        // temporal delimiter and frame OBU in a frame unit, with obu_length, frame_unit_size
+        // and temporal_unit_size
+        let packet = [7, 6, 1, 2 << 3, 3, 6 << 3, 0xaa, 0xbb];
+        let (output, append_failed, _) = mux_packet(CodecFlavor::AV1AnnexB, &packet, append_to_vec);
+        assert!(!append_failed);
+        let data = muxed_data(CodecFlavor::AV1AnnexB);
+        let mut expected = vec![];
+        TemporalUnit::parse(&packet)
+            .unwrap()
+            .splice(&data, &mut expected)
+            .unwrap();
+        assert!(output == expected);
+        // the metadata OBUs go between the temporal delimiter and the frame OBU
+        let temporal_delimiter = output.windows(2).position(|obu| obu == [1, 2 << 3]);
+        assert!(output[temporal_delimiter.unwrap() + 2..].starts_with(&data));
+        assert!(output.ends_with(&[3, 6 << 3, 0xaa, 0xbb]));
+    }
+
+    #[test]
+    fn sink_returns_unappended_data() {
+        let packet = [AUD, IDR_SLICE].concat();
+        let (output, append_failed, unappended) =
+            mux_packet(CodecFlavor::H264AnnexB, &packet, fail_to_append);
+        assert!(append_failed);
+        assert!(output.is_empty());
+        assert!(unappended.unwrap().data == muxed_data(CodecFlavor::H264AnnexB));
+    }
+}
@@ -0,0 +1,63 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use std::{hint::black_box, io::Write};
+use video_bytestream_tools::h26x::RbspWriter;
+
+const SIZES: [usize; 3] = [64, 1024, 64 * 1024];
+
+type Input = (&'static str, fn(usize) -> Vec<u8>);
+
+/// Text-like data without zero bytes, the common case for WebVTT payloads
+fn text(size: usize) -> Vec<u8> {
+    b"00:00:01.000 --> 00:00:01.500\nA line of subtitle text\n\n"
+        .iter()
+        .copied()
+        .cycle()
+        .take(size)
+        .collect()
+}
+
+/// Data with long zero runs where every third byte needs an emulation prevention byte
+fn zeros(size: usize) -> Vec<u8> {
+    vec![0; size]
+}
+
+/// Binary data with scattered zeros, e.g. the big endian fields in the payload headers
+fn mixed(size: usize) -> Vec<u8> {
+    let mut state = 0x9e37_79b9_u32;
+    (0..size)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            if state.is_multiple_of(8) {
+                0
+            } else {
+                (state >> 24) as u8
+            }
+        })
+        .collect()
+}
+
+fn rbsp(c: &mut Criterion) {
+    let mut group = c.benchmark_group("rbsp_write");
+    let inputs: [Input; 3] = [("text", text), ("zeros", zeros), ("mixed", mixed)];
+    for size in SIZES {
+        group.throughput(Throughput::Bytes(u64::try_from(size).unwrap()));
+        for (name, input) in inputs {
+            let data = input(size);
+            let mut buffer = Vec::with_capacity(size * 2);
+            group.bench_with_input(BenchmarkId::new(name, size), &data, |b, data| {
+                b.iter(|| {
+                    buffer.clear();
+                    let mut writer = RbspWriter::new(&mut buffer);
+                    writer.write_all(black_box(data)).unwrap();
+                    writer.finish_rbsp().unwrap();
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, rbsp);
+criterion_main!(benches);
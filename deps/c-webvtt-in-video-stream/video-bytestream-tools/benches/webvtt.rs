@@ -0,0 +1,142 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use std::{hint::black_box, time::Duration};
+use video_bytestream_tools::{
+    av1,
+    h264::{self, H264ByteStreamWrite, H264NalHeader},
+    h265::{self, H265ByteStreamWrite, H265NalHeader},
+    h26x::{NalUnitWrite, RbspWrite},
+    webvtt::{WebvttTrack, WebvttWrite},
+};
+
+const TRACK_COUNTS: [usize; 3] = [1, 8, 32];
+const PAYLOAD: &str =
+    "00:00:01.000 --> 00:00:01.500\nA line of subtitle text of realistic length\n\n";
+
+fn track_names(track_count: usize) -> Vec<(String, String)> {
+    (0..track_count)
+        .map(|i| (format!("Track {i}"), format!("x{i:02}")))
+        .collect()
+}
+
+fn tracks(names: &[(String, String)]) -> Vec<WebvttTrack<'_>> {
+    names
+        .iter()
+        .map(|(name, language)| WebvttTrack {
+            default: false,
+            autoselect: true,
+            forced: false,
+            name,
+            language,
+            assoc_language: None,
+            characteristics: None,
+        })
+        .collect()
+}
+
+/// Writes what the muxer emits for a keyframe carrying a chunk: one header and one
+/// payload per track.
+fn write_packet(writer: &mut impl WebvttWrite, tracks: &[WebvttTrack]) {
+    writer
+        .write_webvtt_header(Duration::from_secs(10), 2, tracks)
+        .unwrap();
+    for track_index in 0..tracks.len() {
+        writer
+            .write_webvtt_payload(
+                u8::try_from(track_index).unwrap(),
+                1234,
+                0,
+                Duration::from_millis(40),
+                PAYLOAD,
+            )
+            .unwrap();
+    }
+}
+
+fn h264_nal_header() -> H264NalHeader {
+    H264NalHeader::from_nal_unit_type_and_nal_ref_idc(h264_reader::nal::UnitType::SEI, 0).unwrap()
+}
+
+fn h265_nal_header() -> H265NalHeader {
+    H265NalHeader::from_nal_unit_type_and_nuh_ids(h265::UnitType::PrefixSeiNut, 0, 0).unwrap()
+}
+
+fn bench_packets(c: &mut Criterion) {
+    let mut group = c.benchmark_group("webvtt_packet");
+    for track_count in TRACK_COUNTS {
+        let names = track_names(track_count);
+        let tracks = tracks(&names);
+        let mut buffer = Vec::with_capacity(64 * 1024);
+
+        group.bench_with_input(
+            BenchmarkId::new("h264_annex_b", track_count),
+            &tracks,
+            |b, tracks| {
+                b.iter(|| {
+                    buffer.clear();
+                    let mut writer = h264::annex_b::AnnexBWriter::new(&mut buffer)
+                        .start_write_nal_unit()
+                        .unwrap()
+                        .write_nal_header(h264_nal_header())
+                        .unwrap();
+                    write_packet(&mut writer, tracks);
+                    writer.finish_rbsp().unwrap();
+                    black_box(buffer.len());
+                })
+            },
+        );
+
+        group.bench_with_input(
+            BenchmarkId::new("h264_avcc4", track_count),
+            &tracks,
+            |b, tracks| {
+                b.iter(|| {
+                    buffer.clear();
+                    let mut writer = h264::avcc::AVCCWriter::new(4, &mut buffer)
+                        .unwrap()
+                        .start_write_nal_unit()
+                        .unwrap()
+                        .write_nal_header(h264_nal_header())
+                        .unwrap();
+                    write_packet(&mut writer, tracks);
+                    writer.finish_rbsp().unwrap();
+                    black_box(buffer.len());
+                })
+            },
+        );
+
+        group.bench_with_input(
+            BenchmarkId::new("h265_annex_b", track_count),
+            &tracks,
+            |b, tracks| {
+                b.iter(|| {
+                    buffer.clear();
+                    let mut writer = h265::annex_b::AnnexBWriter::new(&mut buffer)
+                        .start_write_nal_unit()
+                        .unwrap()
+                        .write_nal_header(h265_nal_header())
+                        .unwrap();
+                    write_packet(&mut writer, tracks);
+                    writer.finish_rbsp().unwrap();
+                    black_box(buffer.len());
+                })
+            },
+        );
+
+        group.bench_with_input(
+            BenchmarkId::new("av1", track_count),
+            &tracks,
+            |b, tracks| {
+                b.iter(|| {
+                    buffer.clear();
+                    let mut writer = av1::OBUWriter::new(&mut buffer);
+                    write_packet(&mut writer, tracks);
+                    black_box(buffer.len());
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_packets);
+criterion_main!(benches);
@@ -1,5 +1,10 @@
-use crate::webvtt::{
-    write_webvtt_header, write_webvtt_payload, CountingSink, WebvttTrack, WebvttWrite,
+use crate::{
+    sei::{self, unsupported_message_error, SeiMessage, SeiWrite},
+    timecode::{Timecode, TimecodeWrite},
+    webvtt::{
+        write_serialized_webvtt_header, write_webvtt_header, write_webvtt_payload,
+        SerializedWebvttHeader, WebvttTrack, WebvttWrite,
+    },
 };
 use bitstream_io::{BigEndian, BitWrite, BitWriter};
 use byteorder::WriteBytesExt;
@@ -10,6 +15,9 @@ use std::{
 
 type Result<T, E = std::io::Error> = std::result::Result<T, E>;
 
+pub mod annex_b;
+pub mod temporal_unit;
+
 pub trait WriteLeb128Ext: BitWrite {
     fn write_leb128(&mut self, mut val: u32) -> std::io::Result<()> {
         loop {
@@ -27,6 +35,24 @@ pub trait WriteLeb128Ext: BitWrite {
 
 impl<W: BitWrite + ?Sized> WriteLeb128Ext for W {}
 
+fn leb128_size(val: u32) -> u32 {
+    (32 - val.leading_zeros()).div_ceil(7).max(1)
+}
+
+/// Reads a leb128 value and advances `data` past it
+pub(crate) fn read_leb128(data: &mut &[u8]) -> Option<usize> {
+    let mut value = 0u64;
+    for i in 0..8 {
+        let (&byte, rest) = data.split_first()?;
+        *data = rest;
+        value |= u64::from(byte & 0x7f) << (i * 7);
+        if byte & 0x80 == 0 {
+            return u32::try_from(value).ok().map(|value| value as usize);
+        }
+    }
+    None
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct OBUHeaderWithSize {
     obu_type: OBUType,
@@ -34,7 +60,7 @@ pub struct OBUHeaderWithSize {
     obu_extension_header: Option<OBUExtensionHeader>,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum OBUType {
     Reserved0,
     SequenceHeader,
@@ -54,7 +80,7 @@ pub enum OBUType {
     Padding,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MetadataType {
     ReservedForAOMUse,
     HdrCll,
@@ -90,7 +116,7 @@ pub enum MetadataType {
     UnregisteredPrivate31,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct OBUExtensionHeader {
     temporal_id: u8,
     spatial_id: u8,
@@ -152,6 +178,39 @@ impl OBUType {
             OBUType::Padding => 15,
         }
     }
+
+    pub fn from_id(id: u8) -> Self {
+        match id & 0b1111 {
+            0 => OBUType::Reserved0,
+            1 => OBUType::SequenceHeader,
+            2 => OBUType::TemporalDelimiter,
+            3 => OBUType::FrameHeader,
+            4 => OBUType::TileGroup,
+            5 => OBUType::Metadata,
+            6 => OBUType::Frame,
+            7 => OBUType::RedundantFrameHeader,
+            8 => OBUType::TileList,
+            9 => OBUType::Reserved9,
+            10 => OBUType::Reserved10,
+            11 => OBUType::Reserved11,
+            12 => OBUType::Reserved12,
+            13 => OBUType::Reserved13,
+            14 => OBUType::Reserved14,
+            _ => OBUType::Padding,
+        }
+    }
+
+    /// OBUs that make up a frame, metadata OBUs of a temporal unit have to precede them
+    pub fn is_frame_data(self) -> bool {
+        matches!(
+            self,
+            OBUType::FrameHeader
+                | OBUType::TileGroup
+                | OBUType::Frame
+                | OBUType::RedundantFrameHeader
+                | OBUType::TileList
+        )
+    }
 }
 
 impl MetadataType {
@@ -212,25 +271,112 @@ impl OBUExtensionHeader {
             spatial_id,
         })
     }
+
+    fn from_byte(byte: u8) -> Self {
+        Self {
+            temporal_id: byte >> 5,
+            spatial_id: (byte >> 3) & 0b11,
+        }
+    }
+
+    pub fn temporal_id(self) -> u8 {
+        self.temporal_id
+    }
+
+    pub fn spatial_id(self) -> u8 {
+        self.spatial_id
+    }
+}
+
+/// The fields of an OBU header that are needed to walk a temporal unit
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ParsedOBUHeader {
+    pub(crate) obu_type: OBUType,
+    pub(crate) obu_extension_header: Option<OBUExtensionHeader>,
+    pub(crate) has_size_field: bool,
 }
 
-pub struct OBUWriter<W: ?Sized + Write>(W);
+impl ParsedOBUHeader {
+    /// Parses the OBU header without `obu_size` and advances `data` past it, `None` if it's
+    /// truncated or the forbidden bit is set
+    pub(crate) fn parse(data: &mut &[u8]) -> Option<Self> {
+        let (&header, rest) = data.split_first()?;
+        if header & 0b1000_0000 != 0 {
+            return None;
+        }
+        *data = rest;
+        let obu_extension_header = if header & 0b100 != 0 {
+            let (&extension_header, rest) = data.split_first()?;
+            *data = rest;
+            Some(OBUExtensionHeader::from_byte(extension_header))
+        } else {
+            None
+        };
+        Some(Self {
+            obu_type: OBUType::from_id(header >> 3),
+            obu_extension_header,
+            has_size_field: header & 0b10 != 0,
+        })
+    }
+}
+
+fn write_obu_header<W: ?Sized + Write>(
+    writer: &mut W,
+    obu_header: OBUHeaderWithSize,
+) -> Result<()> {
+    let mut buffer = [0u8; 10];
+    let header_bytes = obu_header.as_header_bytes(&mut buffer)?;
+    writer.write_all(header_bytes)
+}
+
+/// Writes the OBU header and metadata type of a metadata OBU, `payload_size` excludes the
+/// trailing bits written by `finish_payload`.
+fn write_metadata_obu_header<W: ?Sized + Write>(
+    writer: &mut W,
+    metadata_type: MetadataType,
+    obu_extension_header: Option<OBUExtensionHeader>,
+    payload_size: usize,
+) -> Result<()> {
+    let metadata_type = metadata_type.id();
+    let obu_size = leb128_size(metadata_type) + u32::try_from(payload_size).unwrap() + 1;
+    write_obu_header(
+        writer,
+        OBUHeaderWithSize::new(OBUType::Metadata, Some(obu_size), obu_extension_header),
+    )?;
+    let mut writer = BitWriter::endian(writer, BigEndian);
+    writer.write_leb128(metadata_type)
+}
+
+pub struct OBUWriter<W: ?Sized + Write> {
+    obu_extension_header: Option<OBUExtensionHeader>,
+    inner: W,
+}
 
 impl<W: Write> OBUWriter<W> {
     pub fn new(inner: W) -> Self {
-        Self(inner)
+        Self {
+            obu_extension_header: None,
+            inner,
+        }
     }
-}
 
-impl<W: ?Sized + Write> OBUWriter<W> {
-    fn write_obu_header(&mut self, obu_header: OBUHeaderWithSize) -> Result<()> {
-        let mut buffer = [0u8; 10];
-        let header_bytes = obu_header.as_header_bytes(&mut buffer)?;
-        self.0.write_all(header_bytes)
+    /// Metadata OBUs carry `obu_extension_header` so they only apply to that layer, usually the
+    /// one of the frame OBUs they're placed in front of (see `temporal_unit::MetadataPlacement`)
+    pub fn with_extension_header(inner: W, obu_extension_header: OBUExtensionHeader) -> Self {
+        Self {
+            obu_extension_header: Some(obu_extension_header),
+            inner,
+        }
     }
 
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: ?Sized + Write> OBUWriter<W> {
     fn finish_payload(&mut self) -> Result<()> {
-        self.0.write_u8(0b1000_0000)
+        self.inner.write_u8(0b1000_0000)
     }
 }
 
@@ -241,45 +387,40 @@ impl<W: Write + ?Sized> WebvttWrite for OBUWriter<W> {
         send_frequency_hz: u8,
         subtitle_tracks: &[WebvttTrack],
     ) -> std::io::Result<()> {
-        fn inner<W: ?Sized + Write>(
-            writer: &mut W,
-            max_latency_to_video: Duration,
-            send_frequency_hz: u8,
-            subtitle_tracks: &[WebvttTrack],
-        ) -> std::io::Result<()> {
-            write_webvtt_header(
-                writer,
-                max_latency_to_video,
-                send_frequency_hz,
-                subtitle_tracks,
-                |write, _size| {
-                    let mut writer = BitWriter::endian(write, BigEndian);
-                    writer.write_leb128(MetadataType::UnregisteredPrivate6.id())
-                },
-            )
-        }
-        let mut count = CountingSink::new();
-        inner(
-            &mut count,
-            max_latency_to_video,
-            send_frequency_hz,
-            subtitle_tracks,
-        )?;
-        let header = OBUHeaderWithSize::new(
-            OBUType::Metadata,
-            Some(u32::try_from(count.count()).unwrap() + 1),
-            None,
-        );
-        self.write_obu_header(header)?;
-        inner(
-            &mut self.0,
+        let obu_extension_header = self.obu_extension_header;
+        write_webvtt_header(
+            &mut self.inner,
             max_latency_to_video,
             send_frequency_hz,
             subtitle_tracks,
+            |write, size| {
+                write_metadata_obu_header(
+                    write,
+                    MetadataType::UnregisteredPrivate6,
+                    obu_extension_header,
+                    size,
+                )
+            },
         )?;
         self.finish_payload()
     }
 
+    fn write_serialized_webvtt_header(
+        &mut self,
+        header: &SerializedWebvttHeader,
+    ) -> std::io::Result<()> {
+        let obu_extension_header = self.obu_extension_header;
+        write_serialized_webvtt_header(&mut self.inner, header, |write, size| {
+            write_metadata_obu_header(
+                write,
+                MetadataType::UnregisteredPrivate6,
+                obu_extension_header,
+                size,
+            )
+        })?;
+        self.finish_payload()
+    }
+
     fn write_webvtt_payload(
         &mut self,
         track_index: u8,
@@ -288,50 +429,50 @@ impl<W: Write + ?Sized> WebvttWrite for OBUWriter<W> {
         video_offset: Duration,
         webvtt_payload: &str, // TODO: replace with string type that checks for interior NULs
     ) -> std::io::Result<()> {
-        fn inner<W: ?Sized + Write>(
-            writer: &mut W,
-            track_index: u8,
-            chunk_number: u64,
-            chunk_version: u8,
-            video_offset: Duration,
-            webvtt_payload: &str,
-        ) -> std::io::Result<()> {
-            write_webvtt_payload(
-                writer,
-                track_index,
-                chunk_number,
-                chunk_version,
-                video_offset,
-                webvtt_payload,
-                |write, _size| {
-                    let mut writer = BitWriter::endian(write, BigEndian);
-                    writer.write_leb128(MetadataType::UnregisteredPrivate6.id())
-                },
-            )
-        }
-        let mut count = CountingSink::new();
-        inner(
-            &mut count,
-            track_index,
-            chunk_number,
-            chunk_version,
-            video_offset,
-            webvtt_payload,
-        )?;
-        let header = OBUHeaderWithSize::new(
-            OBUType::Metadata,
-            Some(u32::try_from(count.count()).unwrap() + 1),
-            None,
-        );
-        self.write_obu_header(header)?;
-        inner(
-            &mut self.0,
+        let obu_extension_header = self.obu_extension_header;
+        write_webvtt_payload(
+            &mut self.inner,
             track_index,
             chunk_number,
             chunk_version,
             video_offset,
             webvtt_payload,
+            |write, size| {
+                write_metadata_obu_header(
+                    write,
+                    MetadataType::UnregisteredPrivate6,
+                    obu_extension_header,
+                    size,
+                )
+            },
         )?;
         self.finish_payload()
     }
 }
+
+impl<W: Write + ?Sized> SeiWrite for OBUWriter<W> {
+    fn write_sei_message(&mut self, message: &SeiMessage) -> std::io::Result<()> {
+        let metadata_type = message
+            .metadata_type()
+            .ok_or_else(|| unsupported_message_error(message))?;
+        let obu_extension_header = self.obu_extension_header;
+        sei::write_sei_message(&mut self.inner, message, |write, size| {
+            write_metadata_obu_header(write, metadata_type, obu_extension_header, size)
+        })?;
+        self.finish_payload()
+    }
+}
+
+impl<W: Write + ?Sized> TimecodeWrite for OBUWriter<W> {
+    fn write_timecode(&mut self, timecode: &Timecode) -> std::io::Result<()> {
+        let payload = timecode.av1_metadata_payload()?;
+        // the trailing bits are part of the last payload byte
+        write_metadata_obu_header(
+            &mut self.inner,
+            MetadataType::Timecode,
+            self.obu_extension_header,
+            payload.len() - 1,
+        )?;
+        self.inner.write_all(&payload)
+    }
+}
@@ -0,0 +1,434 @@
+use crate::{
+    av1::temporal_unit::MetadataPlacement,
+    av1::{
+        self, leb128_size, write_obu_header, MetadataType, OBUExtensionHeader, OBUHeaderWithSize,
+        OBUType, ParsedOBUHeader, WriteLeb128Ext,
+    },
+    sei::{self, unsupported_message_error, SeiMessage, SeiWrite},
+    timecode::{Timecode, TimecodeWrite},
+    webvtt::{
+        write_serialized_webvtt_header, write_webvtt_header, write_webvtt_payload,
+        SerializedWebvttHeader, WebvttTrack, WebvttWrite,
+    },
+};
+use bitstream_io::{BigEndian, BitWriter};
+use byteorder::WriteBytesExt;
+use std::{io::Write, time::Duration};
+use thiserror::Error;
+
+type Result<T, E = std::io::Error> = std::result::Result<T, E>;
+
+/// Writes OBUs in the length delimited format of AV1 Annex B: every OBU is prefixed with its
+/// `obu_length` and doesn't carry a size field. The output is meant to be spliced into a frame
+/// unit, see `splice_into_temporal_unit`.
+pub struct AnnexBOBUWriter<W: ?Sized + Write> {
+    obu_extension_header: Option<OBUExtensionHeader>,
+    inner: W,
+}
+
+impl<W: Write> AnnexBOBUWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            obu_extension_header: None,
+            inner,
+        }
+    }
+
+    /// See `OBUWriter::with_extension_header`
+    pub fn with_extension_header(inner: W, obu_extension_header: OBUExtensionHeader) -> Self {
+        Self {
+            obu_extension_header: Some(obu_extension_header),
+            inner,
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+fn write_leb128<W: ?Sized + Write>(writer: &mut W, value: u32) -> Result<()> {
+    let mut writer = BitWriter::endian(writer, BigEndian);
+    writer.write_leb128(value)
+}
+
+/// Writes `obu_length`, the OBU header and metadata type of a metadata OBU, `payload_size`
+/// excludes the trailing bits.
+fn write_metadata_obu_length_and_header<W: ?Sized + Write>(
+    writer: &mut W,
+    metadata_type: MetadataType,
+    obu_extension_header: Option<OBUExtensionHeader>,
+    payload_size: usize,
+) -> Result<()> {
+    let metadata_type = metadata_type.id();
+    let header_size = if obu_extension_header.is_some() { 2 } else { 1 };
+    let obu_length =
+        header_size + leb128_size(metadata_type) + u32::try_from(payload_size).unwrap() + 1;
+    write_leb128(writer, obu_length)?;
+    write_obu_header(
+        writer,
+        OBUHeaderWithSize::new(OBUType::Metadata, None, obu_extension_header),
+    )?;
+    write_leb128(writer, metadata_type)
+}
+
+impl<W: ?Sized + Write> AnnexBOBUWriter<W> {
+    fn finish_payload(&mut self) -> Result<()> {
+        self.inner.write_u8(0b1000_0000)
+    }
+}
+
+impl<W: Write + ?Sized> WebvttWrite for AnnexBOBUWriter<W> {
+    fn write_webvtt_header(
+        &mut self,
+        max_latency_to_video: Duration,
+        send_frequency_hz: u8,
+        subtitle_tracks: &[WebvttTrack],
+    ) -> std::io::Result<()> {
+        let obu_extension_header = self.obu_extension_header;
+        write_webvtt_header(
+            &mut self.inner,
+            max_latency_to_video,
+            send_frequency_hz,
+            subtitle_tracks,
+            |write, size| {
+                write_metadata_obu_length_and_header(
+                    write,
+                    MetadataType::UnregisteredPrivate6,
+                    obu_extension_header,
+                    size,
+                )
+            },
+        )?;
+        self.finish_payload()
+    }
+
+    fn write_serialized_webvtt_header(
+        &mut self,
+        header: &SerializedWebvttHeader,
+    ) -> std::io::Result<()> {
+        let obu_extension_header = self.obu_extension_header;
+        write_serialized_webvtt_header(&mut self.inner, header, |write, size| {
+            write_metadata_obu_length_and_header(
+                write,
+                MetadataType::UnregisteredPrivate6,
+                obu_extension_header,
+                size,
+            )
+        })?;
+        self.finish_payload()
+    }
+
+    fn write_webvtt_payload(
+        &mut self,
+        track_index: u8,
+        chunk_number: u64,
+        chunk_version: u8,
+        video_offset: Duration,
+        webvtt_payload: &str, // TODO: replace with string type that checks for interior NULs
+    ) -> std::io::Result<()> {
+        let obu_extension_header = self.obu_extension_header;
+        write_webvtt_payload(
+            &mut self.inner,
+            track_index,
+            chunk_number,
+            chunk_version,
+            video_offset,
+            webvtt_payload,
+            |write, size| {
+                write_metadata_obu_length_and_header(
+                    write,
+                    MetadataType::UnregisteredPrivate6,
+                    obu_extension_header,
+                    size,
+                )
+            },
+        )?;
+        self.finish_payload()
+    }
+}
+
+impl<W: Write + ?Sized> SeiWrite for AnnexBOBUWriter<W> {
+    fn write_sei_message(&mut self, message: &SeiMessage) -> std::io::Result<()> {
+        let metadata_type = message
+            .metadata_type()
+            .ok_or_else(|| unsupported_message_error(message))?;
+        let obu_extension_header = self.obu_extension_header;
+        sei::write_sei_message(&mut self.inner, message, |write, size| {
+            write_metadata_obu_length_and_header(write, metadata_type, obu_extension_header, size)
+        })?;
+        self.finish_payload()
+    }
+}
+
+impl<W: Write + ?Sized> TimecodeWrite for AnnexBOBUWriter<W> {
+    fn write_timecode(&mut self, timecode: &Timecode) -> std::io::Result<()> {
+        let payload = timecode.av1_metadata_payload()?;
+        // the trailing bits are part of the last payload byte
+        write_metadata_obu_length_and_header(
+            &mut self.inner,
+            MetadataType::Timecode,
+            self.obu_extension_header,
+            payload.len() - 1,
+        )?;
+        self.inner.write_all(&payload)
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum AnnexBError {
+    #[error("leb128 value at offset {0} is invalid or truncated")]
+    InvalidLeb128(usize),
+    #[error("temporal_unit_size of {size} doesn't match the {available} bytes that follow")]
+    TemporalUnitSizeMismatch { size: usize, available: usize },
+    #[error("frame_unit_size of {size} exceeds the {available} bytes left in the temporal unit")]
+    TruncatedFrameUnit { size: usize, available: usize },
+    #[error("Temporal unit doesn't contain a frame unit")]
+    MissingFrameUnit,
+    #[error("Temporal unit exceeds the maximum leb128 size")]
+    TemporalUnitTooLarge,
+    #[error("OBU at offset {offset} of the first frame unit is invalid or truncated")]
+    InvalidOBU { offset: usize },
+}
+
+/// Reads a leb128 value and advances `data` past it, `offset` is only used for errors
+pub(crate) fn read_leb128(data: &mut &[u8], offset: usize) -> Result<usize, AnnexBError> {
+    av1::read_leb128(data).ok_or(AnnexBError::InvalidLeb128(offset))
+}
+
+/// A length delimited temporal unit split around its first frame unit
+#[derive(Debug, Clone, Copy)]
+pub struct TemporalUnit<'a> {
+    /// The content of the first frame unit, without `frame_unit_size`
+    pub first_frame_unit: &'a [u8],
+    /// The frame units after the first one, including their `frame_unit_size`
+    pub remaining_frame_units: &'a [u8],
+    /// Where metadata OBUs go in `first_frame_unit`
+    pub metadata_placement: MetadataPlacement,
+}
+
+/// Finds the `MetadataPlacement` in the content of a frame unit
+fn frame_unit_metadata_placement(frame_unit: &[u8]) -> Result<MetadataPlacement, AnnexBError> {
+    let mut data = frame_unit;
+    while !data.is_empty() {
+        let offset = frame_unit.len() - data.len();
+        let obu_length = av1::read_leb128(&mut data).ok_or(AnnexBError::InvalidOBU { offset })?;
+        let Some((mut obu, rest)) = data.split_at_checked(obu_length) else {
+            return Err(AnnexBError::InvalidOBU { offset });
+        };
+        let header = ParsedOBUHeader::parse(&mut obu).ok_or(AnnexBError::InvalidOBU { offset })?;
+        if header.obu_type.is_frame_data() {
+            return Ok(MetadataPlacement {
+                offset,
+                obu_extension_header: header.obu_extension_header,
+            });
+        }
+        data = rest;
+    }
+    Ok(MetadataPlacement {
+        offset: frame_unit.len(),
+        obu_extension_header: None,
+    })
+}
+
+impl<'a> TemporalUnit<'a> {
+    /// Parses a temporal unit including its `temporal_unit_size`
+    pub fn parse(temporal_unit: &'a [u8]) -> Result<Self, AnnexBError> {
+        let mut data = temporal_unit;
+        let temporal_unit_size = read_leb128(&mut data, 0)?;
+        if temporal_unit_size != data.len() {
+            return Err(AnnexBError::TemporalUnitSizeMismatch {
+                size: temporal_unit_size,
+                available: data.len(),
+            });
+        }
+        if data.is_empty() {
+            return Err(AnnexBError::MissingFrameUnit);
+        }
+        let frame_unit_offset = temporal_unit.len() - data.len();
+        let frame_unit_size = read_leb128(&mut data, frame_unit_offset)?;
+        if frame_unit_size > data.len() {
+            return Err(AnnexBError::TruncatedFrameUnit {
+                size: frame_unit_size,
+                available: data.len(),
+            });
+        }
+        let (first_frame_unit, remaining_frame_units) = data.split_at(frame_unit_size);
+        let metadata_placement = frame_unit_metadata_placement(first_frame_unit)?;
+        Ok(Self {
+            first_frame_unit,
+            remaining_frame_units,
+            metadata_placement,
+        })
+    }
+
+    /// The parts of the temporal unit with `obus`, as written by `AnnexBOBUWriter`, inserted
+    /// into its first frame unit at `metadata_placement`, in order. The rewritten
+    /// `temporal_unit_size` and `frame_unit_size` go into `sizes`, which is the first part.
+    pub fn splice_parts<'b>(
+        &self,
+        obus: &'b [u8],
+        sizes: &'b mut [u8; 10],
+    ) -> Result<[&'b [u8]; 5], AnnexBError>
+    where
+        'a: 'b,
+    {
+        let too_large = |_| AnnexBError::TemporalUnitTooLarge;
+        let frame_unit_size =
+            u32::try_from(self.first_frame_unit.len() + obus.len()).map_err(too_large)?;
+        let temporal_unit_size = u32::try_from(
+            leb128_size(frame_unit_size) as usize
+                + frame_unit_size as usize
+                + self.remaining_frame_units.len(),
+        )
+        .map_err(too_large)?;
+        let unused = {
+            let mut writer = &mut sizes[..];
+            write_leb128(&mut writer, temporal_unit_size).unwrap();
+            write_leb128(&mut writer, frame_unit_size).unwrap();
+            writer.len()
+        };
+        let sizes_length = sizes.len() - unused;
+        let (before, after) = self
+            .first_frame_unit
+            .split_at(self.metadata_placement.offset);
+        Ok([
+            &sizes[..sizes_length],
+            before,
+            obus,
+            after,
+            self.remaining_frame_units,
+        ])
+    }
+
+    /// Writes the temporal unit with `obus` inserted, see [`Self::splice_parts`].
+    /// `frame_unit_size` and `temporal_unit_size` are rewritten to match.
+    pub fn splice(&self, obus: &[u8], output: &mut Vec<u8>) -> Result<(), AnnexBError> {
+        let mut sizes = [0; 10];
+        for part in self.splice_parts(obus, &mut sizes)? {
+            output.extend_from_slice(part);
+        }
+        Ok(())
+    }
+}
+
+/// Writes the length delimited `temporal_unit` (including its `temporal_unit_size`) with `obus`
+/// inserted into its first frame unit, see `TemporalUnit::splice`.
+pub fn splice_into_temporal_unit(
+    temporal_unit: &[u8],
+    obus: &[u8],
+    output: &mut Vec<u8>,
+) -> Result<(), AnnexBError> {
+    TemporalUnit::parse(temporal_unit)?.splice(obus, output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_leb128, splice_into_temporal_unit, AnnexBError, AnnexBOBUWriter};
+    use crate::webvtt::{WebvttWrite, PAYLOAD_GUID};
+    use std::time::Duration;
+
+    fn leb128(value: usize) -> Vec<u8> {
+        let mut output = vec![];
+        super::write_leb128(&mut output, u32::try_from(value).unwrap()).unwrap();
+        output
+    }
+
+    /// Frames `obus` (without `obu_length`) into a frame unit
+    fn frame_unit(obus: &[&[u8]]) -> Vec<u8> {
+        let mut frame_unit = vec![];
+        for obu in obus {
+            frame_unit.extend(leb128(obu.len()));
+            frame_unit.extend_from_slice(obu);
+        }
+        let mut output = leb128(frame_unit.len());
+        output.extend(frame_unit);
+        output
+    }
+
+    fn temporal_unit(frame_units: &[Vec<u8>]) -> Vec<u8> {
+        let content = frame_units.concat();
+        let mut output = leb128(content.len());
+        output.extend(content);
+        output
+    }
+
+    #[test]
+    fn check_webvtt_metadata_obu() {
+        let mut writer = AnnexBOBUWriter::new(vec![]);
+        writer
+            .write_webvtt_payload(0, 1, 0, Duration::from_millis(200), "Some unverified data")
+            .unwrap();
+        let output = writer.into_inner();
+
+        let mut data = &output[..];
+        let obu_length = read_leb128(&mut data, 0).unwrap();
+        assert!(obu_length == data.len());
+        // obu_type 5, no extension, no size field
+        assert!(data[0] == 5 << 3);
+        // metadata_type
+        assert!(data[1] == 6);
+        assert!(&data[2..18] == PAYLOAD_GUID.as_bytes());
+        assert!(data.last() == Some(&0x80));
+    }
+
+    #[test]
+    fn splice_updates_sizes() {
+        let temporal_delimiter: &[u8] = &[2 << 3];
+        let frame: &[u8] = &[6 << 3, 0xaa, 0xbb];
+        let second_frame: &[u8] = &[6 << 3, 0xcc];
+        let input = temporal_unit(&[
+            frame_unit(&[temporal_delimiter, frame]),
+            frame_unit(&[second_frame]),
+        ]);
+
+        // large enough to need two byte leb128 sizes afterwards
+        let metadata: Vec<u8> = (0..200).map(|i| i as u8).collect();
+        let mut obus = leb128(metadata.len());
+        obus.extend_from_slice(&metadata);
+
+        let mut output = vec![];
+        splice_into_temporal_unit(&input, &obus, &mut output).unwrap();
+
+        // the metadata goes between the temporal delimiter and the frame
+        let first_frame_unit = [
+            &leb128(temporal_delimiter.len())[..],
+            temporal_delimiter,
+            &obus,
+            &leb128(frame.len()),
+            frame,
+        ]
+        .concat();
+        let mut expected_first_frame_unit = leb128(first_frame_unit.len());
+        expected_first_frame_unit.extend(first_frame_unit);
+        let expected = temporal_unit(&[expected_first_frame_unit, frame_unit(&[second_frame])]);
+        assert!(output == expected);
+    }
+
+    #[test]
+    fn splice_rejects_malformed_temporal_units() {
+        let mut output = vec![];
+        let mut input = temporal_unit(&[frame_unit(&[&[6 << 3, 0xaa]])]);
+        input.push(0);
+        assert!(matches!(
+            splice_into_temporal_unit(&input, &[], &mut output),
+            Err(AnnexBError::TemporalUnitSizeMismatch { .. })
+        ));
+        assert!(matches!(
+            splice_into_temporal_unit(&[0], &[], &mut output),
+            Err(AnnexBError::MissingFrameUnit)
+        ));
+        assert!(matches!(
+            splice_into_temporal_unit(&[2, 5, 0], &[], &mut output),
+            Err(AnnexBError::TruncatedFrameUnit { .. })
+        ));
+        assert!(matches!(
+            splice_into_temporal_unit(&[0x80], &[], &mut output),
+            Err(AnnexBError::InvalidLeb128(0))
+        ));
+        assert!(matches!(
+            splice_into_temporal_unit(&[3, 2, 5, 0x30], &[], &mut output),
+            Err(AnnexBError::InvalidOBU { offset: 0 })
+        ));
+    }
+}
@@ -0,0 +1,147 @@
+use crate::av1::{read_leb128, OBUExtensionHeader, ParsedOBUHeader};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum TemporalUnitError {
+    #[error("OBU header at offset {0} is invalid or truncated")]
+    InvalidOBUHeader(usize),
+    #[error("obu_size of the OBU at offset {0} is invalid or truncated")]
+    InvalidOBUSize(usize),
+    #[error("OBU at offset {offset} is truncated")]
+    TruncatedOBU { offset: usize },
+}
+
+/// Where metadata OBUs go in a temporal unit: after the temporal delimiter, sequence headers and
+/// existing metadata, in front of the first OBU that is part of a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MetadataPlacement {
+    /// Offset of the first frame header, tile group or frame OBU, the length of the temporal
+    /// unit if there is none
+    pub offset: usize,
+    /// The extension header of that OBU, metadata OBUs carrying it apply to the same layer
+    pub obu_extension_header: Option<OBUExtensionHeader>,
+}
+
+/// Finds the `MetadataPlacement` in a temporal unit in the low overhead bitstream format. The
+/// last OBU may omit `obu_size`, it then extends to the end of the temporal unit.
+pub fn metadata_placement(temporal_unit: &[u8]) -> Result<MetadataPlacement, TemporalUnitError> {
+    let mut data = temporal_unit;
+    while !data.is_empty() {
+        let offset = temporal_unit.len() - data.len();
+        let header =
+            ParsedOBUHeader::parse(&mut data).ok_or(TemporalUnitError::InvalidOBUHeader(offset))?;
+        if header.obu_type.is_frame_data() {
+            return Ok(MetadataPlacement {
+                offset,
+                obu_extension_header: header.obu_extension_header,
+            });
+        }
+        let obu_size = if header.has_size_field {
+            read_leb128(&mut data).ok_or(TemporalUnitError::InvalidOBUSize(offset))?
+        } else {
+            data.len()
+        };
+        let Some(rest) = data.get(obu_size..) else {
+            return Err(TemporalUnitError::TruncatedOBU { offset });
+        };
+        data = rest;
+    }
+    Ok(MetadataPlacement {
+        offset: temporal_unit.len(),
+        obu_extension_header: None,
+    })
+}
+
+/// Writes `temporal_unit` with `obus`, as written by `OBUWriter`, inserted at its
+/// `MetadataPlacement`.
+pub fn insert_metadata(
+    temporal_unit: &[u8],
+    obus: &[u8],
+    output: &mut Vec<u8>,
+) -> Result<(), TemporalUnitError> {
+    let MetadataPlacement { offset, .. } = metadata_placement(temporal_unit)?;
+    output.reserve(temporal_unit.len() + obus.len());
+    output.extend_from_slice(&temporal_unit[..offset]);
+    output.extend_from_slice(obus);
+    output.extend_from_slice(&temporal_unit[offset..]);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{insert_metadata, metadata_placement, MetadataPlacement, TemporalUnitError};
+    use crate::{
+        av1::{OBUExtensionHeader, OBUWriter},
+        webvtt::WebvttWrite,
+    };
+    use std::time::Duration;
+
+    const TEMPORAL_DELIMITER: &[u8] = &[0x12, 0x00];
+    const SEQUENCE_HEADER: &[u8] = &[0x0a, 0x03, 0x00, 0x00, 0x00];
+    const FRAME: &[u8] = &[0x32, 0x02, 0xaa, 0xbb];
+    // temporal_id 1, spatial_id 2
+    const FRAME_WITH_EXTENSION: &[u8] = &[0x36, 0x30, 0x01, 0xaa];
+
+    #[test]
+    fn metadata_goes_before_frame() {
+        let temporal_unit = [TEMPORAL_DELIMITER, SEQUENCE_HEADER, FRAME].concat();
+        let placement = metadata_placement(&temporal_unit).unwrap();
+        assert!(
+            placement
+                == MetadataPlacement {
+                    offset: TEMPORAL_DELIMITER.len() + SEQUENCE_HEADER.len(),
+                    obu_extension_header: None,
+                }
+        );
+
+        let mut writer = OBUWriter::new(vec![]);
+        writer
+            .write_webvtt_payload(0, 1, 0, Duration::from_millis(200), "Some unverified data")
+            .unwrap();
+        let obus = writer.into_inner();
+        let mut output = vec![];
+        insert_metadata(&temporal_unit, &obus, &mut output).unwrap();
+        assert!(output == [TEMPORAL_DELIMITER, SEQUENCE_HEADER, &obus, FRAME].concat());
+    }
+
+    #[test]
+    fn metadata_placement_reports_extension_header() {
+        let temporal_unit = [TEMPORAL_DELIMITER, FRAME_WITH_EXTENSION].concat();
+        let placement = metadata_placement(&temporal_unit).unwrap();
+        let obu_extension_header = placement.obu_extension_header.unwrap();
+        assert!(placement.offset == TEMPORAL_DELIMITER.len());
+        assert!(obu_extension_header == OBUExtensionHeader::new(1, 2).unwrap());
+
+        let mut writer = OBUWriter::with_extension_header(vec![], obu_extension_header);
+        writer
+            .write_webvtt_payload(0, 1, 0, Duration::from_millis(200), "Some unverified data")
+            .unwrap();
+        let obus = writer.into_inner();
+        // obu_type 5 with extension and size fields, followed by the extension header
+        assert!(obus[0] == 0x2e);
+        assert!(obus[1] == 0x30);
+    }
+
+    #[test]
+    fn last_obu_without_size_and_no_frame() {
+        let temporal_unit = [TEMPORAL_DELIMITER, &[0x08, 0x00, 0x00]].concat();
+        let placement = metadata_placement(&temporal_unit).unwrap();
+        assert!(placement.offset == temporal_unit.len());
+    }
+
+    #[test]
+    fn malformed_temporal_units() {
+        assert!(matches!(
+            metadata_placement(&[0x92, 0x00]),
+            Err(TemporalUnitError::InvalidOBUHeader(0))
+        ));
+        assert!(matches!(
+            metadata_placement(&[0x12, 0x00, 0x0a, 0x80]),
+            Err(TemporalUnitError::InvalidOBUSize(2))
+        ));
+        assert!(matches!(
+            metadata_placement(&[0x12, 0x00, 0x0a, 0x05, 0x00]),
+            Err(TemporalUnitError::TruncatedOBU { offset: 2 })
+        ));
+    }
+}
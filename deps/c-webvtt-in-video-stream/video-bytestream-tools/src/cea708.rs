@@ -0,0 +1,790 @@
+//! CEA-608 and CEA-708 captions, carried as ATSC A/53 `cc_data` in ITU-T T.35 user data.
+//!
+//! [`CaptionEncoder`] turns caption text into 608 byte pairs for field 1 / channel 1 and 708
+//! service blocks for service 1, and hands them out frame by frame as [`CcData`].
+
+use crate::t35::{ItutT35Header, ItutT35Write};
+use byteorder::WriteBytesExt;
+use std::{collections::VecDeque, io::Write, time::Duration};
+
+pub const ATSC_USER_IDENTIFIER: [u8; 4] = *b"GA94";
+pub const ATSC_USER_DATA_TYPE_CC_DATA: u8 = 0x03;
+
+/// Characters per row of the 608 caption grid, 708 windows use the same width
+const COLUMNS: usize = 32;
+/// Rows available for pop-on captions
+const MAX_POP_ON_ROWS: usize = 4;
+/// Bytes that fit into a single 708 service block
+const MAX_SERVICE_BLOCK_SIZE: usize = 31;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptionMode {
+    /// Captions are built off screen and replace the displayed caption at once.
+    PopOn,
+    /// Every caption is added as new lines at the bottom, scrolling up older lines.
+    RollUp { rows: RollUpRows },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RollUpRows {
+    Two,
+    Three,
+    Four,
+}
+
+impl RollUpRows {
+    fn count(self) -> u8 {
+        match self {
+            RollUpRows::Two => 2,
+            RollUpRows::Three => 3,
+            RollUpRows::Four => 4,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CcType {
+    Ntsc608Field1,
+    Ntsc608Field2,
+    DtvccPacketData,
+    DtvccPacketStart,
+}
+
+impl CcType {
+    fn id(self) -> u8 {
+        match self {
+            CcType::Ntsc608Field1 => 0,
+            CcType::Ntsc608Field2 => 1,
+            CcType::DtvccPacketData => 2,
+            CcType::DtvccPacketStart => 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CcDataTriplet {
+    pub cc_valid: bool,
+    pub cc_type: CcType,
+    pub cc_data: [u8; 2],
+}
+
+impl CcDataTriplet {
+    fn as_bytes(self) -> [u8; 3] {
+        [
+            0b1111_1000 | u8::from(self.cc_valid) << 2 | self.cc_type.id(),
+            self.cc_data[0],
+            self.cc_data[1],
+        ]
+    }
+}
+
+/// The `cc_data()` of a single frame
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CcData {
+    pub triplets: Vec<CcDataTriplet>,
+}
+
+/// `cc_count` for frames of `frame_time`, 708 captions use a fixed rate of 600 triplets per
+/// second
+pub fn cc_count_for_frame_time(frame_time: Duration) -> u8 {
+    let cc_count = (frame_time.as_secs_f64() * 600.).round();
+    // cc_count has 5 bits, and every frame carries at least the two 608 fields
+    cc_count.clamp(2., 31.) as u8
+}
+
+/// Size of the A/53 user data with the maximum `cc_count` of 31
+const MAX_CC_DATA_PAYLOAD_SIZE: usize = ATSC_USER_IDENTIFIER.len() + 1 + 2 + 3 * 31 + 1;
+
+/// Writes `cc_data` as the ATSC A/53 user data that follows the ITU-T T.35 header
+fn write_cc_data_payload<W: Write + ?Sized>(
+    writer: &mut W,
+    cc_data: &CcData,
+) -> std::io::Result<()> {
+    writer.write_all(&ATSC_USER_IDENTIFIER)?;
+    writer.write_u8(ATSC_USER_DATA_TYPE_CC_DATA)?;
+    // reserved, process_cc_data_flag, additional_data_flag, cc_count
+    writer.write_u8(0b1100_0000 | u8::try_from(cc_data.triplets.len()).unwrap())?;
+    // em_data
+    writer.write_u8(0xff)?;
+    for triplet in &cc_data.triplets {
+        writer.write_all(&triplet.as_bytes())?;
+    }
+    // marker_bits
+    writer.write_u8(0xff)?;
+    Ok(())
+}
+
+pub trait CcDataWrite {
+    fn write_cc_data(&mut self, cc_data: &CcData) -> std::io::Result<()>;
+}
+
+impl<T: ItutT35Write + ?Sized> CcDataWrite for T {
+    fn write_cc_data(&mut self, cc_data: &CcData) -> std::io::Result<()> {
+        let mut buffer = [0; MAX_CC_DATA_PAYLOAD_SIZE];
+        let mut cursor = &mut buffer[..];
+        write_cc_data_payload(&mut cursor, cc_data)?;
+        let size = MAX_CC_DATA_PAYLOAD_SIZE - cursor.len();
+        self.write_itu_t_t35(ItutT35Header::ATSC, &buffer[..size])
+    }
+}
+
+fn with_odd_parity(byte: u8) -> u8 {
+    let byte = byte & 0x7f;
+    if byte.count_ones().is_multiple_of(2) {
+        byte | 0x80
+    } else {
+        byte
+    }
+}
+
+/// How a character is sent in 608
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Cea608Char {
+    Basic(u8),
+    /// Sent as a control code pair
+    Special(u8),
+    /// Sent as a control code pair that replaces the preceding basic fallback character
+    Extended {
+        first: u8,
+        second: u8,
+        fallback: u8,
+    },
+}
+
+const BASIC_REPLACEMENTS: [(char, u8); 10] = [
+    ('á', 0x2a),
+    ('é', 0x5c),
+    ('í', 0x5e),
+    ('ó', 0x5f),
+    ('ú', 0x60),
+    ('ç', 0x7b),
+    ('÷', 0x7c),
+    ('Ñ', 0x7d),
+    ('ñ', 0x7e),
+    ('█', 0x7f),
+];
+
+const SPECIAL_CHARS: [char; 16] = [
+    '®', '°', '½', '¿', '™', '¢', '£', '♪', 'à', '\u{a0}', 'è', 'â', 'ê', 'î', 'ô', 'û',
+];
+
+const EXTENDED_CHARS_1: [(char, u8); 32] = [
+    ('Á', b'A'),
+    ('É', b'E'),
+    ('Ó', b'O'),
+    ('Ú', b'U'),
+    ('Ü', b'U'),
+    ('ü', b'u'),
+    ('‘', b'\''),
+    ('¡', b'!'),
+    ('*', b' '),
+    ('’', b'\''),
+    ('—', b'-'),
+    ('©', b'c'),
+    ('℠', b' '),
+    ('•', b'.'),
+    ('“', b'"'),
+    ('”', b'"'),
+    ('À', b'A'),
+    ('Â', b'A'),
+    ('Ç', b'C'),
+    ('È', b'E'),
+    ('Ê', b'E'),
+    ('Ë', b'E'),
+    ('ë', b'e'),
+    ('Î', b'I'),
+    ('Ï', b'I'),
+    ('ï', b'i'),
+    ('Ô', b'O'),
+    ('Ù', b'U'),
+    ('ù', b'u'),
+    ('Û', b'U'),
+    ('«', b'"'),
+    ('»', b'"'),
+];
+
+const EXTENDED_CHARS_2: [(char, u8); 32] = [
+    ('Ã', b'A'),
+    ('ã', b'a'),
+    ('Í', b'I'),
+    ('Ì', b'I'),
+    ('ì', b'i'),
+    ('Ò', b'O'),
+    ('ò', b'o'),
+    ('Õ', b'O'),
+    ('õ', b'o'),
+    ('{', b'('),
+    ('}', b')'),
+    ('\\', b'/'),
+    ('^', b' '),
+    ('_', b'-'),
+    ('|', b'!'),
+    ('~', b'-'),
+    ('Ä', b'A'),
+    ('ä', b'a'),
+    ('Ö', b'O'),
+    ('ö', b'o'),
+    ('ß', b's'),
+    ('¥', b'Y'),
+    ('¤', b' '),
+    ('│', b'!'),
+    ('Å', b'A'),
+    ('å', b'a'),
+    ('Ø', b'O'),
+    ('ø', b'o'),
+    ('┌', b'+'),
+    ('┐', b'+'),
+    ('└', b'+'),
+    ('┘', b'+'),
+];
+
+/// Maps `c` to the 608 character set, characters that have no representation become `?`
+fn cea608_char(c: char) -> Cea608Char {
+    if let Some(&(_, byte)) = BASIC_REPLACEMENTS.iter().find(|(basic, _)| *basic == c) {
+        return Cea608Char::Basic(byte);
+    }
+    if let Some(index) = SPECIAL_CHARS.iter().position(|&special| special == c) {
+        return Cea608Char::Special(0x30 + u8::try_from(index).unwrap());
+    }
+    let extended = |table: &[(char, u8); 32], first| {
+        let index = table.iter().position(|(extended, _)| *extended == c)?;
+        Some(Cea608Char::Extended {
+            first,
+            second: 0x20 + u8::try_from(index).unwrap(),
+            fallback: table[index].1,
+        })
+    };
+    if let Some(extended) = extended(&EXTENDED_CHARS_1, 0x12).or(extended(&EXTENDED_CHARS_2, 0x13))
+    {
+        return extended;
+    }
+    match u8::try_from(c) {
+        // 0x60 is `ú` in the 608 character set, and there's no grave accent
+        Ok(b'`') => Cea608Char::Basic(b'\''),
+        Ok(byte @ 0x20..=0x7e) => Cea608Char::Basic(byte),
+        _ => Cea608Char::Basic(b'?'),
+    }
+}
+
+// control codes for data channel 1
+const RESUME_CAPTION_LOADING: [u8; 2] = [0x14, 0x20];
+const ROLL_UP_2: [u8; 2] = [0x14, 0x25];
+const ROLL_UP_3: [u8; 2] = [0x14, 0x26];
+const ROLL_UP_4: [u8; 2] = [0x14, 0x27];
+const ERASE_DISPLAYED_MEMORY: [u8; 2] = [0x14, 0x2c];
+const CARRIAGE_RETURN: [u8; 2] = [0x14, 0x2d];
+const ERASE_NON_DISPLAYED_MEMORY: [u8; 2] = [0x14, 0x2e];
+const END_OF_CAPTION: [u8; 2] = [0x14, 0x2f];
+const TAB_OFFSET: u8 = 0x17;
+
+/// Preamble address code for `row` (1-15) with the cursor at `indent` (a multiple of 4)
+fn preamble_address_code(row: u8, indent: u8) -> [u8; 2] {
+    const ROWS: [(u8, u8); 15] = [
+        (0x11, 0x40),
+        (0x11, 0x60),
+        (0x12, 0x40),
+        (0x12, 0x60),
+        (0x15, 0x40),
+        (0x15, 0x60),
+        (0x16, 0x40),
+        (0x16, 0x60),
+        (0x17, 0x40),
+        (0x17, 0x60),
+        (0x10, 0x40),
+        (0x13, 0x40),
+        (0x13, 0x60),
+        (0x14, 0x40),
+        (0x14, 0x60),
+    ];
+    let (first, second) = ROWS[usize::from(row - 1)];
+    [first, second | 0x10 | (indent / 4) << 1]
+}
+
+/// Byte pairs for field 1, with parity applied when they are taken from the queue
+#[derive(Debug, Default)]
+struct Cea608Queue {
+    pairs: VecDeque<[u8; 2]>,
+    pending_char: Option<u8>,
+}
+
+impl Cea608Queue {
+    fn flush_char(&mut self) {
+        if let Some(byte) = self.pending_char.take() {
+            self.pairs.push_back([byte, 0]);
+        }
+    }
+
+    /// Control codes are sent twice, decoders ignore the repetition
+    fn push_control_code(&mut self, code: [u8; 2]) {
+        self.flush_char();
+        self.pairs.push_back(code);
+        self.pairs.push_back(code);
+    }
+
+    fn push_basic(&mut self, byte: u8) {
+        match self.pending_char.take() {
+            Some(pending) => self.pairs.push_back([pending, byte]),
+            None => self.pending_char = Some(byte),
+        }
+    }
+
+    fn push_char(&mut self, c: char) {
+        match cea608_char(c) {
+            Cea608Char::Basic(byte) => self.push_basic(byte),
+            Cea608Char::Special(second) => {
+                self.flush_char();
+                self.pairs.push_back([0x11, second]);
+            }
+            Cea608Char::Extended {
+                first,
+                second,
+                fallback,
+            } => {
+                self.push_basic(fallback);
+                self.flush_char();
+                self.pairs.push_back([first, second]);
+            }
+        }
+    }
+
+    /// Positions the cursor at `row` so that `line` is centered
+    fn push_line(&mut self, row: u8, line: &[char]) {
+        let column = u8::try_from((COLUMNS - line.len()) / 2).unwrap();
+        self.push_control_code(preamble_address_code(row, column / 4 * 4));
+        if column % 4 != 0 {
+            self.push_control_code([TAB_OFFSET, 0x20 + column % 4]);
+        }
+        for &c in line {
+            self.push_char(c);
+        }
+        self.flush_char();
+    }
+
+    fn pop(&mut self) -> Option<[u8; 2]> {
+        self.flush_char();
+        let [first, second] = self.pairs.pop_front()?;
+        Some([with_odd_parity(first), with_odd_parity(second)])
+    }
+}
+
+// 708 commands
+const ETX: u8 = 0x03;
+const CR: u8 = 0x0d;
+const EXT1: u8 = 0x10;
+const CLEAR_WINDOWS: u8 = 0x88;
+const DISPLAY_WINDOWS: u8 = 0x89;
+const DELETE_WINDOWS: u8 = 0x8c;
+const SET_PEN_LOCATION: u8 = 0x92;
+const DEFINE_WINDOW_0: u8 = 0x98;
+
+const G2_CHARS: [(char, u8); 15] = [
+    ('…', 0x25),
+    ('Š', 0x2a),
+    ('Œ', 0x2c),
+    ('█', 0x30),
+    ('‘', 0x31),
+    ('’', 0x32),
+    ('“', 0x33),
+    ('”', 0x34),
+    ('•', 0x35),
+    ('™', 0x39),
+    ('š', 0x3a),
+    ('œ', 0x3c),
+    ('℠', 0x3d),
+    ('Ÿ', 0x3f),
+    ('─', 0x7d),
+];
+
+/// Appends `c` in the 708 code space, characters outside of G0, G1 and the G2 subset above
+/// become `?`
+fn push_cea708_char(command: &mut Vec<u8>, c: char) {
+    match u32::from(c) {
+        0x20..=0x7e | 0xa0..=0xff => command.push(u8::try_from(c).unwrap()),
+        _ if c == '♪' => command.push(0x7f),
+        _ => match G2_CHARS.iter().find(|(g2, _)| *g2 == c) {
+            Some(&(_, byte)) => command.extend_from_slice(&[EXT1, byte]),
+            None => command.push(b'?'),
+        },
+    }
+}
+
+/// DefineWindow for a window anchored at the bottom center of the screen, 32 columns wide
+fn define_window(window_id: u8, visible: bool, rows: u8) -> [u8; 7] {
+    [
+        DEFINE_WINDOW_0 + window_id,
+        // visible, row lock, column lock, priority 0
+        u8::from(visible) << 5 | 0b0001_1000,
+        // relative positioning, anchor vertical 99%
+        0x80 | 99,
+        // anchor horizontal 50%
+        50,
+        // anchor point bottom center, row count
+        7 << 4 | (rows - 1),
+        u8::try_from(COLUMNS - 1).unwrap(),
+        // window style 1, pen style 1
+        1 << 3 | 1,
+    ]
+}
+
+/// Service blocks for service 1, a block never splits a command
+#[derive(Debug, Default)]
+struct Cea708Queue {
+    service_blocks: VecDeque<Vec<u8>>,
+}
+
+impl Cea708Queue {
+    fn push_command(&mut self, command: &[u8]) {
+        match self.service_blocks.back_mut() {
+            Some(block) if block.len() + command.len() <= MAX_SERVICE_BLOCK_SIZE => {
+                block.extend_from_slice(command)
+            }
+            _ => self.service_blocks.push_back(command.to_vec()),
+        }
+    }
+
+    fn push_text(&mut self, text: &[char]) {
+        let mut command = vec![];
+        for &c in text {
+            command.clear();
+            push_cea708_char(&mut command, c);
+            self.push_command(&command);
+        }
+    }
+
+    /// Ends the current service block so the following commands go into a new DTVCC packet
+    fn finish_block(&mut self) {
+        self.push_command(&[ETX]);
+        self.service_blocks.push_back(vec![]);
+    }
+}
+
+/// Splits `text` into rows of at most `COLUMNS` characters, at line breaks and between words
+fn wrap_lines(text: &str) -> Vec<Vec<char>> {
+    let mut lines = vec![];
+    for paragraph in text.lines() {
+        let mut line: Vec<char> = vec![];
+        for word in paragraph.split_whitespace() {
+            let word: Vec<char> = word.chars().collect();
+            for word in word.chunks(COLUMNS) {
+                if !line.is_empty() && line.len() + 1 + word.len() > COLUMNS {
+                    lines.push(std::mem::take(&mut line));
+                }
+                if !line.is_empty() {
+                    line.push(' ');
+                }
+                line.extend_from_slice(word);
+            }
+        }
+        if !line.is_empty() {
+            lines.push(line);
+        }
+    }
+    lines
+}
+
+/// Encodes captions as 608 data for field 1, channel 1 and 708 data for service 1.
+///
+/// The commands for a caption are queued by [`Self::show`] and [`Self::clear`], and sent at
+/// the 608 rate of one byte pair per frame, so a pop-on caption appears a bit after `show`
+/// depending on its length.
+#[derive(Debug)]
+pub struct CaptionEncoder {
+    mode: CaptionMode,
+    cea608: Cea608Queue,
+    cea708: Cea708Queue,
+    dtvcc_pairs: VecDeque<CcDataTriplet>,
+    dtvcc_sequence_number: u8,
+    /// The window pop-on captions are built in, alternates between 0 and 1
+    pop_on_window: u8,
+}
+
+impl CaptionEncoder {
+    pub fn new(mode: CaptionMode) -> Self {
+        Self {
+            mode,
+            cea608: Cea608Queue::default(),
+            cea708: Cea708Queue::default(),
+            dtvcc_pairs: VecDeque::new(),
+            dtvcc_sequence_number: 0,
+            pop_on_window: 0,
+        }
+    }
+
+    pub fn mode(&self) -> CaptionMode {
+        self.mode
+    }
+
+    /// Queues the commands that replace (pop-on) or scroll up (roll-up) the displayed captions
+    /// with `text`. Lines are wrapped at 32 characters, pop-on captions keep the last 4 rows.
+    pub fn show(&mut self, text: &str) {
+        let mut lines = wrap_lines(text);
+        if lines.is_empty() {
+            self.clear();
+            return;
+        }
+        match self.mode {
+            CaptionMode::PopOn => {
+                lines.drain(..lines.len().saturating_sub(MAX_POP_ON_ROWS));
+                let rows = u8::try_from(lines.len()).unwrap();
+
+                self.cea608.push_control_code(RESUME_CAPTION_LOADING);
+                self.cea608.push_control_code(ERASE_NON_DISPLAYED_MEMORY);
+                for (row, line) in (16 - rows..).zip(&lines) {
+                    self.cea608.push_line(row, line);
+                }
+                self.cea608.push_control_code(END_OF_CAPTION);
+
+                let window = self.pop_on_window;
+                self.pop_on_window ^= 1;
+                self.cea708
+                    .push_command(&define_window(window, false, rows));
+                self.cea708.push_command(&[CLEAR_WINDOWS, 1 << window]);
+                self.cea708.push_command(&[SET_PEN_LOCATION, 0, 0]);
+                for (index, line) in lines.iter().enumerate() {
+                    if index != 0 {
+                        self.cea708.push_command(&[CR]);
+                    }
+                    self.cea708.push_text(line);
+                }
+                self.cea708.push_command(&[DISPLAY_WINDOWS, 1 << window]);
+                self.cea708
+                    .push_command(&[DELETE_WINDOWS, 1 << (window ^ 1)]);
+            }
+            CaptionMode::RollUp { rows } => {
+                self.cea608.push_control_code(match rows {
+                    RollUpRows::Two => ROLL_UP_2,
+                    RollUpRows::Three => ROLL_UP_3,
+                    RollUpRows::Four => ROLL_UP_4,
+                });
+                // redefining the window keeps its content
+                self.cea708
+                    .push_command(&define_window(0, true, rows.count()));
+                for line in &lines {
+                    self.cea608.push_control_code(CARRIAGE_RETURN);
+                    self.cea608.push_control_code(preamble_address_code(15, 0));
+                    for &c in line {
+                        self.cea608.push_char(c);
+                    }
+                    self.cea608.flush_char();
+
+                    self.cea708.push_command(&[CR]);
+                    self.cea708.push_text(line);
+                }
+            }
+        }
+        self.cea708.finish_block();
+    }
+
+    /// Queues the commands that remove the displayed captions
+    pub fn clear(&mut self) {
+        self.cea608.push_control_code(ERASE_DISPLAYED_MEMORY);
+        self.cea708.push_command(&[DELETE_WINDOWS, 0b11]);
+        self.cea708.finish_block();
+    }
+
+    /// Whether everything queued has been sent
+    pub fn is_idle(&self) -> bool {
+        self.cea608.pairs.is_empty()
+            && self.cea608.pending_char.is_none()
+            && self.dtvcc_pairs.is_empty()
+            && self.cea708.service_blocks.iter().all(Vec::is_empty)
+    }
+
+    /// Frames the queued service blocks into DTVCC packets
+    fn frame_dtvcc_packets(&mut self) {
+        for block in self.cea708.service_blocks.drain(..) {
+            if block.is_empty() {
+                continue;
+            }
+            // service number 1
+            let mut packet = vec![0, 1 << 5 | u8::try_from(block.len()).unwrap()];
+            packet.extend_from_slice(&block);
+            if !packet.len().is_multiple_of(2) {
+                packet.push(0);
+            }
+            packet[0] = self.dtvcc_sequence_number << 6 | u8::try_from(packet.len() / 2).unwrap();
+            self.dtvcc_sequence_number = (self.dtvcc_sequence_number + 1) % 4;
+            for (index, pair) in packet.chunks_exact(2).enumerate() {
+                self.dtvcc_pairs.push_back(CcDataTriplet {
+                    cc_valid: true,
+                    cc_type: if index == 0 {
+                        CcType::DtvccPacketStart
+                    } else {
+                        CcType::DtvccPacketData
+                    },
+                    cc_data: [pair[0], pair[1]],
+                });
+            }
+        }
+    }
+
+    /// Takes the `cc_data` for the next frame, `cc_count` is usually from
+    /// [`cc_count_for_frame_time`]. Unused triplets are padding.
+    pub fn next_cc_data(&mut self, cc_count: u8) -> CcData {
+        self.frame_dtvcc_packets();
+        let cc_count = cc_count.clamp(2, 31);
+        let mut triplets = Vec::with_capacity(cc_count.into());
+        triplets.push(CcDataTriplet {
+            cc_valid: true,
+            cc_type: CcType::Ntsc608Field1,
+            cc_data: self.cea608.pop().unwrap_or([0x80, 0x80]),
+        });
+        triplets.push(CcDataTriplet {
+            cc_valid: true,
+            cc_type: CcType::Ntsc608Field2,
+            cc_data: [0x80, 0x80],
+        });
+        while triplets.len() < usize::from(cc_count) {
+            triplets.push(self.dtvcc_pairs.pop_front().unwrap_or(CcDataTriplet {
+                cc_valid: false,
+                cc_type: CcType::DtvccPacketData,
+                cc_data: [0, 0],
+            }));
+        }
+        CcData { triplets }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        cc_count_for_frame_time, with_odd_parity, wrap_lines, CaptionEncoder, CaptionMode, CcData,
+        CcDataWrite, CcType, RollUpRows, END_OF_CAPTION, ERASE_DISPLAYED_MEMORY,
+    };
+    use crate::av1::OBUWriter;
+    use std::time::Duration;
+
+    fn drain(encoder: &mut CaptionEncoder) -> (Vec<[u8; 2]>, Vec<u8>) {
+        let mut cea608 = vec![];
+        let mut dtvcc = vec![];
+        while !encoder.is_idle() {
+            for triplet in encoder.next_cc_data(20).triplets {
+                match triplet.cc_type {
+                    CcType::Ntsc608Field1 => cea608.push(triplet.cc_data),
+                    CcType::DtvccPacketStart | CcType::DtvccPacketData if triplet.cc_valid => {
+                        dtvcc.extend_from_slice(&triplet.cc_data)
+                    }
+                    _ => {}
+                }
+            }
+        }
+        (cea608, dtvcc)
+    }
+
+    fn strip_parity(pairs: &[[u8; 2]]) -> Vec<[u8; 2]> {
+        pairs
+            .iter()
+            .map(|pair| [pair[0] & 0x7f, pair[1] & 0x7f])
+            .filter(|&pair| pair != [0, 0])
+            .collect()
+    }
+
+    #[test]
+    fn odd_parity() {
+        for byte in 0..0x80 {
+            assert!(with_odd_parity(byte).count_ones() % 2 == 1);
+            assert!(with_odd_parity(byte) & 0x7f == byte);
+        }
+    }
+
+    #[test]
+    fn cc_count() {
+        assert!(cc_count_for_frame_time(Duration::from_secs_f64(1001. / 30000.)) == 20);
+        assert!(cc_count_for_frame_time(Duration::from_secs_f64(1. / 60.)) == 10);
+        assert!(cc_count_for_frame_time(Duration::from_secs_f64(1. / 24.)) == 25);
+    }
+
+    #[test]
+    fn wraps_at_32_columns() {
+        let lines = wrap_lines("This caption is a little bit too long for a single row\nnext");
+        let lines: Vec<String> = lines.iter().map(|line| line.iter().collect()).collect();
+        assert!(
+            lines
+                == [
+                    "This caption is a little bit too",
+                    "long for a single row",
+                    "next"
+                ]
+        );
+    }
+
+    #[test]
+    fn pop_on_608() {
+        let mut encoder = CaptionEncoder::new(CaptionMode::PopOn);
+        encoder.show("Hi é");
+        let (cea608, _) = drain(&mut encoder);
+        let pairs = strip_parity(&cea608);
+        assert!(pairs[..4] == [[0x14, 0x20], [0x14, 0x20], [0x14, 0x2e], [0x14, 0x2e]]);
+        // row 15, centered at column 14: indent 12 and tab offset 2
+        assert!(pairs[4..8] == [[0x14, 0x76], [0x14, 0x76], [0x17, 0x22], [0x17, 0x22]]);
+        assert!(pairs[8..10] == [[b'H', b'i'], [b' ', 0x5c]]);
+        assert!(pairs[10..] == [END_OF_CAPTION, END_OF_CAPTION]);
+
+        encoder.clear();
+        let (cea608, _) = drain(&mut encoder);
+        assert!(strip_parity(&cea608) == [ERASE_DISPLAYED_MEMORY, ERASE_DISPLAYED_MEMORY]);
+    }
+
+    #[test]
+    fn ascii_differing_from_608() {
+        let mut encoder = CaptionEncoder::new(CaptionMode::RollUp {
+            rows: RollUpRows::Two,
+        });
+        encoder.show("`ú");
+        let (cea608, _) = drain(&mut encoder);
+        let pairs = strip_parity(&cea608);
+        assert!(pairs[pairs.len() - 1] == [b'\'', 0x60]);
+    }
+
+    #[test]
+    fn extended_char_follows_fallback() {
+        let mut encoder = CaptionEncoder::new(CaptionMode::RollUp {
+            rows: RollUpRows::Two,
+        });
+        encoder.show("Ä");
+        let (cea608, _) = drain(&mut encoder);
+        let pairs = strip_parity(&cea608);
+        assert!(pairs[pairs.len() - 2..] == [[b'A', 0], [0x13, 0x30]]);
+    }
+
+    #[test]
+    fn roll_up_708_packets() {
+        let mut encoder = CaptionEncoder::new(CaptionMode::RollUp {
+            rows: RollUpRows::Three,
+        });
+        encoder.show("Hello");
+        let (_, dtvcc) = drain(&mut encoder);
+        let packet_size = usize::from(dtvcc[0] & 0x3f) * 2;
+        assert!(dtvcc[0] >> 6 == 0);
+        assert!(dtvcc.len() == packet_size);
+        let block_size = usize::from(dtvcc[1] & 0x1f);
+        // service 1
+        assert!(dtvcc[1] >> 5 == 1);
+        let block = &dtvcc[2..2 + block_size];
+        // DefineWindow 0, visible, 3 rows
+        assert!(block[0] == 0x98);
+        assert!(block[1] & 0b0010_0000 != 0);
+        assert!(block[4] & 0x0f == 2);
+        assert!(block[7..] == *b"\rHello\x03");
+    }
+
+    #[test]
+    fn cc_data_payload() {
+        let mut encoder = CaptionEncoder::new(CaptionMode::PopOn);
+        let cc_data: CcData = encoder.next_cc_data(20);
+        let mut writer = OBUWriter::new(vec![]);
+        writer.write_cc_data(&cc_data).unwrap();
+        let output = writer.into_inner();
+        // OBU header, obu_size, metadata_type
+        let payload = &output[3..];
+        assert!(payload[..8] == [0xb5, 0x00, 0x31, b'G', b'A', b'9', b'4', 0x03]);
+        assert!(payload[8] == 0xc0 | 20);
+        assert!(payload[9] == 0xff);
+        assert!(payload[10..13] == [0xfc, 0x80, 0x80]);
+        assert!(payload[13..16] == [0xfd, 0x80, 0x80]);
+        assert!(payload[16..19] == [0xfa, 0x00, 0x00]);
+        // marker_bits, trailing bits
+        assert!(payload[payload.len() - 2..] == [0xff, 0x80]);
+        assert!(payload.len() == 8 + 2 + 60 + 1 + 1);
+    }
+}
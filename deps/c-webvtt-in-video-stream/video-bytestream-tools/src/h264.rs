@@ -1,6 +1,7 @@
 use crate::{
     h26x::{annex_b::WriteNalHeader, NalUnitWrite, NalUnitWriter, RbspWrite, RbspWriter},
-    webvtt::{WebvttTrack, WebvttWrite},
+    sei::{SeiMessage, SeiWrite},
+    webvtt::{SerializedWebvttHeader, WebvttTrack, WebvttWrite},
 };
 use bitstream_io::{BigEndian, BitWrite, BitWriter};
 use h264_reader::nal::UnitType;
@@ -133,6 +134,13 @@ impl<W: Write + ?Sized> WebvttWrite for H264RbspWriter<W> {
             .write_webvtt_header(max_latency_to_video, send_frequency_hz, subtitle_tracks)
     }
 
+    fn write_serialized_webvtt_header(
+        &mut self,
+        header: &SerializedWebvttHeader,
+    ) -> std::io::Result<()> {
+        self.0.write_serialized_webvtt_header(header)
+    }
+
     fn write_webvtt_payload(
         &mut self,
         track_index: u8,
@@ -151,17 +159,59 @@ impl<W: Write + ?Sized> WebvttWrite for H264RbspWriter<W> {
     }
 }
 
+impl<W: Write + ?Sized> SeiWrite for H264RbspWriter<W> {
+    fn write_sei_message(&mut self, message: &SeiMessage) -> std::io::Result<()> {
+        self.0.write_sei_message(message)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::{
-        h264::{H264NalHeader, H264NalUnitWriter},
+        cea708::{CaptionEncoder, CaptionMode, CcDataWrite},
+        h264::{
+            avcc::{AVCCWriter, AVCDecoderConfigurationRecord, DecoderConfigurationRecordError},
+            H264ByteStreamWrite, H264NalHeader, H264NalUnitWriter,
+        },
         h26x::{NalUnitWrite, NalUnitWriter, RbspWrite},
-        webvtt::{WebvttWrite, PAYLOAD_GUID, USER_DATA_UNREGISTERED},
+        sei::USER_DATA_REGISTERED_ITU_T_T35,
+        webvtt::{
+            SerializedWebvttHeader, WebvttTrack, WebvttWrite, MAX_VIDEO_OFFSET, PAYLOAD_GUID,
+            USER_DATA_UNREGISTERED,
+        },
     };
     use byteorder::{BigEndian, ReadBytesExt};
     use h264_reader::nal::{Nal, RefNal, UnitType};
     use std::{io::Read, time::Duration};
 
+    #[test]
+    fn check_cc_data_sei() {
+        let mut writer = vec![];
+
+        let nalu_writer = H264NalUnitWriter(NalUnitWriter::new(&mut writer));
+        let nal_header =
+            H264NalHeader::from_nal_unit_type_and_nal_ref_idc(UnitType::SEI, 0).unwrap();
+        let mut payload_writer = nalu_writer.write_nal_header(nal_header).unwrap();
+        let mut encoder = CaptionEncoder::new(CaptionMode::PopOn);
+        encoder.show("Hello");
+        let cc_data = encoder.next_cc_data(20);
+        payload_writer.write_cc_data(&cc_data).unwrap();
+        payload_writer.finish_rbsp().unwrap();
+
+        let nal = RefNal::new(&writer, &[], true);
+        assert!(nal.is_complete());
+        let mut byte_reader = nal.rbsp_bytes();
+        assert!(usize::from(byte_reader.read_u8().unwrap()) == USER_DATA_REGISTERED_ITU_T_T35);
+        let length = usize::from(byte_reader.read_u8().unwrap());
+        let mut payload = vec![];
+        byte_reader.read_to_end(&mut payload).unwrap();
+        // rbsp_trailing_bits
+        assert!(payload.pop() == Some(0x80));
+        assert!(payload.len() == length);
+        assert!(payload[..8] == [0xb5, 0x00, 0x31, b'G', b'A', b'9', b'4', 0x03]);
+        assert!(payload[8] & 0x1f == 20);
+    }
+
     #[test]
     fn check_webvtt_sei() {
         let mut writer = vec![];
@@ -215,6 +265,24 @@ mod tests {
         println!("{writer:02x?}");
     }
 
+    #[test]
+    fn reject_large_video_offset() {
+        let mut writer = vec![];
+
+        let nalu_writer = H264NalUnitWriter(NalUnitWriter::new(&mut writer));
+        let nal_header =
+            H264NalHeader::from_nal_unit_type_and_nal_ref_idc(UnitType::SEI, 0).unwrap();
+        let mut payload_writer = nalu_writer.write_nal_header(nal_header).unwrap();
+        let error = payload_writer
+            .write_webvtt_payload(0, 1, 0, MAX_VIDEO_OFFSET, "Some text")
+            .unwrap_err();
+        assert!(error.kind() == std::io::ErrorKind::InvalidInput);
+        payload_writer.finish_rbsp().unwrap();
+        assert!(!writer
+            .windows(16)
+            .any(|window| window == PAYLOAD_GUID.as_bytes()));
+    }
+
     #[test]
     fn check_webvtt_multi_sei() {
         let mut writer = vec![];
@@ -269,4 +337,114 @@ mod tests {
         );
         println!("{writer:02x?}");
     }
+
+    #[test]
+    fn check_serialized_webvtt_header() {
+        let tracks = [
+            WebvttTrack {
+                default: true,
+                autoselect: false,
+                forced: false,
+                name: "English",
+                language: "en",
+                assoc_language: None,
+                characteristics: None,
+            },
+            WebvttTrack {
+                default: false,
+                autoselect: true,
+                forced: true,
+                name: "Deutsch",
+                language: "de",
+                assoc_language: Some("en"),
+                characteristics: Some("public.accessibility.transcribes-spoken-dialog"),
+            },
+        ];
+        let latency = Duration::from_millis(1500);
+        let nal_header =
+            H264NalHeader::from_nal_unit_type_and_nal_ref_idc(UnitType::SEI, 0).unwrap();
+
+        let mut expected = vec![];
+        let mut payload_writer = H264NalUnitWriter(NalUnitWriter::new(&mut expected))
+            .write_nal_header(nal_header)
+            .unwrap();
+        payload_writer
+            .write_webvtt_header(latency, 2, &tracks)
+            .unwrap();
+        payload_writer.finish_rbsp().unwrap();
+
+        let mut writer = vec![];
+        let mut payload_writer = H264NalUnitWriter(NalUnitWriter::new(&mut writer))
+            .write_nal_header(nal_header)
+            .unwrap();
+        payload_writer
+            .write_serialized_webvtt_header(&SerializedWebvttHeader::new(latency, 2, &tracks))
+            .unwrap();
+        payload_writer.finish_rbsp().unwrap();
+
+        assert!(writer == expected);
+    }
+
+    #[test]
+    fn check_avcc_length_prefix() {
+        let nal_header =
+            H264NalHeader::from_nal_unit_type_and_nal_ref_idc(UnitType::SEI, 0).unwrap();
+        for length_size in [1, 2, 4] {
+            let mut writer = vec![];
+            let mut payload_writer = AVCCWriter::new(length_size, &mut writer)
+                .unwrap()
+                .start_write_nal_unit()
+                .unwrap()
+                .write_nal_header(nal_header)
+                .unwrap();
+            payload_writer
+                .write_webvtt_payload(0, 1, 0, Duration::from_millis(200), "Some text")
+                .unwrap();
+            payload_writer.finish_rbsp().unwrap();
+
+            let (length, nal) = writer.split_at(length_size);
+            let length = length
+                .iter()
+                .fold(0, |length, byte| length << 8 | usize::from(*byte));
+            assert!(length == nal.len());
+            assert!(&nal[3..19] == PAYLOAD_GUID.as_bytes());
+        }
+    }
+
+    #[test]
+    fn check_avc_decoder_configuration_record() {
+        let record = [
+            0x01, 0x42, 0xc0, 0x1e, 0xff, 0xe1, 0x00, 0x04, 0x67, 0x42, 0xc0, 0x1e, 0x01, 0x00,
+            0x04, 0x68, 0xce, 0x3c, 0x80,
+        ];
+        let parsed = AVCDecoderConfigurationRecord::parse(&record).unwrap();
+        assert!(parsed.profile_indication == 0x42);
+        assert!(parsed.level_indication == 0x1e);
+        assert!(parsed.length_size == 4);
+        assert!(parsed.sequence_parameter_sets == [&[0x67, 0x42, 0xc0, 0x1e][..]]);
+        assert!(parsed.picture_parameter_sets == [&[0x68, 0xce, 0x3c, 0x80][..]]);
+
+        let mut two_byte_lengths = record;
+        two_byte_lengths[4] = 0xfd;
+        assert!(
+            AVCDecoderConfigurationRecord::parse(&two_byte_lengths)
+                .unwrap()
+                .length_size
+                == 2
+        );
+        let mut three_byte_lengths = record;
+        three_byte_lengths[4] = 0xfe;
+        assert!(matches!(
+            AVCDecoderConfigurationRecord::parse(&three_byte_lengths),
+            Err(DecoderConfigurationRecordError::InvalidLengthSize(3))
+        ));
+        assert!(matches!(
+            AVCDecoderConfigurationRecord::parse(&record[..record.len() - 1]),
+            Err(DecoderConfigurationRecordError::Truncated)
+        ));
+        assert!(matches!(
+            AVCDecoderConfigurationRecord::parse(&[0, 0, 0, 1, 0x67]),
+            Err(DecoderConfigurationRecordError::UnsupportedVersion(0))
+        ));
+    }
 }
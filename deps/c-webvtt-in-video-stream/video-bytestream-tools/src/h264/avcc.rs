@@ -1,38 +1,58 @@
 use crate::{
     h264::{H264ByteStreamWrite, H264NalHeader},
-    h26x::{NalUnitWrite, NalUnitWriter, RbspWrite, Result},
-    webvtt::{WebvttTrack, WebvttWrite},
+    h26x::{
+        length_prefixed::{
+            DecoderConfigurationRecordReader, LengthPrefixedBuffer, LengthPrefixedWriter,
+        },
+        NalUnitWrite, NalUnitWriter, RbspWrite, Result,
+    },
+    sei::{SeiMessage, SeiWrite},
+    webvtt::{SerializedWebvttHeader, WebvttTrack, WebvttWrite},
 };
-use byteorder::{BigEndian, WriteBytesExt};
 use std::{io::Write, time::Duration};
-use thiserror::Error;
 
 use super::{H264NalUnitWriter, H264RbspWriter};
 
-const AVCC_MAX_LENGTH: [usize; 4] = [0xff, 0xff_ff, 0, 0xff_ff_ff_ff];
+pub use crate::h26x::length_prefixed::{
+    DecoderConfigurationRecordError, InvalidLengthError, MaxNalUnitSizeExceededError,
+};
 
-pub struct AVCCWriter<W: ?Sized + Write> {
-    length_size: usize,
-    inner: W,
+/// The `avcC` extradata of length-prefixed H.264 streams
+#[derive(Debug, Clone)]
+pub struct AVCDecoderConfigurationRecord<'a> {
+    pub profile_indication: u8,
+    pub level_indication: u8,
+    pub length_size: usize,
+    pub sequence_parameter_sets: Vec<&'a [u8]>,
+    pub picture_parameter_sets: Vec<&'a [u8]>,
 }
 
-#[derive(Error, Debug)]
-#[error("AVCC length of {0} is unsupported")]
-pub struct InvalidLengthError(pub usize);
-
-#[derive(Error, Debug)]
-#[error("Tried to write {required} bytes which exceeds the max size of {max}")]
-pub struct MaxNalUnitSizeExceededError {
-    max: usize,
-    required: usize,
+impl<'a> AVCDecoderConfigurationRecord<'a> {
+    pub fn parse(record: &'a [u8]) -> Result<Self, DecoderConfigurationRecordError> {
+        let mut reader = DecoderConfigurationRecordReader::new(record)?;
+        let profile_indication = reader.read_u8()?;
+        let _profile_compatibility = reader.read_u8()?;
+        let level_indication = reader.read_u8()?;
+        let length_size = reader.read_length_size()?;
+        let sps_count = reader.read_u8()? & 0b1_1111;
+        let sequence_parameter_sets = reader.read_nal_units(sps_count.into())?;
+        let pps_count = reader.read_u8()?;
+        let picture_parameter_sets = reader.read_nal_units(pps_count.into())?;
+        Ok(Self {
+            profile_indication,
+            level_indication,
+            length_size,
+            sequence_parameter_sets,
+            picture_parameter_sets,
+        })
+    }
 }
 
+pub struct AVCCWriter<W: ?Sized + Write>(LengthPrefixedWriter<W>);
+
 impl<W: Write> AVCCWriter<W> {
     pub fn new(length_size: usize, inner: W) -> Result<Self, InvalidLengthError> {
-        match length_size {
-            1 | 2 | 4 => Ok(Self { length_size, inner }),
-            _ => Err(InvalidLengthError(length_size)),
-        }
+        LengthPrefixedWriter::new(length_size, inner).map(Self)
     }
 }
 
@@ -41,54 +61,12 @@ impl<W: Write> H264ByteStreamWrite<W> for AVCCWriter<W> {
 
     fn start_write_nal_unit(self) -> Result<AVCCNalUnitWriter<AVCCWriterBuffer<W>>> {
         Ok(AVCCNalUnitWriter {
-            inner: H264NalUnitWriter(NalUnitWriter::new(AVCCWriterBuffer::new(self))),
+            inner: H264NalUnitWriter(NalUnitWriter::new(LengthPrefixedBuffer::new(self.0))),
         })
     }
 }
 
-pub struct AVCCWriterBuffer<W: ?Sized + Write> {
-    avcc_buffer: Vec<u8>,
-    avcc_writer: AVCCWriter<W>,
-}
-
-impl<W: Write> AVCCWriterBuffer<W> {
-    fn new(avcc_writer: AVCCWriter<W>) -> Self {
-        Self {
-            avcc_buffer: vec![],
-            avcc_writer,
-        }
-    }
-
-    fn finish(mut self) -> Result<AVCCWriter<W>> {
-        match self.avcc_writer.length_size {
-            1 => self.write_u8(self.avcc_buffer.len().try_into().unwrap())?,
-            2 => self.write_u16::<BigEndian>(self.avcc_buffer.len().try_into().unwrap())?,
-            4 => self.write_u32::<BigEndian>(self.avcc_buffer.len().try_into().unwrap())?,
-            _ => unreachable!(),
-        }
-        self.avcc_writer.inner.write_all(&self.avcc_buffer)?;
-        Ok(self.avcc_writer)
-    }
-}
-
-impl<W: ?Sized + Write> Write for AVCCWriterBuffer<W> {
-    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        let length = self.avcc_buffer.len();
-        let additional_length = buf.len();
-        if length + additional_length > AVCC_MAX_LENGTH[self.avcc_writer.length_size] {
-            Err(std::io::Error::other(MaxNalUnitSizeExceededError {
-                max: AVCC_MAX_LENGTH[self.avcc_writer.length_size],
-                required: length + additional_length,
-            }))
-        } else {
-            self.avcc_buffer.write(buf)
-        }
-    }
-
-    fn flush(&mut self) -> std::io::Result<()> {
-        Ok(())
-    }
-}
+pub type AVCCWriterBuffer<W> = LengthPrefixedBuffer<W>;
 
 pub struct AVCCNalUnitWriter<W: ?Sized + Write> {
     inner: H264NalUnitWriter<W>,
@@ -113,7 +91,7 @@ impl<W: Write> RbspWrite<W> for AVCCRbspWriter<AVCCWriterBuffer<W>> {
 
     fn finish_rbsp(self) -> Result<Self::Writer> {
         let buffer = self.0.finish_rbsp()?;
-        buffer.finish()
+        buffer.finish().map(AVCCWriter)
     }
 }
 
@@ -128,6 +106,13 @@ impl<W: Write + ?Sized> WebvttWrite for AVCCRbspWriter<W> {
             .write_webvtt_header(max_latency_to_video, send_frequency_hz, subtitle_tracks)
     }
 
+    fn write_serialized_webvtt_header(
+        &mut self,
+        header: &SerializedWebvttHeader,
+    ) -> std::io::Result<()> {
+        self.0.write_serialized_webvtt_header(header)
+    }
+
     fn write_webvtt_payload(
         &mut self,
         track_index: u8,
@@ -145,3 +130,9 @@ impl<W: Write + ?Sized> WebvttWrite for AVCCRbspWriter<W> {
         )
     }
 }
+
+impl<W: Write + ?Sized> SeiWrite for AVCCRbspWriter<W> {
+    fn write_sei_message(&mut self, message: &SeiMessage) -> std::io::Result<()> {
+        self.0.write_sei_message(message)
+    }
+}
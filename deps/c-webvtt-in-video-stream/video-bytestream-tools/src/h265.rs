@@ -1,6 +1,8 @@
 use crate::{
     h26x::{annex_b::WriteNalHeader, NalUnitWrite, NalUnitWriter, RbspWrite, RbspWriter},
-    webvtt::{WebvttTrack, WebvttWrite},
+    sei::{SeiMessage, SeiWrite},
+    timecode::{write_h265_time_code, Timecode, TimecodeWrite},
+    webvtt::{SerializedWebvttHeader, WebvttTrack, WebvttWrite},
 };
 use bitstream_io::{BigEndian, BitWrite, BitWriter};
 use std::{io::Write, time::Duration};
@@ -8,6 +10,7 @@ use std::{io::Write, time::Duration};
 type Result<T, E = std::io::Error> = std::result::Result<T, E>;
 
 pub mod annex_b;
+pub mod hvcc;
 
 #[derive(Debug, Clone, Copy)]
 pub enum UnitType {
@@ -243,6 +246,13 @@ impl<W: Write + ?Sized> WebvttWrite for H265RbspWriter<W> {
             .write_webvtt_header(max_latency_to_video, send_frequency_hz, subtitle_tracks)
     }
 
+    fn write_serialized_webvtt_header(
+        &mut self,
+        header: &SerializedWebvttHeader,
+    ) -> std::io::Result<()> {
+        self.0.write_serialized_webvtt_header(header)
+    }
+
     fn write_webvtt_payload(
         &mut self,
         track_index: u8,
@@ -261,10 +271,26 @@ impl<W: Write + ?Sized> WebvttWrite for H265RbspWriter<W> {
     }
 }
 
+impl<W: Write + ?Sized> SeiWrite for H265RbspWriter<W> {
+    fn write_sei_message(&mut self, message: &SeiMessage) -> std::io::Result<()> {
+        self.0.write_sei_message(message)
+    }
+}
+
+impl<W: Write + ?Sized> TimecodeWrite for H265RbspWriter<W> {
+    fn write_timecode(&mut self, timecode: &Timecode) -> std::io::Result<()> {
+        write_h265_time_code(self, timecode)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::{
-        h265::{H265NalHeader, H265NalUnitWriter, UnitType},
+        h265::{
+            annex_b::AnnexBWriter,
+            hvcc::{HEVCDecoderConfigurationRecord, HVCCWriter},
+            H265ByteStreamWrite, H265NalHeader, H265NalUnitWriter, UnitType,
+        },
         h26x::{NalUnitWrite, NalUnitWriter, RbspWrite},
         webvtt::{WebvttWrite, PAYLOAD_GUID, USER_DATA_UNREGISTERED},
     };
@@ -297,7 +323,7 @@ mod tests {
                 let res = self.inner.read_u8();
                 let byte = match res {
                     Ok(byte) => byte,
-                    Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(0),
+                    Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(read),
                     Err(err) => return Err(err),
                 };
                 let mut last_read_iter = self.last_read.iter();
@@ -352,6 +378,7 @@ mod tests {
 
         assert!(&writer[4..20] == PAYLOAD_GUID.as_bytes());
         assert!(writer[0] == nal_unit_type.id() << 1);
+        assert!((writer[0] >> 1) & 0x3f == 39);
 
         let mut reader = RbspReader::new(&writer[2..]);
 
@@ -364,7 +391,9 @@ mod tests {
                 break;
             }
         }
-        assert!(dbg!(length + 1) == dbg!(reader.clone().bytes().count()));
+        let mut remaining = vec![];
+        reader.clone().read_to_end(&mut remaining).unwrap();
+        assert!(length + 1 == remaining.len());
         reader.read_u128::<BigEndian>().unwrap();
         assert!(track_index == reader.read_u8().unwrap());
         assert!(chunk_number == reader.read_u64::<BigEndian>().unwrap());
@@ -412,6 +441,7 @@ mod tests {
 
         assert!(&writer[4..20] == PAYLOAD_GUID.as_bytes());
         assert!(writer[0] == nal_unit_type.id() << 1);
+        assert!((writer[0] >> 1) & 0x3f == 39);
 
         let mut reader = RbspReader::new(&writer[2..]);
 
@@ -434,4 +464,97 @@ mod tests {
         );
         println!("{writer:02x?}");
     }
+
+    #[test]
+    fn check_webvtt_suffix_sei() {
+        let mut writer = vec![];
+
+        let nal_header =
+            H265NalHeader::from_nal_unit_type_and_nuh_ids(UnitType::SuffixSeiNut, 0, 0).unwrap();
+        let mut payload_writer = AnnexBWriter::new(&mut writer)
+            .start_write_nal_unit()
+            .unwrap()
+            .write_nal_header(nal_header)
+            .unwrap();
+        payload_writer
+            .write_webvtt_payload(0, 1, 0, Duration::from_millis(200), "Some unverified data")
+            .unwrap();
+        payload_writer.finish_rbsp().unwrap();
+
+        assert!(writer[..4] == [0, 0, 0, 1]);
+        let nal_unit_type = (writer[4] >> 1) & 0x3f;
+        assert!(nal_unit_type == 40);
+        let nuh_layer_id = (writer[4] & 1) << 5 | writer[5] >> 3;
+        assert!(nuh_layer_id == 0);
+        let nuh_temporal_id_plus1 = writer[5] & 0b111;
+        assert!(nuh_temporal_id_plus1 == 1);
+        assert!(usize::from(writer[6]) == USER_DATA_UNREGISTERED);
+        assert!(&writer[8..24] == PAYLOAD_GUID.as_bytes());
+    }
+
+    #[test]
+    fn check_hvcc_length_prefix() {
+        let nal_header =
+            H265NalHeader::from_nal_unit_type_and_nuh_ids(UnitType::PrefixSeiNut, 0, 0).unwrap();
+        for length_size in [1, 2, 4] {
+            let mut writer = vec![];
+            let mut payload_writer = HVCCWriter::new(length_size, &mut writer)
+                .unwrap()
+                .start_write_nal_unit()
+                .unwrap()
+                .write_nal_header(nal_header)
+                .unwrap();
+            payload_writer
+                .write_webvtt_payload(0, 1, 0, Duration::from_millis(200), "Some text")
+                .unwrap();
+            payload_writer.finish_rbsp().unwrap();
+
+            let (length, nal) = writer.split_at(length_size);
+            let length = length
+                .iter()
+                .fold(0, |length, byte| length << 8 | usize::from(*byte));
+            assert!(length == nal.len());
+            assert!((nal[0] >> 1) & 0x3f == 39);
+            assert!(&nal[4..20] == PAYLOAD_GUID.as_bytes());
+        }
+        assert!(HVCCWriter::new(3, vec![]).is_err());
+    }
+
+    #[test]
+    fn check_hevc_decoder_configuration_record() {
+        let mut record = vec![
+            0x01, // configurationVersion
+            0x01, // general_profile_space, general_tier_flag, general_profile_idc
+            0x60, 0x00, 0x00, 0x00, // general_profile_compatibility_flags
+            0x90, 0x00, 0x00, 0x00, 0x00, 0x00, // general_constraint_indicator_flags
+            0x5d, // general_level_idc
+            0xf0, 0x00, // min_spatial_segmentation_idc
+            0xfc, // parallelismType
+            0xfd, // chromaFormat
+            0xf8, // bitDepthLumaMinus8
+            0xf8, // bitDepthChromaMinus8
+            0x00, 0x00, // avgFrameRate
+            0x0f, // constantFrameRate, numTemporalLayers, temporalIdNested, lengthSizeMinusOne
+            0x04, // numOfArrays
+        ];
+        for (nal_unit_type, nal_unit) in [
+            (32, &[0x40, 0x01, 0x0c][..]),
+            (33, &[0x42, 0x01, 0x01][..]),
+            (34, &[0x44, 0x01, 0xc1][..]),
+            (39, &[0x4e, 0x01, 0x05][..]),
+        ] {
+            record.push(0x80 | nal_unit_type);
+            record.extend_from_slice(&1u16.to_be_bytes());
+            record.extend_from_slice(&u16::try_from(nal_unit.len()).unwrap().to_be_bytes());
+            record.extend_from_slice(nal_unit);
+        }
+        let parsed = HEVCDecoderConfigurationRecord::parse(&record).unwrap();
+        assert!(parsed.general_profile_idc == 1);
+        assert!(parsed.general_level_idc == 0x5d);
+        assert!(parsed.length_size == 4);
+        assert!(parsed.video_parameter_sets == [&[0x40, 0x01, 0x0c][..]]);
+        assert!(parsed.sequence_parameter_sets == [&[0x42, 0x01, 0x01][..]]);
+        assert!(parsed.picture_parameter_sets == [&[0x44, 0x01, 0xc1][..]]);
+        assert!(HEVCDecoderConfigurationRecord::parse(&record[..30]).is_err());
+    }
 }
@@ -7,7 +7,9 @@ use crate::{
         },
         NalUnitWrite, RbspWrite, Result,
     },
-    webvtt::{WebvttTrack, WebvttWrite},
+    sei::{SeiMessage, SeiWrite},
+    timecode::{write_h265_time_code, Timecode, TimecodeWrite},
+    webvtt::{SerializedWebvttHeader, WebvttTrack, WebvttWrite},
 };
 use std::{io::Write, time::Duration};
 
@@ -59,6 +61,13 @@ impl<W: Write + ?Sized> WebvttWrite for AnnexBRbspWriter<W> {
             .write_webvtt_header(max_latency_to_video, send_frequency_hz, subtitle_tracks)
     }
 
+    fn write_serialized_webvtt_header(
+        &mut self,
+        header: &SerializedWebvttHeader,
+    ) -> std::io::Result<()> {
+        self.0.write_serialized_webvtt_header(header)
+    }
+
     fn write_webvtt_payload(
         &mut self,
         track_index: u8,
@@ -76,3 +85,15 @@ impl<W: Write + ?Sized> WebvttWrite for AnnexBRbspWriter<W> {
         )
     }
 }
+
+impl<W: Write + ?Sized> SeiWrite for AnnexBRbspWriter<W> {
+    fn write_sei_message(&mut self, message: &SeiMessage) -> std::io::Result<()> {
+        self.0.write_sei_message(message)
+    }
+}
+
+impl<W: Write + ?Sized> TimecodeWrite for AnnexBRbspWriter<W> {
+    fn write_timecode(&mut self, timecode: &Timecode) -> std::io::Result<()> {
+        write_h265_time_code(self, timecode)
+    }
+}
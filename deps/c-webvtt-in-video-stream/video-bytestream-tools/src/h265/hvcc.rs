@@ -0,0 +1,161 @@
+use crate::{
+    h265::{H265ByteStreamWrite, H265NalHeader},
+    h26x::{
+        length_prefixed::{
+            DecoderConfigurationRecordReader, LengthPrefixedBuffer, LengthPrefixedWriter,
+        },
+        NalUnitWrite, NalUnitWriter, RbspWrite, Result,
+    },
+    sei::{SeiMessage, SeiWrite},
+    timecode::{write_h265_time_code, Timecode, TimecodeWrite},
+    webvtt::{SerializedWebvttHeader, WebvttTrack, WebvttWrite},
+};
+use std::{io::Write, time::Duration};
+
+use super::{H265NalUnitWriter, H265RbspWriter};
+
+pub use crate::h26x::length_prefixed::{
+    DecoderConfigurationRecordError, InvalidLengthError, MaxNalUnitSizeExceededError,
+};
+
+/// The `hvcC` extradata of length-prefixed H.265 streams
+#[derive(Debug, Clone)]
+pub struct HEVCDecoderConfigurationRecord<'a> {
+    pub general_profile_idc: u8,
+    pub general_level_idc: u8,
+    pub length_size: usize,
+    pub video_parameter_sets: Vec<&'a [u8]>,
+    pub sequence_parameter_sets: Vec<&'a [u8]>,
+    pub picture_parameter_sets: Vec<&'a [u8]>,
+}
+
+impl<'a> HEVCDecoderConfigurationRecord<'a> {
+    pub fn parse(record: &'a [u8]) -> Result<Self, DecoderConfigurationRecordError> {
+        let mut reader = DecoderConfigurationRecordReader::new(record)?;
+        let general_profile_idc = reader.read_u8()? & 0b1_1111;
+        // general_profile_compatibility_flags and general_constraint_indicator_flags
+        reader.skip(4 + 6)?;
+        let general_level_idc = reader.read_u8()?;
+        // min_spatial_segmentation_idc, parallelismType, chromaFormat, bitDepthLumaMinus8,
+        // bitDepthChromaMinus8 and avgFrameRate
+        reader.skip(2 + 1 + 1 + 1 + 1 + 2)?;
+        let length_size = reader.read_length_size()?;
+        let mut record = Self {
+            general_profile_idc,
+            general_level_idc,
+            length_size,
+            video_parameter_sets: vec![],
+            sequence_parameter_sets: vec![],
+            picture_parameter_sets: vec![],
+        };
+        let array_count = reader.read_u8()?;
+        for _ in 0..array_count {
+            let nal_unit_type = reader.read_u8()? & 0b11_1111;
+            let nal_unit_count = reader.read_u16()?;
+            let nal_units = reader.read_nal_units(nal_unit_count.into())?;
+            match nal_unit_type {
+                32 => record.video_parameter_sets.extend(nal_units),
+                33 => record.sequence_parameter_sets.extend(nal_units),
+                34 => record.picture_parameter_sets.extend(nal_units),
+                // e.g. SEI arrays
+                _ => {}
+            }
+        }
+        Ok(record)
+    }
+}
+
+pub struct HVCCWriter<W: ?Sized + Write>(LengthPrefixedWriter<W>);
+
+impl<W: Write> HVCCWriter<W> {
+    pub fn new(length_size: usize, inner: W) -> Result<Self, InvalidLengthError> {
+        LengthPrefixedWriter::new(length_size, inner).map(Self)
+    }
+}
+
+impl<W: Write> H265ByteStreamWrite<W> for HVCCWriter<W> {
+    type Writer = HVCCNalUnitWriter<HVCCWriterBuffer<W>>;
+
+    fn start_write_nal_unit(self) -> Result<HVCCNalUnitWriter<HVCCWriterBuffer<W>>> {
+        Ok(HVCCNalUnitWriter {
+            inner: H265NalUnitWriter(NalUnitWriter::new(LengthPrefixedBuffer::new(self.0))),
+        })
+    }
+}
+
+pub type HVCCWriterBuffer<W> = LengthPrefixedBuffer<W>;
+
+pub struct HVCCNalUnitWriter<W: ?Sized + Write> {
+    inner: H265NalUnitWriter<W>,
+}
+
+impl<W: Write> NalUnitWrite<W> for HVCCNalUnitWriter<HVCCWriterBuffer<W>> {
+    type Writer = HVCCRbspWriter<HVCCWriterBuffer<W>>;
+    type NalHeader = H265NalHeader;
+
+    fn write_nal_header(
+        self,
+        nal_header: Self::NalHeader,
+    ) -> Result<HVCCRbspWriter<HVCCWriterBuffer<W>>> {
+        self.inner.write_nal_header(nal_header).map(HVCCRbspWriter)
+    }
+}
+
+pub struct HVCCRbspWriter<W: ?Sized + Write>(H265RbspWriter<W>);
+
+impl<W: Write> RbspWrite<W> for HVCCRbspWriter<HVCCWriterBuffer<W>> {
+    type Writer = HVCCWriter<W>;
+
+    fn finish_rbsp(self) -> Result<Self::Writer> {
+        let buffer = self.0.finish_rbsp()?;
+        buffer.finish().map(HVCCWriter)
+    }
+}
+
+impl<W: Write + ?Sized> WebvttWrite for HVCCRbspWriter<W> {
+    fn write_webvtt_header(
+        &mut self,
+        max_latency_to_video: Duration,
+        send_frequency_hz: u8,
+        subtitle_tracks: &[WebvttTrack],
+    ) -> std::io::Result<()> {
+        self.0
+            .write_webvtt_header(max_latency_to_video, send_frequency_hz, subtitle_tracks)
+    }
+
+    fn write_serialized_webvtt_header(
+        &mut self,
+        header: &SerializedWebvttHeader,
+    ) -> std::io::Result<()> {
+        self.0.write_serialized_webvtt_header(header)
+    }
+
+    fn write_webvtt_payload(
+        &mut self,
+        track_index: u8,
+        chunk_number: u64,
+        chunk_version: u8,
+        video_offset: Duration,
+        webvtt_payload: &str, // TODO: replace with string type that checks for interior NULs
+    ) -> std::io::Result<()> {
+        self.0.write_webvtt_payload(
+            track_index,
+            chunk_number,
+            chunk_version,
+            video_offset,
+            webvtt_payload,
+        )
+    }
+}
+
+impl<W: Write + ?Sized> SeiWrite for HVCCRbspWriter<W> {
+    fn write_sei_message(&mut self, message: &SeiMessage) -> std::io::Result<()> {
+        self.0.write_sei_message(message)
+    }
+}
+
+impl<W: Write + ?Sized> TimecodeWrite for HVCCRbspWriter<W> {
+    fn write_timecode(&mut self, timecode: &Timecode) -> std::io::Result<()> {
+        write_h265_time_code(self, timecode)
+    }
+}
@@ -0,0 +1,276 @@
+use crate::{
+    h26x::{annex_b::WriteNalHeader, NalUnitWrite, NalUnitWriter, RbspWrite, RbspWriter},
+    sei::{SeiMessage, SeiWrite},
+    timecode::{write_h265_time_code, Timecode, TimecodeWrite},
+    webvtt::{SerializedWebvttHeader, WebvttTrack, WebvttWrite},
+};
+use bitstream_io::{BigEndian, BitWrite, BitWriter};
+use std::{io::Write, time::Duration};
+
+type Result<T, E = std::io::Error> = std::result::Result<T, E>;
+
+pub mod annex_b;
+
+#[derive(Debug, Clone, Copy)]
+pub enum UnitType {
+    TrailNut,
+    StsaNut,
+    RadlNut,
+    RaslNut,
+    RsvVcl4,
+    RsvVcl5,
+    RsvVcl6,
+    IdrWRadl,
+    IdrNLp,
+    CraNut,
+    GdrNut,
+    RsvIrap11,
+    OpiNut,
+    DciNut,
+    VpsNut,
+    SpsNut,
+    PpsNut,
+    PrefixApsNut,
+    SuffixApsNut,
+    PhNut,
+    AudNut,
+    EosNut,
+    EobNut,
+    PrefixSeiNut,
+    SuffixSeiNut,
+    FdNut,
+    RsvNvcl26,
+    RsvNvcl27,
+    Unspec28,
+    Unspec29,
+    Unspec30,
+    Unspec31,
+}
+
+impl UnitType {
+    fn id(self) -> u8 {
+        match self {
+            UnitType::TrailNut => 0,
+            UnitType::StsaNut => 1,
+            UnitType::RadlNut => 2,
+            UnitType::RaslNut => 3,
+            UnitType::RsvVcl4 => 4,
+            UnitType::RsvVcl5 => 5,
+            UnitType::RsvVcl6 => 6,
+            UnitType::IdrWRadl => 7,
+            UnitType::IdrNLp => 8,
+            UnitType::CraNut => 9,
+            UnitType::GdrNut => 10,
+            UnitType::RsvIrap11 => 11,
+            UnitType::OpiNut => 12,
+            UnitType::DciNut => 13,
+            UnitType::VpsNut => 14,
+            UnitType::SpsNut => 15,
+            UnitType::PpsNut => 16,
+            UnitType::PrefixApsNut => 17,
+            UnitType::SuffixApsNut => 18,
+            UnitType::PhNut => 19,
+            UnitType::AudNut => 20,
+            UnitType::EosNut => 21,
+            UnitType::EobNut => 22,
+            UnitType::PrefixSeiNut => 23,
+            UnitType::SuffixSeiNut => 24,
+            UnitType::FdNut => 25,
+            UnitType::RsvNvcl26 => 26,
+            UnitType::RsvNvcl27 => 27,
+            UnitType::Unspec28 => 28,
+            UnitType::Unspec29 => 29,
+            UnitType::Unspec30 => 30,
+            UnitType::Unspec31 => 31,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct H266NalHeader {
+    nal_unit_type: UnitType,
+    nuh_layer_id: u8,
+    nuh_temporal_id: u8,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum H266NalHeaderError {
+    NuhLayerIdOutOfRange(u8),
+    NuhTemporalIdOutOfRange(u8),
+}
+
+impl H266NalHeader {
+    pub fn from_nal_unit_type_and_nuh_ids(
+        nal_unit_type: UnitType,
+        nuh_layer_id: u8,
+        nuh_temporal_id: u8,
+    ) -> Result<Self, H266NalHeaderError> {
+        if nuh_layer_id >= 0b100_0000 {
+            return Err(H266NalHeaderError::NuhLayerIdOutOfRange(nuh_layer_id));
+        }
+        if nuh_temporal_id >= (0b1000 - 1) {
+            return Err(H266NalHeaderError::NuhTemporalIdOutOfRange(nuh_temporal_id));
+        }
+        Ok(Self {
+            nal_unit_type,
+            nuh_layer_id,
+            nuh_temporal_id,
+        })
+    }
+
+    fn as_header_bytes(&self) -> Result<[u8; 2]> {
+        let mut output = [0u8; 2];
+        let mut writer = BitWriter::endian(&mut output[..], BigEndian);
+        writer.write(1, 0)?;
+        writer.write(1, 0)?;
+        writer.write(6, self.nuh_layer_id)?;
+        writer.write(5, self.nal_unit_type.id())?;
+        writer.write(3, self.nuh_temporal_id + 1)?;
+        assert!(writer.into_unwritten() == (0, 0));
+        Ok(output)
+    }
+}
+
+impl<W: ?Sized + Write> WriteNalHeader<W> for H266NalHeader {
+    fn write_to(self, writer: &mut W) -> crate::h26x::Result<()> {
+        writer.write_all(&self.as_header_bytes()?[..])
+    }
+}
+
+pub trait H266ByteStreamWrite<W: ?Sized + Write> {
+    type Writer: NalUnitWrite<W>;
+    fn start_write_nal_unit(self) -> Result<Self::Writer>;
+}
+
+impl<W: Write> H266ByteStreamWrite<W> for W {
+    type Writer = H266NalUnitWriter<W>;
+
+    fn start_write_nal_unit(self) -> Result<Self::Writer> {
+        Ok(H266NalUnitWriter(NalUnitWriter::new(self)))
+    }
+}
+
+pub struct H266NalUnitWriter<W: ?Sized + Write>(NalUnitWriter<W>);
+pub struct H266RbspWriter<W: ?Sized + Write>(RbspWriter<W>);
+
+impl<W: Write> NalUnitWrite<W> for H266NalUnitWriter<W> {
+    type Writer = H266RbspWriter<W>;
+    type NalHeader = H266NalHeader;
+
+    fn write_nal_header(mut self, nal_header: H266NalHeader) -> Result<H266RbspWriter<W>> {
+        self.0.inner.write_all(&nal_header.as_header_bytes()?[..])?;
+        Ok(H266RbspWriter(RbspWriter::new(self.0.inner)))
+    }
+}
+
+impl<W: Write> RbspWrite<W> for H266RbspWriter<W> {
+    type Writer = W;
+
+    fn finish_rbsp(self) -> crate::h26x::Result<Self::Writer> {
+        self.0.finish_rbsp()
+    }
+}
+
+impl<W: Write + ?Sized> WebvttWrite for H266RbspWriter<W> {
+    fn write_webvtt_header(
+        &mut self,
+        max_latency_to_video: Duration,
+        send_frequency_hz: u8,
+        subtitle_tracks: &[WebvttTrack],
+    ) -> std::io::Result<()> {
+        self.0
+            .write_webvtt_header(max_latency_to_video, send_frequency_hz, subtitle_tracks)
+    }
+
+    fn write_serialized_webvtt_header(
+        &mut self,
+        header: &SerializedWebvttHeader,
+    ) -> std::io::Result<()> {
+        self.0.write_serialized_webvtt_header(header)
+    }
+
+    fn write_webvtt_payload(
+        &mut self,
+        track_index: u8,
+        chunk_number: u64,
+        chunk_version: u8,
+        video_offset: Duration,
+        webvtt_payload: &str, // TODO: replace with string type that checks for interior NULs
+    ) -> std::io::Result<()> {
+        self.0.write_webvtt_payload(
+            track_index,
+            chunk_number,
+            chunk_version,
+            video_offset,
+            webvtt_payload,
+        )
+    }
+}
+
+impl<W: Write + ?Sized> SeiWrite for H266RbspWriter<W> {
+    fn write_sei_message(&mut self, message: &SeiMessage) -> std::io::Result<()> {
+        self.0.write_sei_message(message)
+    }
+}
+
+impl<W: Write + ?Sized> TimecodeWrite for H266RbspWriter<W> {
+    fn write_timecode(&mut self, timecode: &Timecode) -> std::io::Result<()> {
+        write_h265_time_code(self, timecode)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        h266::{annex_b::AnnexBWriter, H266ByteStreamWrite, H266NalHeader, UnitType},
+        h26x::{NalUnitWrite, RbspWrite},
+        webvtt::{WebvttWrite, PAYLOAD_GUID, USER_DATA_UNREGISTERED},
+    };
+    use std::time::Duration;
+
+    #[test]
+    fn check_webvtt_sei() {
+        let mut writer = vec![];
+
+        for (nal_unit_type, id) in [(UnitType::PrefixSeiNut, 23), (UnitType::SuffixSeiNut, 24)] {
+            writer.clear();
+            let nuh_layer_id = 1;
+            let nuh_temporal_id = 2;
+            let nal_header = H266NalHeader::from_nal_unit_type_and_nuh_ids(
+                nal_unit_type,
+                nuh_layer_id,
+                nuh_temporal_id,
+            )
+            .unwrap();
+            let mut payload_writer = AnnexBWriter::new(&mut writer)
+                .start_write_nal_unit()
+                .unwrap()
+                .write_nal_header(nal_header)
+                .unwrap();
+            payload_writer
+                .write_webvtt_payload(0, 1, 0, Duration::from_millis(200), "Some unverified data")
+                .unwrap();
+            payload_writer.finish_rbsp().unwrap();
+
+            assert!(writer[..4] == [0, 0, 0, 1]);
+            // forbidden_zero_bit and nuh_reserved_zero_bit
+            assert!(writer[4] >> 6 == 0);
+            assert!(writer[4] & 0b11_1111 == nuh_layer_id);
+            assert!(writer[5] >> 3 == id);
+            assert!(writer[5] & 0b111 == nuh_temporal_id + 1);
+            assert!(usize::from(writer[6]) == USER_DATA_UNREGISTERED);
+            assert!(&writer[8..24] == PAYLOAD_GUID.as_bytes());
+            assert!(writer.last() == Some(&0x80));
+        }
+    }
+
+    #[test]
+    fn check_nal_header_ranges() {
+        assert!(
+            H266NalHeader::from_nal_unit_type_and_nuh_ids(UnitType::PrefixSeiNut, 64, 0).is_err()
+        );
+        assert!(
+            H266NalHeader::from_nal_unit_type_and_nuh_ids(UnitType::PrefixSeiNut, 0, 7).is_err()
+        );
+    }
+}
@@ -1,10 +1,17 @@
-use crate::webvtt::{
-    write_webvtt_header, write_webvtt_payload, WebvttTrack, WebvttWrite, USER_DATA_UNREGISTERED,
+use crate::{
+    sei::{self, unsupported_message_error, SeiMessage, SeiWrite},
+    webvtt::{
+        write_serialized_webvtt_header, write_webvtt_header, write_webvtt_payload,
+        SerializedWebvttHeader, WebvttTrack, WebvttWrite, USER_DATA_UNREGISTERED,
+    },
 };
 use byteorder::WriteBytesExt;
-use std::{collections::VecDeque, io::Write, time::Duration};
+use memchr::memchr;
+use std::{io::Write, time::Duration};
 
+pub mod access_unit;
 pub(crate) mod annex_b;
+pub(crate) mod length_prefixed;
 
 pub(crate) type Result<T, E = std::io::Error> = std::result::Result<T, E>;
 
@@ -24,8 +31,9 @@ impl<W: Write> NalUnitWriter<W> {
     }
 }
 
-pub(crate) struct RbspWriter<W: ?Sized + Write> {
-    last_written: VecDeque<u8>,
+/// Inserts emulation prevention bytes into everything written through it.
+pub struct RbspWriter<W: ?Sized + Write> {
+    trailing_zeros: u8,
     inner: W,
 }
 
@@ -37,7 +45,7 @@ pub trait RbspWrite<W: ?Sized + Write> {
 impl<W: Write> RbspWriter<W> {
     pub fn new(inner: W) -> Self {
         Self {
-            last_written: VecDeque::with_capacity(3),
+            trailing_zeros: 0,
             inner,
         }
     }
@@ -50,24 +58,30 @@ impl<W: Write> RbspWriter<W> {
 
 impl<W: ?Sized + Write> Write for RbspWriter<W> {
     fn write(&mut self, buf: &[u8]) -> Result<usize> {
-        let mut written = 0;
-        for &byte in buf {
-            let mut last_written_iter = self.last_written.iter();
-            if last_written_iter.next() == Some(&0)
-                && last_written_iter.next() == Some(&0)
-                && (byte == 0 || byte == 1 || byte == 2 || byte == 3)
-            {
+        // bytes since the last emulation prevention byte are passed through as one slice
+        let mut pending_start = 0;
+        let mut position = 0;
+        while position < buf.len() {
+            let byte = buf[position];
+            if self.trailing_zeros == 2 && byte <= 3 {
+                self.inner.write_all(&buf[pending_start..position])?;
                 self.inner.write_u8(3)?;
-                self.last_written.clear();
+                pending_start = position;
+                self.trailing_zeros = 0;
             }
-            self.inner.write_u8(byte)?;
-            written += 1;
-            self.last_written.push_back(byte);
-            if self.last_written.len() > 2 {
-                self.last_written.pop_front();
+            if byte == 0 {
+                self.trailing_zeros = (self.trailing_zeros + 1).min(2);
+                position += 1;
+            } else {
+                self.trailing_zeros = 0;
+                position = match memchr(0, &buf[position + 1..]) {
+                    Some(offset) => position + 1 + offset,
+                    None => buf.len(),
+                };
             }
         }
-        Ok(written)
+        self.inner.write_all(&buf[pending_start..])?;
+        Ok(buf.len())
     }
 
     fn flush(&mut self) -> Result<()> {
@@ -75,7 +89,8 @@ impl<W: ?Sized + Write> Write for RbspWriter<W> {
     }
 }
 
-pub(crate) fn write_sei_header<W: ?Sized + Write>(
+/// Writes the `payload_type` and `payload_size` of a `sei_message`
+pub fn write_sei_header<W: ?Sized + Write>(
     writer: &mut W,
     mut payload_type: usize,
     mut payload_size: usize,
@@ -109,6 +124,15 @@ impl<W: Write + ?Sized> WebvttWrite for RbspWriter<W> {
         )
     }
 
+    fn write_serialized_webvtt_header(
+        &mut self,
+        header: &SerializedWebvttHeader,
+    ) -> std::io::Result<()> {
+        write_serialized_webvtt_header(self, header, |writer, size| {
+            write_sei_header(writer, USER_DATA_UNREGISTERED, size)
+        })
+    }
+
     fn write_webvtt_payload(
         &mut self,
         track_index: u8,
@@ -128,3 +152,104 @@ impl<W: Write + ?Sized> WebvttWrite for RbspWriter<W> {
         )
     }
 }
+
+impl<W: Write + ?Sized> SeiWrite for RbspWriter<W> {
+    fn write_sei_message(&mut self, message: &SeiMessage) -> std::io::Result<()> {
+        let payload_type = message
+            .payload_type()
+            .ok_or_else(|| unsupported_message_error(message))?;
+        sei::write_sei_message(self, message, |writer, size| {
+            write_sei_header(writer, payload_type, size)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RbspWriter;
+    use byteorder::WriteBytesExt;
+    use std::{collections::VecDeque, io::Write};
+
+    /// The original byte at a time implementation, kept as a reference
+    struct ReferenceRbspWriter {
+        last_written: VecDeque<u8>,
+        inner: Vec<u8>,
+    }
+
+    impl Write for ReferenceRbspWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            let mut written = 0;
+            for &byte in buf {
+                let mut last_written_iter = self.last_written.iter();
+                if last_written_iter.next() == Some(&0)
+                    && last_written_iter.next() == Some(&0)
+                    && (byte == 0 || byte == 1 || byte == 2 || byte == 3)
+                {
+                    self.inner.write_u8(3)?;
+                    self.last_written.clear();
+                }
+                self.inner.write_u8(byte)?;
+                written += 1;
+                self.last_written.push_back(byte);
+                if self.last_written.len() > 2 {
+                    self.last_written.pop_front();
+                }
+            }
+            Ok(written)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    struct XorShift(u64);
+
+    impl XorShift {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        /// Mostly 0-3 so that emulation prevention is exercised a lot
+        fn next_byte(&mut self) -> u8 {
+            let value = self.next();
+            if value.is_multiple_of(4) {
+                (value >> 8) as u8
+            } else {
+                (value >> 8) as u8 & 0b11
+            }
+        }
+    }
+
+    #[test]
+    fn rbsp_writer_matches_reference() {
+        let mut rng = XorShift(0x2545_f491_4f6c_dd1d);
+        for _ in 0..2000 {
+            let length = usize::try_from(rng.next() % 300).unwrap();
+            let data: Vec<u8> = (0..length).map(|_| rng.next_byte()).collect();
+
+            let mut reference = ReferenceRbspWriter {
+                last_written: VecDeque::new(),
+                inner: vec![],
+            };
+            reference.write_all(&data).unwrap();
+
+            // split the input across several writes to check that state carries over
+            let mut writer = RbspWriter::new(vec![]);
+            let mut remaining = &data[..];
+            while !remaining.is_empty() {
+                let split = usize::try_from(rng.next() % 8)
+                    .unwrap()
+                    .min(remaining.len());
+                let (chunk, rest) = remaining.split_at(split);
+                writer.write_all(chunk).unwrap();
+                remaining = rest;
+            }
+
+            assert!(writer.inner == reference.inner, "input: {data:02x?}");
+        }
+    }
+}
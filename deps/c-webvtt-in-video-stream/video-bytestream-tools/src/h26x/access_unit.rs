@@ -0,0 +1,263 @@
+use memchr::memmem;
+use thiserror::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VideoCodec {
+    H264,
+    H265,
+    H266,
+}
+
+impl VideoCodec {
+    fn nal_header_size(self) -> usize {
+        match self {
+            VideoCodec::H264 => 1,
+            VideoCodec::H265 | VideoCodec::H266 => 2,
+        }
+    }
+
+    fn is_vcl(self, nal_header: &[u8]) -> bool {
+        match self {
+            VideoCodec::H264 => matches!(nal_header[0] & 0x1f, 1..=5),
+            VideoCodec::H265 => (nal_header[0] >> 1) & 0x3f <= 31,
+            VideoCodec::H266 => nal_header[1] >> 3 <= 11,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NalUnitFraming {
+    AnnexB,
+    LengthPrefixed { length_size: usize },
+}
+
+#[derive(Error, Debug)]
+pub enum AccessUnitError {
+    #[error("Annex B access unit doesn't start with a start code")]
+    MissingStartCode,
+    #[error("NAL unit at offset {offset} is truncated")]
+    TruncatedNalUnit { offset: usize },
+    #[error("NAL unit length size of {0} is unsupported")]
+    InvalidLengthSize(usize),
+}
+
+/// Returns the offset of the first VCL NAL unit including its start code or length prefix,
+/// this is where SEI NAL units go so they follow AUD and parameter sets. Access units without
+/// VCL NAL units return their length.
+pub fn sei_insertion_offset(
+    access_unit: &[u8],
+    framing: NalUnitFraming,
+    codec: VideoCodec,
+) -> Result<usize, AccessUnitError> {
+    match framing {
+        NalUnitFraming::AnnexB => annex_b_sei_insertion_offset(access_unit, codec),
+        NalUnitFraming::LengthPrefixed { length_size } => {
+            length_prefixed_sei_insertion_offset(access_unit, length_size, codec)
+        }
+    }
+}
+
+fn annex_b_sei_insertion_offset(
+    access_unit: &[u8],
+    codec: VideoCodec,
+) -> Result<usize, AccessUnitError> {
+    let mut start_codes = memmem::find_iter(access_unit, &[0, 0, 1]).peekable();
+    let Some(&first_start_code) = start_codes.peek() else {
+        return Err(AccessUnitError::MissingStartCode);
+    };
+    if access_unit[..first_start_code]
+        .iter()
+        .any(|&byte| byte != 0)
+    {
+        return Err(AccessUnitError::MissingStartCode);
+    }
+    for start_code in start_codes {
+        let nal_header_start = start_code + 3;
+        let Some(nal_header) =
+            access_unit.get(nal_header_start..nal_header_start + codec.nal_header_size())
+        else {
+            return Err(AccessUnitError::TruncatedNalUnit { offset: start_code });
+        };
+        if codec.is_vcl(nal_header) {
+            // zero_byte and trailing_zero_8bits of the previous NAL unit stay in front
+            let leading_zeros = access_unit[..start_code]
+                .iter()
+                .rev()
+                .take_while(|&&byte| byte == 0)
+                .count();
+            return Ok(start_code - leading_zeros);
+        }
+    }
+    Ok(access_unit.len())
+}
+
+fn length_prefixed_sei_insertion_offset(
+    access_unit: &[u8],
+    length_size: usize,
+    codec: VideoCodec,
+) -> Result<usize, AccessUnitError> {
+    if !matches!(length_size, 1 | 2 | 4) {
+        return Err(AccessUnitError::InvalidLengthSize(length_size));
+    }
+    let mut offset = 0;
+    while offset < access_unit.len() {
+        let Some(length) = access_unit.get(offset..offset + length_size) else {
+            return Err(AccessUnitError::TruncatedNalUnit { offset });
+        };
+        let length = length
+            .iter()
+            .fold(0, |length, &byte| length << 8 | usize::from(byte));
+        let nal_unit_start = offset + length_size;
+        let nal_unit_end = nal_unit_start + length;
+        if length < codec.nal_header_size() || nal_unit_end > access_unit.len() {
+            return Err(AccessUnitError::TruncatedNalUnit { offset });
+        }
+        if codec.is_vcl(&access_unit[nal_unit_start..nal_unit_end]) {
+            return Ok(offset);
+        }
+        offset = nal_unit_end;
+    }
+    Ok(access_unit.len())
+}
+
+/// Writes `access_unit` with `sei` inserted at `sei_insertion_offset`, `sei` has to use the
+/// same framing as the access unit.
+pub fn insert_sei(
+    access_unit: &[u8],
+    framing: NalUnitFraming,
+    codec: VideoCodec,
+    sei: &[u8],
+    output: &mut Vec<u8>,
+) -> Result<(), AccessUnitError> {
+    let offset = sei_insertion_offset(access_unit, framing, codec)?;
+    output.reserve(access_unit.len() + sei.len());
+    output.extend_from_slice(&access_unit[..offset]);
+    output.extend_from_slice(sei);
+    output.extend_from_slice(&access_unit[offset..]);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{insert_sei, sei_insertion_offset, AccessUnitError, NalUnitFraming, VideoCodec};
+
+    const H264_AUD: &[u8] = &[0x09, 0xf0];
+    const H264_SPS: &[u8] = &[0x67, 0x42, 0x00, 0x1e];
+    const H264_PPS: &[u8] = &[0x68, 0xce, 0x38, 0x80];
+    const H264_IDR: &[u8] = &[0x65, 0x88, 0x84, 0x00, 0x33];
+    const H264_SLICE: &[u8] = &[0x41, 0x9a, 0x02];
+    const H265_AUD: &[u8] = &[0x46, 0x01, 0x50];
+    const H265_VPS: &[u8] = &[0x40, 0x01, 0x0c];
+    const H265_IDR: &[u8] = &[0x26, 0x01, 0xaf];
+    const H266_AUD: &[u8] = &[0x00, 0xa1, 0x10];
+    const H266_SPS: &[u8] = &[0x00, 0x79, 0x80];
+    const H266_IDR: &[u8] = &[0x00, 0x41, 0xa2];
+
+    fn annex_b(nal_units: &[&[u8]]) -> Vec<u8> {
+        let mut access_unit = vec![0];
+        for nal_unit in nal_units {
+            access_unit.extend_from_slice(&[0, 0, 1]);
+            access_unit.extend_from_slice(nal_unit);
+        }
+        access_unit
+    }
+
+    fn length_prefixed(nal_units: &[&[u8]]) -> Vec<u8> {
+        let mut access_unit = vec![];
+        for nal_unit in nal_units {
+            access_unit.extend_from_slice(&u32::try_from(nal_unit.len()).unwrap().to_be_bytes());
+            access_unit.extend_from_slice(nal_unit);
+        }
+        access_unit
+    }
+
+    #[test]
+    fn annex_b_sei_goes_before_first_vcl() {
+        let access_unit = annex_b(&[H264_AUD, H264_SPS, H264_PPS, H264_IDR, H264_SLICE]);
+        let offset =
+            sei_insertion_offset(&access_unit, NalUnitFraming::AnnexB, VideoCodec::H264).unwrap();
+        assert!(offset == annex_b(&[H264_AUD, H264_SPS, H264_PPS]).len());
+
+        let sei = [0, 0, 0, 1, 0x06, 0x05, 0x00, 0x80];
+        let mut output = vec![];
+        insert_sei(
+            &access_unit,
+            NalUnitFraming::AnnexB,
+            VideoCodec::H264,
+            &sei,
+            &mut output,
+        )
+        .unwrap();
+        assert!(output[..offset] == access_unit[..offset]);
+        assert!(output[offset..offset + sei.len()] == sei);
+        assert!(output[offset + sei.len()..] == access_unit[offset..]);
+    }
+
+    #[test]
+    fn annex_b_four_byte_start_code_stays_together() {
+        let mut access_unit = annex_b(&[H264_AUD]);
+        access_unit.extend_from_slice(&[0, 0, 0, 1]);
+        access_unit.extend_from_slice(H264_SLICE);
+        let offset =
+            sei_insertion_offset(&access_unit, NalUnitFraming::AnnexB, VideoCodec::H264).unwrap();
+        assert!(offset == annex_b(&[H264_AUD]).len());
+    }
+
+    #[test]
+    fn annex_b_h265_vcl_detection() {
+        let access_unit = annex_b(&[H265_AUD, H265_VPS, H265_IDR]);
+        let offset =
+            sei_insertion_offset(&access_unit, NalUnitFraming::AnnexB, VideoCodec::H265).unwrap();
+        assert!(offset == annex_b(&[H265_AUD, H265_VPS]).len());
+    }
+
+    #[test]
+    fn annex_b_h266_vcl_detection() {
+        let access_unit = annex_b(&[H266_AUD, H266_SPS, H266_IDR]);
+        let offset =
+            sei_insertion_offset(&access_unit, NalUnitFraming::AnnexB, VideoCodec::H266).unwrap();
+        assert!(offset == annex_b(&[H266_AUD, H266_SPS]).len());
+    }
+
+    #[test]
+    fn length_prefixed_sei_goes_before_first_vcl() {
+        let access_unit = length_prefixed(&[H264_SPS, H264_PPS, H264_IDR]);
+        let framing = NalUnitFraming::LengthPrefixed { length_size: 4 };
+        let offset = sei_insertion_offset(&access_unit, framing, VideoCodec::H264).unwrap();
+        assert!(offset == length_prefixed(&[H264_SPS, H264_PPS]).len());
+    }
+
+    #[test]
+    fn access_unit_without_vcl_appends() {
+        let access_unit = annex_b(&[H264_SPS, H264_PPS]);
+        let offset =
+            sei_insertion_offset(&access_unit, NalUnitFraming::AnnexB, VideoCodec::H264).unwrap();
+        assert!(offset == access_unit.len());
+    }
+
+    #[test]
+    fn malformed_access_units() {
+        assert!(matches!(
+            sei_insertion_offset(H264_IDR, NalUnitFraming::AnnexB, VideoCodec::H264),
+            Err(AccessUnitError::MissingStartCode)
+        ));
+        let mut access_unit = length_prefixed(&[H264_SPS, H264_IDR]);
+        access_unit.pop();
+        assert!(matches!(
+            sei_insertion_offset(
+                &access_unit,
+                NalUnitFraming::LengthPrefixed { length_size: 4 },
+                VideoCodec::H264
+            ),
+            Err(AccessUnitError::TruncatedNalUnit { offset: 8 })
+        ));
+        assert!(matches!(
+            sei_insertion_offset(
+                &access_unit,
+                NalUnitFraming::LengthPrefixed { length_size: 3 },
+                VideoCodec::H264
+            ),
+            Err(AccessUnitError::InvalidLengthSize(3))
+        ));
+    }
+}
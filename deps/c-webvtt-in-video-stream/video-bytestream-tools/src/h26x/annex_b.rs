@@ -1,6 +1,7 @@
 use crate::{
     h26x::{NalUnitWriter, RbspWriter, Result},
-    webvtt::{WebvttTrack, WebvttWrite},
+    sei::{SeiMessage, SeiWrite},
+    webvtt::{SerializedWebvttHeader, WebvttTrack, WebvttWrite},
 };
 use byteorder::WriteBytesExt;
 use std::{io::Write, time::Duration};
@@ -73,6 +74,13 @@ impl<W: Write + ?Sized> WebvttWrite for AnnexBRbspWriter<W> {
             .write_webvtt_header(max_latency_to_video, send_frequency_hz, subtitle_tracks)
     }
 
+    fn write_serialized_webvtt_header(
+        &mut self,
+        header: &SerializedWebvttHeader,
+    ) -> std::io::Result<()> {
+        self.inner.write_serialized_webvtt_header(header)
+    }
+
     fn write_webvtt_payload(
         &mut self,
         track_index: u8,
@@ -90,3 +98,9 @@ impl<W: Write + ?Sized> WebvttWrite for AnnexBRbspWriter<W> {
         )
     }
 }
+
+impl<W: Write + ?Sized> SeiWrite for AnnexBRbspWriter<W> {
+    fn write_sei_message(&mut self, message: &SeiMessage) -> std::io::Result<()> {
+        self.inner.write_sei_message(message)
+    }
+}
@@ -0,0 +1,147 @@
+use crate::h26x::Result;
+use byteorder::{BigEndian, WriteBytesExt};
+use std::io::Write;
+use thiserror::Error;
+
+// indexed by length_size - 1
+const MAX_LENGTH: [usize; 4] = [0xff, 0xff_ff, 0, 0xff_ff_ff_ff];
+
+pub(crate) struct LengthPrefixedWriter<W: ?Sized + Write> {
+    length_size: usize,
+    inner: W,
+}
+
+#[derive(Error, Debug)]
+#[error("NAL unit length size of {0} is unsupported")]
+pub struct InvalidLengthError(pub usize);
+
+#[derive(Error, Debug)]
+#[error("Tried to write {required} bytes which exceeds the max size of {max}")]
+pub struct MaxNalUnitSizeExceededError {
+    max: usize,
+    required: usize,
+}
+
+impl<W: Write> LengthPrefixedWriter<W> {
+    pub fn new(length_size: usize, inner: W) -> Result<Self, InvalidLengthError> {
+        match length_size {
+            1 | 2 | 4 => Ok(Self { length_size, inner }),
+            _ => Err(InvalidLengthError(length_size)),
+        }
+    }
+}
+
+/// Buffers a NAL unit until its length is known
+pub struct LengthPrefixedBuffer<W: ?Sized + Write> {
+    buffer: Vec<u8>,
+    writer: LengthPrefixedWriter<W>,
+}
+
+impl<W: Write> LengthPrefixedBuffer<W> {
+    pub(crate) fn new(writer: LengthPrefixedWriter<W>) -> Self {
+        Self {
+            buffer: vec![],
+            writer,
+        }
+    }
+
+    pub(crate) fn finish(mut self) -> Result<LengthPrefixedWriter<W>> {
+        let length = self.buffer.len();
+        let inner = &mut self.writer.inner;
+        match self.writer.length_size {
+            1 => inner.write_u8(length.try_into().unwrap())?,
+            2 => inner.write_u16::<BigEndian>(length.try_into().unwrap())?,
+            4 => inner.write_u32::<BigEndian>(length.try_into().unwrap())?,
+            _ => unreachable!(),
+        }
+        inner.write_all(&self.buffer)?;
+        Ok(self.writer)
+    }
+}
+
+impl<W: ?Sized + Write> Write for LengthPrefixedBuffer<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let length = self.buffer.len();
+        let additional_length = buf.len();
+        let max = MAX_LENGTH[self.writer.length_size - 1];
+        if length + additional_length > max {
+            Err(std::io::Error::other(MaxNalUnitSizeExceededError {
+                max,
+                required: length + additional_length,
+            }))
+        } else {
+            self.buffer.write(buf)
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum DecoderConfigurationRecordError {
+    #[error("Decoder configuration record is truncated")]
+    Truncated,
+    #[error("Decoder configuration record version {0} is unsupported")]
+    UnsupportedVersion(u8),
+    #[error("NAL unit length size of {0} is unsupported")]
+    InvalidLengthSize(usize),
+}
+
+/// Reads the header fields of a decoder configuration record one at a time
+pub(crate) struct DecoderConfigurationRecordReader<'a>(&'a [u8]);
+
+impl<'a> DecoderConfigurationRecordReader<'a> {
+    pub(crate) fn new(record: &'a [u8]) -> Result<Self, DecoderConfigurationRecordError> {
+        let mut reader = Self(record);
+        match reader.read_u8()? {
+            1 => Ok(reader),
+            version => Err(DecoderConfigurationRecordError::UnsupportedVersion(version)),
+        }
+    }
+
+    pub(crate) fn skip(
+        &mut self,
+        length: usize,
+    ) -> Result<&'a [u8], DecoderConfigurationRecordError> {
+        if self.0.len() < length {
+            return Err(DecoderConfigurationRecordError::Truncated);
+        }
+        let (skipped, rest) = self.0.split_at(length);
+        self.0 = rest;
+        Ok(skipped)
+    }
+
+    pub(crate) fn read_u8(&mut self) -> Result<u8, DecoderConfigurationRecordError> {
+        Ok(self.skip(1)?[0])
+    }
+
+    pub(crate) fn read_u16(&mut self) -> Result<u16, DecoderConfigurationRecordError> {
+        let bytes = self.skip(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    /// Reads the 2 bit lengthSizeMinusOne field in the low bits of the next byte
+    pub(crate) fn read_length_size(&mut self) -> Result<usize, DecoderConfigurationRecordError> {
+        match usize::from(self.read_u8()? & 0b11) + 1 {
+            length_size @ (1 | 2 | 4) => Ok(length_size),
+            length_size => Err(DecoderConfigurationRecordError::InvalidLengthSize(
+                length_size,
+            )),
+        }
+    }
+
+    /// Reads `count` NAL units, each prefixed with a 16 bit length
+    pub(crate) fn read_nal_units(
+        &mut self,
+        count: usize,
+    ) -> Result<Vec<&'a [u8]>, DecoderConfigurationRecordError> {
+        (0..count)
+            .map(|_| {
+                let length = self.read_u16()?;
+                self.skip(length.into())
+            })
+            .collect()
+    }
+}
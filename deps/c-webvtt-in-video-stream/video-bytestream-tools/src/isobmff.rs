@@ -0,0 +1,365 @@
+//! Fragmented ISOBMFF output for WebVTT tracks as specified by ISO/IEC 14496-30, for CMAF and
+//! DASH packaging.
+//!
+//! A wvtt sample covers a time span with a constant set of active cues, each cue is a `vttc`
+//! box. Spans without cues are covered by a sample with a single `vtte` box.
+
+/// Writes a box whose size is filled in once `write_content` returns
+fn write_box(output: &mut Vec<u8>, box_type: &[u8; 4], write_content: impl FnOnce(&mut Vec<u8>)) {
+    let start = output.len();
+    output.extend_from_slice(&[0; 4]);
+    output.extend_from_slice(box_type);
+    write_content(output);
+    let size = u32::try_from(output.len() - start).unwrap();
+    output[start..start + 4].copy_from_slice(&size.to_be_bytes());
+}
+
+fn write_full_box(
+    output: &mut Vec<u8>,
+    box_type: &[u8; 4],
+    version: u8,
+    flags: u32,
+    write_content: impl FnOnce(&mut Vec<u8>),
+) {
+    write_box(output, box_type, |output| {
+        output.extend_from_slice(&(u32::from(version) << 24 | flags).to_be_bytes());
+        write_content(output);
+    });
+}
+
+fn write_u16(output: &mut Vec<u8>, value: u16) {
+    output.extend_from_slice(&value.to_be_bytes());
+}
+
+fn write_u32(output: &mut Vec<u8>, value: u32) {
+    output.extend_from_slice(&value.to_be_bytes());
+}
+
+fn write_null_terminated(output: &mut Vec<u8>, string: &str) {
+    output.extend_from_slice(string.as_bytes());
+    output.push(0);
+}
+
+const UNITY_MATRIX: [u32; 9] = [0x0001_0000, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000];
+
+fn write_matrix(output: &mut Vec<u8>) {
+    for value in UNITY_MATRIX {
+        write_u32(output, value);
+    }
+}
+
+/// Packs an ISO 639-2/T code for `mdhd`, anything else becomes `und`
+fn packed_language(language: &str) -> u16 {
+    let language = if language.len() == 3 && language.bytes().all(|c| c.is_ascii_lowercase()) {
+        language
+    } else {
+        "und"
+    };
+    language
+        .bytes()
+        .fold(0, |packed, c| packed << 5 | u16::from(c - 0x60))
+}
+
+pub struct WvttTrackConfig<'a> {
+    pub track_id: u32,
+    pub timescale: u32,
+    /// BCP 47 language tag, written to `elng` and, if it's an ISO 639-2/T code, `mdhd`
+    pub language: &'a str,
+    /// Written as the `hdlr` name
+    pub name: &'a str,
+}
+
+/// Writes `ftyp` and a `moov` with a single wvtt track and no samples
+pub fn write_wvtt_init_segment(output: &mut Vec<u8>, config: &WvttTrackConfig) {
+    write_box(output, b"ftyp", |output| {
+        output.extend_from_slice(b"iso6");
+        write_u32(output, 0);
+        output.extend_from_slice(b"iso6cmfcdash");
+    });
+    write_box(output, b"moov", |output| {
+        write_full_box(output, b"mvhd", 0, 0, |output| {
+            // creation_time, modification_time
+            output.extend_from_slice(&[0; 8]);
+            write_u32(output, config.timescale);
+            // duration
+            write_u32(output, 0);
+            // rate, volume, reserved
+            write_u32(output, 0x0001_0000);
+            write_u16(output, 0x0100);
+            output.extend_from_slice(&[0; 10]);
+            write_matrix(output);
+            // pre_defined
+            output.extend_from_slice(&[0; 24]);
+            write_u32(output, config.track_id + 1);
+        });
+        write_box(output, b"trak", |output| {
+            // track_enabled | track_in_movie
+            write_full_box(output, b"tkhd", 0, 0x3, |output| {
+                output.extend_from_slice(&[0; 8]);
+                write_u32(output, config.track_id);
+                // reserved, duration, reserved
+                output.extend_from_slice(&[0; 4 + 4 + 8]);
+                // layer, alternate_group, volume, reserved
+                output.extend_from_slice(&[0; 8]);
+                write_matrix(output);
+                // width, height
+                output.extend_from_slice(&[0; 8]);
+            });
+            write_box(output, b"mdia", |output| {
+                write_full_box(output, b"mdhd", 0, 0, |output| {
+                    output.extend_from_slice(&[0; 8]);
+                    write_u32(output, config.timescale);
+                    write_u32(output, 0);
+                    write_u16(output, packed_language(config.language));
+                    write_u16(output, 0);
+                });
+                write_full_box(output, b"hdlr", 0, 0, |output| {
+                    write_u32(output, 0);
+                    output.extend_from_slice(b"text");
+                    output.extend_from_slice(&[0; 12]);
+                    write_null_terminated(output, config.name);
+                });
+                if !config.language.is_empty() {
+                    write_full_box(output, b"elng", 0, 0, |output| {
+                        write_null_terminated(output, config.language);
+                    });
+                }
+                write_box(output, b"minf", |output| {
+                    write_full_box(output, b"nmhd", 0, 0, |_| {});
+                    write_box(output, b"dinf", |output| {
+                        write_full_box(output, b"dref", 0, 0, |output| {
+                            write_u32(output, 1);
+                            // media data is in the same file
+                            write_full_box(output, b"url ", 0, 0x1, |_| {});
+                        });
+                    });
+                    write_stbl(output);
+                });
+            });
+        });
+        write_box(output, b"mvex", |output| {
+            write_full_box(output, b"trex", 0, 0, |output| {
+                write_u32(output, config.track_id);
+                // default_sample_description_index, duration, size, flags
+                write_u32(output, 1);
+                output.extend_from_slice(&[0; 12]);
+            });
+        });
+    });
+}
+
+fn write_stbl(output: &mut Vec<u8>) {
+    write_box(output, b"stbl", |output| {
+        write_full_box(output, b"stsd", 0, 0, |output| {
+            write_u32(output, 1);
+            write_box(output, b"wvtt", |output| {
+                // reserved, data_reference_index
+                output.extend_from_slice(&[0; 6]);
+                write_u16(output, 1);
+                write_box(output, b"vttC", |output| {
+                    output.extend_from_slice(b"WEBVTT");
+                });
+            });
+        });
+        // empty sample tables, the samples are in the fragments
+        for box_type in [b"stts", b"stsc", b"stco"] {
+            write_full_box(output, box_type, 0, 0, |output| write_u32(output, 0));
+        }
+        write_full_box(output, b"stsz", 0, 0, |output| {
+            output.extend_from_slice(&[0; 8]);
+        });
+    });
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct WvttCue<'a> {
+    /// Written as `iden`
+    pub id: Option<&'a str>,
+    /// Written as `sttg`
+    pub settings: Option<&'a str>,
+    /// Written as `payl`
+    pub payload: &'a str,
+}
+
+/// `duration` is in the timescale of the track, a sample without cues is written as `vtte`
+#[derive(Debug, Clone)]
+pub struct WvttSample<'a> {
+    pub duration: u32,
+    pub cues: Vec<WvttCue<'a>>,
+}
+
+fn write_sample(output: &mut Vec<u8>, sample: &WvttSample) {
+    if sample.cues.is_empty() {
+        write_box(output, b"vtte", |_| {});
+    }
+    for cue in &sample.cues {
+        write_box(output, b"vttc", |output| {
+            if let Some(id) = cue.id {
+                write_box(output, b"iden", |output| {
+                    output.extend_from_slice(id.as_bytes())
+                });
+            }
+            if let Some(settings) = cue.settings {
+                write_box(output, b"sttg", |output| {
+                    output.extend_from_slice(settings.as_bytes())
+                });
+            }
+            write_box(output, b"payl", |output| {
+                output.extend_from_slice(cue.payload.as_bytes())
+            });
+        });
+    }
+}
+
+/// Writes a `moof` and `mdat` with `samples` starting at `base_media_decode_time`
+pub fn write_wvtt_fragment(
+    output: &mut Vec<u8>,
+    track_id: u32,
+    sequence_number: u32,
+    base_media_decode_time: u64,
+    samples: &[WvttSample],
+) {
+    let mut mdat = vec![];
+    let sample_sizes: Vec<u32> = samples
+        .iter()
+        .map(|sample| {
+            let start = mdat.len();
+            write_sample(&mut mdat, sample);
+            u32::try_from(mdat.len() - start).unwrap()
+        })
+        .collect();
+
+    let moof_start = output.len();
+    let mut data_offset_position = 0;
+    write_box(output, b"moof", |output| {
+        write_full_box(output, b"mfhd", 0, 0, |output| {
+            write_u32(output, sequence_number)
+        });
+        write_box(output, b"traf", |output| {
+            // default-base-is-moof
+            write_full_box(output, b"tfhd", 0, 0x02_0000, |output| {
+                write_u32(output, track_id);
+            });
+            write_full_box(output, b"tfdt", 1, 0, |output| {
+                output.extend_from_slice(&base_media_decode_time.to_be_bytes());
+            });
+            // data-offset-present | sample-duration-present | sample-size-present
+            write_full_box(output, b"trun", 0, 0x00_0301, |output| {
+                write_u32(output, u32::try_from(samples.len()).unwrap());
+                data_offset_position = output.len();
+                write_u32(output, 0);
+                for (sample, size) in samples.iter().zip(&sample_sizes) {
+                    write_u32(output, sample.duration);
+                    write_u32(output, *size);
+                }
+            });
+        });
+    });
+    // the samples start right after the mdat header
+    let data_offset = u32::try_from(output.len() - moof_start + 8).unwrap();
+    output[data_offset_position..data_offset_position + 4]
+        .copy_from_slice(&data_offset.to_be_bytes());
+    write_box(output, b"mdat", |output| output.extend_from_slice(&mdat));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        write_wvtt_fragment, write_wvtt_init_segment, WvttCue, WvttSample, WvttTrackConfig,
+    };
+
+    /// Splits `data` into `(type, content)` of the boxes it contains
+    fn boxes(mut data: &[u8]) -> Vec<(&[u8], &[u8])> {
+        let mut boxes = vec![];
+        while !data.is_empty() {
+            let size = usize::try_from(u32::from_be_bytes(data[..4].try_into().unwrap())).unwrap();
+            boxes.push((&data[4..8], &data[8..size]));
+            data = &data[size..];
+        }
+        boxes
+    }
+
+    fn find<'a>(data: &'a [u8], box_type: &[u8]) -> &'a [u8] {
+        boxes(data)
+            .into_iter()
+            .find(|(t, _)| *t == box_type)
+            .unwrap()
+            .1
+    }
+
+    #[test]
+    fn init_segment() {
+        let mut output = vec![];
+        write_wvtt_init_segment(
+            &mut output,
+            &WvttTrackConfig {
+                track_id: 2,
+                timescale: 1000,
+                language: "en",
+                name: "English",
+            },
+        );
+        let types: Vec<_> = boxes(&output).into_iter().map(|(t, _)| t).collect();
+        assert!(types == [b"ftyp", b"moov"]);
+        let moov = find(&output, b"moov");
+        let mdia = find(find(moov, b"trak"), b"mdia");
+        // "und" packed
+        assert!(find(mdia, b"mdhd")[20..22] == [0x55, 0xc4]);
+        assert!(find(mdia, b"elng") == b"\0\0\0\0en\0");
+        let stsd = find(find(find(mdia, b"minf"), b"stbl"), b"stsd");
+        let wvtt = find(&stsd[8..], b"wvtt");
+        assert!(find(&wvtt[8..], b"vttC") == b"WEBVTT");
+        assert!(find(find(moov, b"mvex"), b"trex")[4..8] == 2u32.to_be_bytes());
+    }
+
+    #[test]
+    fn fragment_samples() {
+        let mut output = vec![0xaa];
+        let cue = WvttCue {
+            id: Some("1"),
+            settings: Some("line:0"),
+            payload: "Hello",
+        };
+        write_wvtt_fragment(
+            &mut output,
+            1,
+            7,
+            2000,
+            &[
+                WvttSample {
+                    duration: 500,
+                    cues: vec![],
+                },
+                WvttSample {
+                    duration: 1500,
+                    cues: vec![cue],
+                },
+            ],
+        );
+        let fragment = &output[1..];
+        let moof = find(fragment, b"moof");
+        assert!(find(moof, b"mfhd") == [0, 0, 0, 0, 0, 0, 0, 7]);
+        let traf = find(moof, b"traf");
+        assert!(find(traf, b"tfdt")[4..] == 2000u64.to_be_bytes());
+        let trun = find(traf, b"trun");
+        assert!(trun[4..8] == 2u32.to_be_bytes());
+        let data_offset = usize::try_from(u32::from_be_bytes(trun[8..12].try_into().unwrap()));
+        let vtte_size = u32::from_be_bytes(trun[16..20].try_into().unwrap());
+        let vttc_size = u32::from_be_bytes(trun[24..28].try_into().unwrap());
+        assert!(vtte_size == 8);
+
+        let samples = &fragment[data_offset.unwrap()..];
+        assert!(samples.len() == usize::try_from(vtte_size + vttc_size).unwrap());
+        assert!(
+            boxes(samples)
+                .into_iter()
+                .map(|(t, _)| t)
+                .collect::<Vec<_>>()
+                == [b"vtte", b"vttc"]
+        );
+        let vttc = find(samples, b"vttc");
+        assert!(find(vttc, b"iden") == b"1");
+        assert!(find(vttc, b"sttg") == b"line:0");
+        assert!(find(vttc, b"payl") == b"Hello");
+    }
+}
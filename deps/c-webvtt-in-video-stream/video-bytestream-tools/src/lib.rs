@@ -1,5 +1,12 @@
 pub mod av1;
+pub mod cea708;
 pub mod h264;
 pub mod h265;
+pub mod h266;
 pub mod h26x;
+pub mod isobmff;
+pub mod mpeg_ts;
+pub mod sei;
+pub mod t35;
+pub mod timecode;
 pub mod webvtt;
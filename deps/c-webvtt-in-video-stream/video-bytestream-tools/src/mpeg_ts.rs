@@ -0,0 +1,688 @@
+//! Remuxing of MPEG transport streams with SEI NAL units inserted into every H.264, H.265 or
+//! H.266 access unit, e.g. to add WebVTT to a `.ts` recording.
+//!
+//! The video PID is the first H.26x stream announced in a PMT. Every video PES packet is
+//! reassembled, passed to a callback that writes the SEI NAL units for it, and repacketized in
+//! place: packets of other PIDs keep their position, adaptation fields (and with them the PCR)
+//! stay on their packets, and the packets for the bytes the PES grew by follow its last packet.
+//! `PES_packet_length` and the continuity counters of the video PID are rewritten accordingly.
+//!
+//! Packets with the `transport_error_indicator` set aren't reassembled. On the video PID they're
+//! renumbered like the other video packets, unless the PES they're in gets SEI inserted: their
+//! payload has no place in the rewritten PES, so they're dropped.
+
+use crate::h26x::access_unit::{insert_sei, NalUnitFraming, VideoCodec};
+use pes::{
+    adaptation_field_content, payload_capacity, update_packet_length, write_packet, PesHeader,
+};
+use psi::{parse_pat, parse_pmt, SectionBuffer, PAT_PID};
+use std::time::Duration;
+use thiserror::Error;
+
+pub(crate) mod pes;
+pub mod psi;
+
+pub const TS_PACKET_SIZE: usize = 188;
+pub(crate) const TS_PACKET_PAYLOAD_SIZE: usize = TS_PACKET_SIZE - 4;
+const SYNC_BYTE: u8 = 0x47;
+
+#[derive(Error, Debug)]
+pub enum TsError {
+    #[error("TS packet at offset {0} doesn't start with a sync byte")]
+    LostSync(u64),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// A complete access unit of the video PID
+#[derive(Debug, Clone, Copy)]
+pub struct VideoAccessUnit<'a> {
+    pub codec: VideoCodec,
+    /// 90 kHz timestamps, see [`timestamp_to_duration`]
+    pub pts: Option<u64>,
+    pub dts: Option<u64>,
+    /// `random_access_indicator` of the first TS packet, muxers set it on keyframes
+    pub random_access: bool,
+    /// Annex B byte stream
+    pub data: &'a [u8],
+}
+
+/// Converts a 90 kHz PES timestamp, which wraps around after 2^33 ticks (about 26.5 hours)
+pub fn timestamp_to_duration(timestamp: u64) -> Duration {
+    Duration::from_nanos(timestamp * 100_000 / 9)
+}
+
+struct Packet<'a> {
+    pid: u16,
+    payload_unit_start: bool,
+    transport_error: bool,
+    continuity_counter: u8,
+    /// Bytes after `adaptation_field_length`
+    adaptation_field: Option<&'a [u8]>,
+    payload: &'a [u8],
+}
+
+impl<'a> Packet<'a> {
+    fn parse(packet: &'a [u8; TS_PACKET_SIZE]) -> Self {
+        let adaptation_field_control = packet[3] >> 4 & 0b11;
+        let (adaptation_field, payload_start) = if adaptation_field_control & 0b10 != 0 {
+            let adaptation_field_end = (5 + usize::from(packet[4])).min(TS_PACKET_SIZE);
+            (Some(&packet[5..adaptation_field_end]), adaptation_field_end)
+        } else {
+            (None, 4)
+        };
+        Self {
+            pid: u16::from_be_bytes([packet[1], packet[2]]) & 0x1fff,
+            payload_unit_start: packet[1] & 0x40 != 0,
+            transport_error: packet[1] & 0x80 != 0,
+            continuity_counter: packet[3] & 0x0f,
+            adaptation_field,
+            payload: if adaptation_field_control & 0b01 != 0 {
+                &packet[payload_start..]
+            } else {
+                &[]
+            },
+        }
+    }
+}
+
+/// The continuity counter for the next packet of the video PID, the first one keeps its own
+fn next_continuity_counter(last: &mut Option<u8>, has_payload: bool, original: u8) -> u8 {
+    let continuity_counter = match *last {
+        None => original,
+        Some(last) if has_payload => (last + 1) & 0x0f,
+        Some(last) => last,
+    };
+    *last = Some(continuity_counter);
+    continuity_counter
+}
+
+/// A video PES that is being reassembled
+struct PendingPes {
+    /// Every packet since the start of the PES, of all PIDs
+    packets: Vec<[u8; TS_PACKET_SIZE]>,
+    data: Vec<u8>,
+}
+
+#[derive(Default)]
+pub struct TsRemuxer {
+    /// Bytes of a packet split across calls of `push`
+    partial_packet: Vec<u8>,
+    offset: u64,
+    pat: SectionBuffer,
+    pmts: Vec<(u16, SectionBuffer)>,
+    video: Option<(u16, VideoCodec)>,
+    video_continuity_counter: Option<u8>,
+    pending: Option<PendingPes>,
+    sei: Vec<u8>,
+    pes: Vec<u8>,
+}
+
+impl TsRemuxer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Remuxes `input` into `output`, packets may be split across calls. `write_sei` is called
+    /// for every video access unit and appends the Annex B SEI NAL units (including start
+    /// codes) to insert, if any.
+    ///
+    /// Output is delayed by up to one video PES, see [`Self::finish`].
+    pub fn push<F>(
+        &mut self,
+        mut input: &[u8],
+        output: &mut Vec<u8>,
+        write_sei: &mut F,
+    ) -> Result<(), TsError>
+    where
+        F: FnMut(&VideoAccessUnit, &mut Vec<u8>) -> std::io::Result<()>,
+    {
+        if !self.partial_packet.is_empty() {
+            let missing = TS_PACKET_SIZE - self.partial_packet.len();
+            let (rest, remaining) = input.split_at(missing.min(input.len()));
+            self.partial_packet.extend_from_slice(rest);
+            input = remaining;
+            if self.partial_packet.len() < TS_PACKET_SIZE {
+                return Ok(());
+            }
+            let packet: [u8; TS_PACKET_SIZE] = self.partial_packet[..].try_into().unwrap();
+            self.partial_packet.clear();
+            self.push_packet(&packet, output, write_sei)?;
+        }
+        let mut packets = input.chunks_exact(TS_PACKET_SIZE);
+        for packet in &mut packets {
+            self.push_packet(packet.try_into().unwrap(), output, write_sei)?;
+        }
+        self.partial_packet.extend_from_slice(packets.remainder());
+        Ok(())
+    }
+
+    /// Writes the last video PES, and a trailing partial packet unchanged.
+    pub fn finish<F>(mut self, output: &mut Vec<u8>, write_sei: &mut F) -> Result<(), TsError>
+    where
+        F: FnMut(&VideoAccessUnit, &mut Vec<u8>) -> std::io::Result<()>,
+    {
+        self.flush(output, write_sei)?;
+        output.extend_from_slice(&self.partial_packet);
+        Ok(())
+    }
+
+    fn push_packet<F>(
+        &mut self,
+        packet: &[u8; TS_PACKET_SIZE],
+        output: &mut Vec<u8>,
+        write_sei: &mut F,
+    ) -> Result<(), TsError>
+    where
+        F: FnMut(&VideoAccessUnit, &mut Vec<u8>) -> std::io::Result<()>,
+    {
+        if packet[0] != SYNC_BYTE {
+            return Err(TsError::LostSync(self.offset));
+        }
+        self.offset += TS_PACKET_SIZE as u64;
+        let parsed = Packet::parse(packet);
+        if parsed.transport_error {
+            self.write_or_queue(packet, output);
+            return Ok(());
+        }
+        if parsed.pid == PAT_PID {
+            if let Some(pmt_pids) = self
+                .pat
+                .push(parsed.payload_unit_start, parsed.payload)
+                .and_then(parse_pat)
+            {
+                self.pmts.retain(|(pid, _)| pmt_pids.contains(pid));
+                for pid in pmt_pids {
+                    if !self.pmts.iter().any(|(pmt_pid, _)| *pmt_pid == pid) {
+                        self.pmts.push((pid, SectionBuffer::default()));
+                    }
+                }
+            }
+        }
+        let video = self
+            .pmts
+            .iter_mut()
+            .find(|(pid, _)| *pid == parsed.pid)
+            .and_then(|(_, section)| section.push(parsed.payload_unit_start, parsed.payload))
+            .and_then(parse_pmt)
+            .flatten();
+        if video.is_some() && video != self.video {
+            self.flush(output, write_sei)?;
+            self.video = video;
+            self.video_continuity_counter = None;
+        }
+
+        let is_video = self.video.is_some_and(|(pid, _)| pid == parsed.pid);
+        if is_video && parsed.payload_unit_start {
+            self.flush(output, write_sei)?;
+            self.pending = Some(PendingPes {
+                packets: vec![],
+                data: vec![],
+            });
+        }
+        match &mut self.pending {
+            Some(pending) => {
+                pending.packets.push(*packet);
+                if is_video {
+                    pending.data.extend_from_slice(parsed.payload);
+                }
+            }
+            // the stream started in the middle of a PES
+            None if is_video => self.write_video_packet(packet, output),
+            None => output.extend_from_slice(packet),
+        }
+        Ok(())
+    }
+
+    fn write_or_queue(&mut self, packet: &[u8; TS_PACKET_SIZE], output: &mut Vec<u8>) {
+        let is_video = self
+            .video
+            .is_some_and(|(pid, _)| pid == Packet::parse(packet).pid);
+        match &mut self.pending {
+            Some(pending) => pending.packets.push(*packet),
+            None if is_video => self.write_video_packet(packet, output),
+            None => output.extend_from_slice(packet),
+        }
+    }
+
+    /// Writes a packet of the video PID unchanged apart from its continuity counter
+    fn write_video_packet(&mut self, packet: &[u8; TS_PACKET_SIZE], output: &mut Vec<u8>) {
+        let parsed = Packet::parse(packet);
+        let continuity_counter = next_continuity_counter(
+            &mut self.video_continuity_counter,
+            !parsed.payload.is_empty(),
+            parsed.continuity_counter,
+        );
+        output.extend_from_slice(&packet[..3]);
+        output.push(packet[3] & 0xf0 | continuity_counter);
+        output.extend_from_slice(&packet[4..]);
+    }
+
+    /// Inserts the SEI for the pending PES and writes it with the packets it was interleaved with
+    fn flush<F>(&mut self, output: &mut Vec<u8>, write_sei: &mut F) -> Result<(), TsError>
+    where
+        F: FnMut(&VideoAccessUnit, &mut Vec<u8>) -> std::io::Result<()>,
+    {
+        let Some(pending) = self.pending.take() else {
+            return Ok(());
+        };
+        let Some((video_pid, codec)) = self.video else {
+            output.extend(pending.packets.iter().flatten());
+            return Ok(());
+        };
+        self.sei.clear();
+        self.pes.clear();
+        if let Some(header) = PesHeader::parse(&pending.data) {
+            let end = match header.packet_length {
+                0 => pending.data.len(),
+                packet_length => 6 + usize::from(packet_length),
+            };
+            let access_unit = &pending.data[header.header_size..end];
+            let random_access = Packet::parse(&pending.packets[0])
+                .adaptation_field
+                .and_then(|adaptation_field| adaptation_field.first())
+                .is_some_and(|flags| flags & 0x40 != 0);
+            write_sei(
+                &VideoAccessUnit {
+                    codec,
+                    pts: header.pts,
+                    dts: header.dts,
+                    random_access,
+                    data: access_unit,
+                },
+                &mut self.sei,
+            )?;
+            if !self.sei.is_empty() {
+                self.pes
+                    .extend_from_slice(&pending.data[..header.header_size]);
+                if insert_sei(
+                    access_unit,
+                    NalUnitFraming::AnnexB,
+                    codec,
+                    &self.sei,
+                    &mut self.pes,
+                )
+                .is_ok()
+                {
+                    update_packet_length(&mut self.pes, header.packet_length != 0);
+                } else {
+                    self.pes.clear();
+                }
+            }
+        }
+        if self.pes.is_empty() {
+            for packet in &pending.packets {
+                if Packet::parse(packet).pid == video_pid {
+                    self.write_video_packet(packet, output);
+                } else {
+                    output.extend_from_slice(packet);
+                }
+            }
+            return Ok(());
+        }
+
+        let is_video = |packet: &Packet| packet.pid == video_pid && !packet.transport_error;
+        let last_video_packet = pending
+            .packets
+            .iter()
+            .rposition(|packet| is_video(&Packet::parse(packet)))
+            .unwrap();
+        let mut remaining = &self.pes[..];
+        let mut payload_unit_start = true;
+        for (index, packet) in pending.packets.iter().enumerate() {
+            let parsed = Packet::parse(packet);
+            if !is_video(&parsed) {
+                if parsed.pid != video_pid {
+                    output.extend_from_slice(packet);
+                }
+                continue;
+            }
+            let header = [SYNC_BYTE, packet[1] & !0x40, packet[2]];
+            let adaptation_field = parsed.adaptation_field.map(adaptation_field_content);
+            let (payload, rest) =
+                remaining.split_at(payload_capacity(adaptation_field).min(remaining.len()));
+            remaining = rest;
+            // packets that carried the stuffing of the original PES may have nothing left
+            if !payload.is_empty() || adaptation_field.is_some_and(|field| !field.is_empty()) {
+                let continuity_counter = next_continuity_counter(
+                    &mut self.video_continuity_counter,
+                    !payload.is_empty(),
+                    parsed.continuity_counter,
+                );
+                let header = if payload_unit_start && !payload.is_empty() {
+                    payload_unit_start = false;
+                    [header[0], header[1] | 0x40, header[2]]
+                } else {
+                    header
+                };
+                write_packet(
+                    output,
+                    header,
+                    continuity_counter,
+                    adaptation_field,
+                    payload,
+                );
+            }
+            if index == last_video_packet {
+                for payload in remaining.chunks(TS_PACKET_PAYLOAD_SIZE) {
+                    let continuity_counter = next_continuity_counter(
+                        &mut self.video_continuity_counter,
+                        true,
+                        parsed.continuity_counter,
+                    );
+                    write_packet(output, header, continuity_counter, None, payload);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        pes::write_packet, Packet, TsRemuxer, VideoAccessUnit, SYNC_BYTE, TS_PACKET_PAYLOAD_SIZE,
+        TS_PACKET_SIZE,
+    };
+    use crate::h26x::access_unit::VideoCodec;
+
+    const PMT_PID: u16 = 0x1000;
+    const VIDEO_PID: u16 = 0x100;
+    const AUDIO_PID: u16 = 0x101;
+
+    /// Too large to fit into the stuffing of the first PES
+    fn large_sei() -> Vec<u8> {
+        let mut sei = vec![0, 0, 0, 1, 0x06, 0x05, 34];
+        sei.resize(sei.len() + 34, 0xaa);
+        sei.push(0x80);
+        sei
+    }
+
+    fn header(pid: u16, payload_unit_start: bool) -> [u8; 3] {
+        let [pid_high, pid_low] = pid.to_be_bytes();
+        [
+            SYNC_BYTE,
+            pid_high | u8::from(payload_unit_start) << 6,
+            pid_low,
+        ]
+    }
+
+    fn section_packet(pid: u16, section: &[u8]) -> Vec<u8> {
+        let mut payload = vec![0];
+        payload.extend_from_slice(section);
+        // CRC_32, which isn't checked
+        payload.extend_from_slice(&[0; 4]);
+        let mut output = vec![];
+        write_packet(&mut output, header(pid, true), 0, None, &payload);
+        output
+    }
+
+    /// A bounded video PES packet with a PTS
+    fn pes(pts: u64, access_unit: &[u8]) -> Vec<u8> {
+        let mut pes = vec![0, 0, 1, 0xe0, 0, 0, 0x80, 0x80, 5];
+        pes.extend_from_slice(&[
+            0x21 | (pts >> 29) as u8 & 0x0e,
+            (pts >> 22) as u8,
+            (pts >> 14) as u8 | 1,
+            (pts >> 7) as u8,
+            (pts << 1) as u8 | 1,
+        ]);
+        pes.extend_from_slice(access_unit);
+        let packet_length = u16::try_from(pes.len() - 6).unwrap();
+        pes[4..6].copy_from_slice(&packet_length.to_be_bytes());
+        pes
+    }
+
+    // random_access_indicator and a PCR on the first packet
+    const FIRST_ADAPTATION_FIELD: [u8; 7] = [0x50, 0, 0, 0, 0, 0x7e, 0];
+
+    fn pes_packets(
+        pid: u16,
+        continuity_counter: &mut u8,
+        pts: u64,
+        access_unit: &[u8],
+        output: &mut Vec<u8>,
+    ) -> usize {
+        let pes = pes(pts, access_unit);
+        let adaptation_field = FIRST_ADAPTATION_FIELD;
+        let (first, rest) =
+            pes.split_at((TS_PACKET_PAYLOAD_SIZE - 1 - adaptation_field.len()).min(pes.len()));
+        write_packet(
+            output,
+            header(pid, true),
+            *continuity_counter,
+            Some(&adaptation_field),
+            first,
+        );
+        let mut packets = 1;
+        for payload in rest.chunks(TS_PACKET_PAYLOAD_SIZE) {
+            *continuity_counter = (*continuity_counter + 1) & 0x0f;
+            write_packet(
+                output,
+                header(pid, false),
+                *continuity_counter,
+                None,
+                payload,
+            );
+            packets += 1;
+        }
+        *continuity_counter = (*continuity_counter + 1) & 0x0f;
+        packets
+    }
+
+    fn access_unit(slice_size: usize) -> Vec<u8> {
+        let mut access_unit = vec![0, 0, 0, 1, 0x09, 0xf0, 0, 0, 1, 0x65];
+        access_unit.resize(access_unit.len() + slice_size, 0x42);
+        access_unit
+    }
+
+    /// A PAT and a PMT with audio and H.264 video
+    fn program_tables() -> Vec<u8> {
+        let mut stream = section_packet(0, &[0x00, 0xb0, 13, 0, 1, 0xc1, 0, 0, 0, 1, 0xf0, 0x00]);
+        stream.extend(section_packet(
+            PMT_PID,
+            &[
+                0x02, 0xb0, 23, 0, 1, 0xc1, 0, 0, 0xe1, 0x00, 0xf0, 0, 0x0f, 0xe1, 0x01, 0xf0, 0,
+                0x1b, 0xe1, 0x00, 0xf0, 0,
+            ],
+        ));
+        stream
+    }
+
+    /// [`program_tables`] and two video PES packets, the first one interleaved with audio
+    fn transport_stream() -> Vec<u8> {
+        let mut stream = program_tables();
+        let mut video = vec![];
+        let mut continuity_counter = 14;
+        pes_packets(
+            VIDEO_PID,
+            &mut continuity_counter,
+            90000,
+            &access_unit(500),
+            &mut video,
+        );
+        stream.extend_from_slice(&video[..TS_PACKET_SIZE]);
+        write_packet(&mut stream, header(AUDIO_PID, true), 0, None, &[0xab; 184]);
+        stream.extend_from_slice(&video[TS_PACKET_SIZE..]);
+        pes_packets(
+            VIDEO_PID,
+            &mut continuity_counter,
+            93003,
+            &access_unit(100),
+            &mut stream,
+        );
+        stream
+    }
+
+    fn remux(
+        input: &[u8],
+        chunk_size: usize,
+        mut write_sei: impl FnMut(&VideoAccessUnit, &mut Vec<u8>) -> std::io::Result<()>,
+    ) -> Vec<u8> {
+        let mut remuxer = TsRemuxer::new();
+        let mut output = vec![];
+        for chunk in input.chunks(chunk_size) {
+            remuxer.push(chunk, &mut output, &mut write_sei).unwrap();
+        }
+        remuxer.finish(&mut output, &mut write_sei).unwrap();
+        output
+    }
+
+    /// `transport_stream` with a corrupted packet on the video PID in the first PES
+    fn transport_stream_with_error() -> Vec<u8> {
+        let mut stream = transport_stream();
+        let mut corrupted = vec![];
+        write_packet(
+            &mut corrupted,
+            header(VIDEO_PID, false),
+            7,
+            None,
+            &[0xff; TS_PACKET_PAYLOAD_SIZE],
+        );
+        corrupted[1] |= 0x80;
+        let offset = 3 * TS_PACKET_SIZE;
+        stream.splice(offset..offset, corrupted);
+        stream
+    }
+
+    /// Checks that the continuity counters of the video PID count up from 14
+    fn assert_video_continuity(stream: &[u8]) {
+        let mut continuity_counter = 14;
+        for packet in packets(stream).filter(|packet| packet.pid == VIDEO_PID) {
+            assert!(packet.continuity_counter == continuity_counter);
+            continuity_counter = (continuity_counter + 1) & 0x0f;
+        }
+    }
+
+    fn packets(stream: &[u8]) -> impl Iterator<Item = Packet<'_>> {
+        stream
+            .chunks_exact(TS_PACKET_SIZE)
+            .map(|packet| Packet::parse(packet.try_into().unwrap()))
+    }
+
+    #[test]
+    fn unchanged_without_sei() {
+        let input = transport_stream();
+        let mut access_units = vec![];
+        let output = remux(&input, 100, |access_unit, _| {
+            access_units.push((
+                access_unit.codec,
+                access_unit.pts,
+                access_unit.random_access,
+                access_unit.data.len(),
+            ));
+            Ok(())
+        });
+        assert!(output == input);
+        assert!(
+            access_units
+                == [
+                    (VideoCodec::H264, Some(90000), true, access_unit(500).len()),
+                    (VideoCodec::H264, Some(93003), true, access_unit(100).len()),
+                ]
+        );
+    }
+
+    #[test]
+    fn sei_inserted_into_every_pes() {
+        let input = transport_stream();
+        let output = remux(&input, 1000, |_, sei| {
+            sei.extend_from_slice(&large_sei());
+            Ok(())
+        });
+        assert!(output.len().is_multiple_of(TS_PACKET_SIZE));
+
+        let pids: Vec<_> = packets(&output).map(|packet| packet.pid).collect();
+        // the first PES needs one more packet, which goes after its last packet
+        assert!(pids[..5] == [0, PMT_PID, VIDEO_PID, AUDIO_PID, VIDEO_PID]);
+        assert!(pids[5..] == [VIDEO_PID, VIDEO_PID, VIDEO_PID]);
+        assert!(pids.len() == packets(&input).count() + 1);
+
+        let mut pes_packets: Vec<Vec<u8>> = vec![];
+        let mut continuity_counter = 14;
+        for packet in packets(&output).filter(|packet| packet.pid == VIDEO_PID) {
+            assert!(packet.continuity_counter == continuity_counter);
+            continuity_counter = (continuity_counter + 1) & 0x0f;
+            if packet.payload_unit_start {
+                // the PCR stays on the first packet
+                assert!(packet.adaptation_field.unwrap()[..2] == [0x50, 0]);
+                pes_packets.push(vec![]);
+            }
+            pes_packets
+                .last_mut()
+                .unwrap()
+                .extend_from_slice(packet.payload);
+        }
+        assert!(pes_packets.len() == 2);
+        for (pes, slice_size) in pes_packets.iter().zip([500, 100]) {
+            let packet_length = usize::from(u16::from_be_bytes([pes[4], pes[5]]));
+            assert!(packet_length == pes.len() - 6);
+            let mut expected = access_unit(slice_size);
+            expected.splice(6..6, large_sei());
+            assert!(pes[14..] == expected);
+        }
+    }
+
+    #[test]
+    fn transport_errors_on_video_pid() {
+        let input = transport_stream_with_error();
+        let corrupted = |stream: &[u8]| {
+            packets(stream)
+                .filter(|packet| packet.transport_error)
+                .count()
+        };
+
+        let output = remux(&input, 100, |_, _| Ok(()));
+        assert!(output.len() == input.len());
+        assert!(corrupted(&output) == 1);
+        assert_video_continuity(&output);
+
+        let output = remux(&input, 1000, |_, sei| {
+            sei.extend_from_slice(&large_sei());
+            Ok(())
+        });
+        assert!(corrupted(&output) == 0);
+        assert!(packets(&output).count() == packets(&input).count());
+        assert_video_continuity(&output);
+    }
+
+    #[test]
+    fn zero_length_adaptation_field() {
+        // the last packet has an adaptation field without flags, and bytes after the end of
+        // the bounded PES that the SEI doesn't make up for
+        let first_capacity = TS_PACKET_PAYLOAD_SIZE - 1 - FIRST_ADAPTATION_FIELD.len();
+        let mut data = pes(90000, &access_unit(295));
+        let access_unit_end = data.len();
+        data.resize(first_capacity + TS_PACKET_PAYLOAD_SIZE - 1, 0xff);
+        let (first, last) = data.split_at(first_capacity);
+        let mut input = program_tables();
+        write_packet(
+            &mut input,
+            header(VIDEO_PID, true),
+            0,
+            Some(&FIRST_ADAPTATION_FIELD),
+            first,
+        );
+        write_packet(&mut input, header(VIDEO_PID, false), 1, Some(&[]), last);
+        assert!(packets(&input).last().unwrap().adaptation_field == Some(&[][..]));
+
+        let sei = [0, 0, 0, 1, 0x06, 0x05, 1, 0xaa, 0x80];
+        let output = remux(&input, 1000, |_, output| {
+            output.extend_from_slice(&sei);
+            Ok(())
+        });
+        let video: Vec<_> = packets(&output)
+            .filter(|packet| packet.pid == VIDEO_PID)
+            .collect();
+        assert!(video.len() == 2);
+        let adaptation_field = video[1].adaptation_field.unwrap();
+        // no flags, then stuffing
+        assert!(adaptation_field[0] == 0);
+        assert!(adaptation_field[1..].iter().all(|&byte| byte == 0xff));
+        let payload = [video[0].payload, video[1].payload].concat();
+        let mut expected = access_unit(295);
+        expected.splice(6..6, sei);
+        assert!(payload[14..] == expected);
+        assert!(payload.len() == access_unit_end + sei.len());
+    }
+}
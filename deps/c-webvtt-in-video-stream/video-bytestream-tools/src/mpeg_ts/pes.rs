@@ -0,0 +1,138 @@
+use super::{TS_PACKET_PAYLOAD_SIZE, TS_PACKET_SIZE};
+
+/// The start of a PES packet with `PES_packet_data_byte`s following at `header_size`
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct PesHeader {
+    pub(crate) header_size: usize,
+    /// `PES_packet_length`, zero if unbounded
+    pub(crate) packet_length: u16,
+    pub(crate) pts: Option<u64>,
+    pub(crate) dts: Option<u64>,
+}
+
+fn read_timestamp(bytes: &[u8]) -> Option<u64> {
+    let bytes = bytes.get(..5)?;
+    Some(
+        u64::from(bytes[0] >> 1 & 0b111) << 30
+            | u64::from(bytes[1]) << 22
+            | u64::from(bytes[2] >> 1) << 15
+            | u64::from(bytes[3]) << 7
+            | u64::from(bytes[4] >> 1),
+    )
+}
+
+impl PesHeader {
+    /// Parses the header of a video PES packet, `None` if it's malformed or truncated
+    pub(crate) fn parse(pes: &[u8]) -> Option<Self> {
+        if pes.get(..3)? != [0, 0, 1] || !(0xe0..=0xef).contains(pes.get(3)?) {
+            return None;
+        }
+        let packet_length = u16::from_be_bytes([pes[4], *pes.get(5)?]);
+        if *pes.get(6)? >> 6 != 0b10 {
+            return None;
+        }
+        let pts_dts_flags = *pes.get(7)? >> 6;
+        let header_size = 9 + usize::from(*pes.get(8)?);
+        if pes.len() < header_size
+            || (packet_length != 0 && pes.len() < 6 + usize::from(packet_length))
+        {
+            return None;
+        }
+        let pts = match pts_dts_flags {
+            0b10 | 0b11 => Some(read_timestamp(&pes[9..header_size])?),
+            _ => None,
+        };
+        let dts = match pts_dts_flags {
+            0b11 => Some(read_timestamp(&pes[14..header_size])?),
+            _ => None,
+        };
+        Some(Self {
+            header_size,
+            packet_length,
+            pts,
+            dts,
+        })
+    }
+}
+
+/// The `adaptation_field` of a TS packet after `adaptation_field_length` without stuffing
+/// bytes, i.e. its flags and the fields they announce.
+pub(crate) fn adaptation_field_content(adaptation_field: &[u8]) -> &[u8] {
+    let Some(&flags) = adaptation_field.first() else {
+        return adaptation_field;
+    };
+    let mut size = 1;
+    // PCR, OPCR, splice_countdown
+    if flags & 0x10 != 0 {
+        size += 6;
+    }
+    if flags & 0x08 != 0 {
+        size += 6;
+    }
+    if flags & 0x04 != 0 {
+        size += 1;
+    }
+    // transport_private_data and adaptation_field_extension, both prefixed with their length
+    for flag in [0x02, 0x01] {
+        if flags & flag != 0 {
+            size += 1 + usize::from(adaptation_field.get(size).copied().unwrap_or(0));
+        }
+    }
+    &adaptation_field[..size.min(adaptation_field.len())]
+}
+
+/// Payload bytes that fit into a packet with `adaptation_field`
+pub(crate) fn payload_capacity(adaptation_field: Option<&[u8]>) -> usize {
+    match adaptation_field {
+        Some(adaptation_field) => TS_PACKET_PAYLOAD_SIZE - 1 - adaptation_field.len(),
+        None => TS_PACKET_PAYLOAD_SIZE,
+    }
+}
+
+/// Writes a TS packet, the adaptation field is added or padded with stuffing bytes if
+/// `payload` doesn't fill the packet.
+pub(crate) fn write_packet(
+    output: &mut Vec<u8>,
+    header: [u8; 3],
+    continuity_counter: u8,
+    adaptation_field: Option<&[u8]>,
+    payload: &[u8],
+) {
+    assert!(payload.len() <= payload_capacity(adaptation_field));
+    let start = output.len();
+    let adaptation_field_control = match (
+        adaptation_field.is_some() || payload.len() < TS_PACKET_PAYLOAD_SIZE,
+        !payload.is_empty(),
+    ) {
+        (true, true) => 0b11,
+        (true, false) => 0b10,
+        (false, _) => 0b01,
+    };
+    output.extend_from_slice(&header);
+    output.push(adaptation_field_control << 4 | continuity_counter & 0x0f);
+    if adaptation_field_control & 0b10 != 0 {
+        let adaptation_field_length = TS_PACKET_PAYLOAD_SIZE - 1 - payload.len();
+        output.push(u8::try_from(adaptation_field_length).unwrap());
+        let content = match adaptation_field {
+            Some(adaptation_field) if !adaptation_field.is_empty() => adaptation_field,
+            // no flags set
+            _ if adaptation_field_length > 0 => &[0],
+            _ => &[],
+        };
+        output.extend_from_slice(content);
+        output.resize(output.len() + adaptation_field_length - content.len(), 0xff);
+    }
+    output.extend_from_slice(payload);
+    assert!(output.len() - start == TS_PACKET_SIZE);
+}
+
+/// Replaces `PES_packet_length` of the header at the start of `pes`, bounded lengths that
+/// no longer fit become unbounded, which is allowed for video.
+pub(crate) fn update_packet_length(pes: &mut [u8], bounded: bool) {
+    let packet_length = if bounded {
+        u16::try_from(pes.len() - 6).unwrap_or(0)
+    } else {
+        0
+    };
+    pes[4..6].copy_from_slice(&packet_length.to_be_bytes());
+}
@@ -0,0 +1,81 @@
+use crate::h26x::access_unit::VideoCodec;
+
+pub const PAT_PID: u16 = 0;
+const PAT_TABLE_ID: u8 = 0x00;
+const PMT_TABLE_ID: u8 = 0x02;
+
+pub const STREAM_TYPE_H264: u8 = 0x1b;
+pub const STREAM_TYPE_H265: u8 = 0x24;
+pub const STREAM_TYPE_H266: u8 = 0x33;
+
+/// Collects the first section of a PSI table that may span several TS packets
+#[derive(Debug, Default)]
+pub(crate) struct SectionBuffer {
+    data: Vec<u8>,
+    started: bool,
+}
+
+impl SectionBuffer {
+    /// Adds the payload of a TS packet, returns the section once it's complete
+    pub(crate) fn push(&mut self, payload_unit_start: bool, payload: &[u8]) -> Option<&[u8]> {
+        if payload_unit_start {
+            let (&pointer_field, rest) = payload.split_first()?;
+            self.data.clear();
+            self.data
+                .extend_from_slice(rest.get(usize::from(pointer_field)..)?);
+            self.started = true;
+        } else if self.started {
+            self.data.extend_from_slice(payload);
+        } else {
+            return None;
+        }
+        let section_length =
+            usize::from(u16::from_be_bytes([*self.data.get(1)?, *self.data.get(2)?]) & 0x0fff);
+        let section = self.data.get(..3 + section_length)?;
+        self.started = false;
+        Some(section)
+    }
+}
+
+/// The fields of a long form section between `last_section_number` and `CRC_32`
+fn section_body(section: &[u8], table_id: u8) -> Option<&[u8]> {
+    if *section.first()? != table_id || section.len() < 12 {
+        return None;
+    }
+    Some(&section[8..section.len() - 4])
+}
+
+/// Returns the PMT PIDs of all programs in a PAT
+pub(crate) fn parse_pat(section: &[u8]) -> Option<Vec<u16>> {
+    let body = section_body(section, PAT_TABLE_ID)?;
+    Some(
+        body.chunks_exact(4)
+            .filter(|program| program[..2] != [0, 0])
+            .map(|program| u16::from_be_bytes([program[2], program[3]]) & 0x1fff)
+            .collect(),
+    )
+}
+
+/// Returns the PID and codec of the first H.26x video stream in a PMT
+pub(crate) fn parse_pmt(section: &[u8]) -> Option<Option<(u16, VideoCodec)>> {
+    let body = section_body(section, PMT_TABLE_ID)?;
+    let program_info_length =
+        usize::from(u16::from_be_bytes([*body.get(2)?, *body.get(3)?]) & 0x0fff);
+    let mut streams = body.get(4 + program_info_length..)?;
+    while streams.len() >= 5 {
+        let stream_type = streams[0];
+        let pid = u16::from_be_bytes([streams[1], streams[2]]) & 0x1fff;
+        let es_info_length = usize::from(u16::from_be_bytes([streams[3], streams[4]]) & 0x0fff);
+        let codec = match stream_type {
+            STREAM_TYPE_H264 => Some(VideoCodec::H264),
+            STREAM_TYPE_H265 => Some(VideoCodec::H265),
+            STREAM_TYPE_H266 => Some(VideoCodec::H266),
+            _ => None,
+        };
+        if let Some(codec) = codec {
+            return Some(Some((pid, codec)));
+        }
+        streams = streams.get(5 + es_info_length..)?;
+    }
+    Some(None)
+}
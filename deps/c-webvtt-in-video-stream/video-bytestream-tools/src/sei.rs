@@ -0,0 +1,236 @@
+//! Arbitrary SEI messages, for timed metadata other than WebVTT.
+//!
+//! H.26x RBSP writers write every message as a `sei_message` into the SEI NAL unit they were
+//! started for, so several messages can share one NAL unit. AV1 writers write every message as
+//! its own metadata OBU.
+//!
+//! ```
+//! use video_bytestream_tools::{
+//!     h264::{annex_b::AnnexBWriter, H264ByteStreamWrite, H264NalHeader},
+//!     h26x::{NalUnitWrite, RbspWrite},
+//!     sei::{SeiMessage, SeiWrite},
+//! };
+//! use uuid::uuid;
+//!
+//! let nal_header = H264NalHeader::from_nal_unit_type_and_nal_ref_idc(
+//!     h264_reader::nal::UnitType::SEI,
+//!     0,
+//! )
+//! .unwrap();
+//! let mut buffer = vec![];
+//! let mut writer = AnnexBWriter::new(&mut buffer)
+//!     .start_write_nal_unit()
+//!     .unwrap()
+//!     .write_nal_header(nal_header)
+//!     .unwrap();
+//! writer
+//!     .write_sei_messages(&[
+//!         SeiMessage::UserDataUnregistered {
+//!             uuid: uuid!("6c1d3c54-4b8b-4c4e-9d6a-3f2f0e8f4a11"),
+//!             payload: b"my timed metadata",
+//!         },
+//!         SeiMessage::Raw {
+//!             payload_type: 1,
+//!             payload: &[0x00, 0x10],
+//!         },
+//!     ])
+//!     .unwrap();
+//! writer.finish_rbsp().unwrap();
+//! ```
+
+use crate::{av1::MetadataType, t35::ItutT35Header};
+use std::io::Write;
+use uuid::Uuid;
+
+pub const PIC_TIMING: usize = 1;
+pub const USER_DATA_REGISTERED_ITU_T_T35: usize = 4;
+pub const USER_DATA_UNREGISTERED: usize = 5;
+pub const TIME_CODE: usize = 136;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeiMessage<'a> {
+    /// `user_data_unregistered`, or a metadata OBU of type unregistered private 6 in AV1, the
+    /// same way the WebVTT payloads are carried.
+    UserDataUnregistered { uuid: Uuid, payload: &'a [u8] },
+    /// `user_data_registered_itu_t_t35`, or an ITU-T T.35 metadata OBU in AV1. `payload`
+    /// follows the terminal provider code of `header`.
+    UserDataRegisteredItuTT35 {
+        header: ItutT35Header,
+        payload: &'a [u8],
+    },
+    /// Any `payload_type` with its raw payload, only supported by H.26x writers.
+    Raw {
+        payload_type: usize,
+        payload: &'a [u8],
+    },
+    /// A metadata OBU with its raw payload, only supported by AV1 writers.
+    Metadata {
+        metadata_type: MetadataType,
+        payload: &'a [u8],
+    },
+}
+
+impl SeiMessage<'_> {
+    /// `None` for messages that AV1 has no equivalent for
+    pub(crate) fn payload_type(&self) -> Option<usize> {
+        match self {
+            SeiMessage::UserDataUnregistered { .. } => Some(USER_DATA_UNREGISTERED),
+            SeiMessage::UserDataRegisteredItuTT35 { .. } => Some(USER_DATA_REGISTERED_ITU_T_T35),
+            SeiMessage::Raw { payload_type, .. } => Some(*payload_type),
+            SeiMessage::Metadata { .. } => None,
+        }
+    }
+
+    /// `None` for messages that H.26x has no equivalent for
+    pub(crate) fn metadata_type(&self) -> Option<MetadataType> {
+        match self {
+            SeiMessage::UserDataUnregistered { .. } => Some(MetadataType::UnregisteredPrivate6),
+            SeiMessage::UserDataRegisteredItuTT35 { .. } => Some(MetadataType::ItutT35),
+            SeiMessage::Raw { .. } => None,
+            SeiMessage::Metadata { metadata_type, .. } => Some(*metadata_type),
+        }
+    }
+
+    fn payload_size(&self) -> usize {
+        match self {
+            SeiMessage::UserDataUnregistered { uuid, payload } => {
+                uuid.as_bytes().len() + payload.len()
+            }
+            SeiMessage::UserDataRegisteredItuTT35 { header, payload } => {
+                header.size() + payload.len()
+            }
+            SeiMessage::Raw { payload, .. } | SeiMessage::Metadata { payload, .. } => payload.len(),
+        }
+    }
+}
+
+pub(crate) fn unsupported_message_error(message: &SeiMessage) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        format!("{message:?} isn't supported by this bitstream format"),
+    )
+}
+
+pub(crate) fn write_sei_message<W: Write + ?Sized>(
+    writer: &mut W,
+    message: &SeiMessage,
+    write_format_header: impl FnOnce(&mut W, usize) -> std::io::Result<()>,
+) -> std::io::Result<()> {
+    write_format_header(writer, message.payload_size())?;
+    match message {
+        SeiMessage::UserDataUnregistered { uuid, payload } => {
+            writer.write_all(uuid.as_bytes())?;
+            writer.write_all(payload)
+        }
+        SeiMessage::UserDataRegisteredItuTT35 { header, payload } => {
+            header.write_to(writer)?;
+            writer.write_all(payload)
+        }
+        SeiMessage::Raw { payload, .. } | SeiMessage::Metadata { payload, .. } => {
+            writer.write_all(payload)
+        }
+    }
+}
+
+pub trait SeiWrite {
+    /// Fails with [`std::io::ErrorKind::Unsupported`] for messages the bitstream format can't
+    /// carry, nothing is written then.
+    fn write_sei_message(&mut self, message: &SeiMessage) -> std::io::Result<()>;
+
+    fn write_sei_messages(&mut self, messages: &[SeiMessage]) -> std::io::Result<()> {
+        for message in messages {
+            self.write_sei_message(message)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SeiMessage, SeiWrite, USER_DATA_UNREGISTERED};
+    use crate::{
+        av1::{MetadataType, OBUWriter},
+        h264::{H264ByteStreamWrite, H264NalHeader},
+        h26x::{NalUnitWrite, RbspWrite},
+    };
+    use byteorder::ReadBytesExt;
+    use h264_reader::nal::{Nal, RefNal, UnitType};
+    use std::io::Read;
+    use uuid::uuid;
+
+    #[test]
+    fn multiple_messages_in_one_nal_unit() {
+        let mut buffer = vec![];
+        let nal_header =
+            H264NalHeader::from_nal_unit_type_and_nal_ref_idc(UnitType::SEI, 0).unwrap();
+        let mut writer = (&mut buffer)
+            .start_write_nal_unit()
+            .unwrap()
+            .write_nal_header(nal_header)
+            .unwrap();
+        let uuid = uuid!("6c1d3c54-4b8b-4c4e-9d6a-3f2f0e8f4a11");
+        let large_payload = vec![0x42; 300];
+        writer
+            .write_sei_messages(&[
+                SeiMessage::UserDataUnregistered {
+                    uuid,
+                    payload: &[0, 0, 1],
+                },
+                SeiMessage::Raw {
+                    payload_type: 300,
+                    payload: &large_payload,
+                },
+            ])
+            .unwrap();
+        writer.finish_rbsp().unwrap();
+
+        let nal = RefNal::new(&buffer, &[], true);
+        let mut rbsp = vec![];
+        nal.rbsp_bytes().read_to_end(&mut rbsp).unwrap();
+        let mut reader = &rbsp[..];
+        assert!(usize::from(reader.read_u8().unwrap()) == USER_DATA_UNREGISTERED);
+        assert!(reader.read_u8().unwrap() == 16 + 3);
+        let mut uuid_bytes = [0; 16];
+        reader.read_exact(&mut uuid_bytes).unwrap();
+        assert!(uuid_bytes == *uuid.as_bytes());
+        assert!(reader[..3] == [0, 0, 1]);
+        reader = &reader[3..];
+        // payload type and size of 300 are coded as 255 + 45
+        assert!(reader[..4] == [255, 45, 255, 45]);
+        reader = &reader[4..];
+        assert!(reader[..300] == large_payload[..]);
+        assert!(reader[300..] == [0x80]);
+    }
+
+    #[test]
+    fn av1_metadata_obus() {
+        let mut writer = OBUWriter::new(vec![]);
+        writer
+            .write_sei_message(&SeiMessage::Metadata {
+                metadata_type: MetadataType::UnregisteredPrivate7,
+                payload: &[1, 2, 3],
+            })
+            .unwrap();
+        let uuid = uuid!("6c1d3c54-4b8b-4c4e-9d6a-3f2f0e8f4a11");
+        writer
+            .write_sei_message(&SeiMessage::UserDataUnregistered {
+                uuid,
+                payload: &[4],
+            })
+            .unwrap();
+        let error = writer
+            .write_sei_message(&SeiMessage::Raw {
+                payload_type: 1,
+                payload: &[],
+            })
+            .unwrap_err();
+        assert!(error.kind() == std::io::ErrorKind::Unsupported);
+
+        let output = writer.into_inner();
+        // obu_type 5 with size field, obu_size, metadata_type, payload, trailing bits
+        assert!(output[..7] == [0x2a, 5, 7, 1, 2, 3, 0x80]);
+        assert!(output[7..10] == [0x2a, 19, 6]);
+        assert!(output[10..26] == *uuid.as_bytes());
+        assert!(output[26..] == [4, 0x80]);
+    }
+}
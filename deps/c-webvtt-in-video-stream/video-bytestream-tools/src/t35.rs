@@ -0,0 +1,160 @@
+//! ITU-T T.35 registered user data, e.g. CEA-708 captions or HDR10+ dynamic metadata.
+//!
+//! Written as `user_data_registered_itu_t_t35` SEI messages by H.26x writers and as ITU-T T.35
+//! metadata OBUs by AV1 writers.
+
+use crate::sei::{SeiMessage, SeiWrite};
+use byteorder::{BigEndian, WriteBytesExt};
+use std::io::Write;
+use thiserror::Error;
+
+pub const ITU_T_T35_COUNTRY_CODE_USA: u8 = 0xb5;
+/// Signals that `itu_t_t35_country_code_extension_byte` follows
+pub const ITU_T_T35_COUNTRY_CODE_EXTENSION: u8 = 0xff;
+pub const ITU_T_T35_PROVIDER_CODE_ATSC: u16 = 0x0031;
+pub const ITU_T_T35_PROVIDER_CODE_DOLBY: u16 = 0x003b;
+pub const ITU_T_T35_PROVIDER_CODE_SAMSUNG: u16 = 0x003c;
+
+#[derive(Error, Debug)]
+#[error(
+    "country code 0xff needs an extension byte, see ItutT35Header::with_country_code_extension"
+)]
+pub struct MissingCountryCodeExtensionError;
+
+/// The country code (with its extension byte) and terminal provider code that start every
+/// ITU-T T.35 payload
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ItutT35Header {
+    country_code: u8,
+    country_code_extension: Option<u8>,
+    terminal_provider_code: u16,
+}
+
+impl ItutT35Header {
+    /// ATSC A/53 user data in the USA, used for `GA94` captions
+    pub const ATSC: Self = Self {
+        country_code: ITU_T_T35_COUNTRY_CODE_USA,
+        country_code_extension: None,
+        terminal_provider_code: ITU_T_T35_PROVIDER_CODE_ATSC,
+    };
+
+    pub fn new(
+        country_code: u8,
+        terminal_provider_code: u16,
+    ) -> Result<Self, MissingCountryCodeExtensionError> {
+        if country_code == ITU_T_T35_COUNTRY_CODE_EXTENSION {
+            return Err(MissingCountryCodeExtensionError);
+        }
+        Ok(Self {
+            country_code,
+            country_code_extension: None,
+            terminal_provider_code,
+        })
+    }
+
+    /// A header with country code 0xff followed by `country_code_extension`
+    pub fn with_country_code_extension(
+        country_code_extension: u8,
+        terminal_provider_code: u16,
+    ) -> Self {
+        Self {
+            country_code: ITU_T_T35_COUNTRY_CODE_EXTENSION,
+            country_code_extension: Some(country_code_extension),
+            terminal_provider_code,
+        }
+    }
+
+    pub fn country_code(&self) -> u8 {
+        self.country_code
+    }
+
+    pub fn country_code_extension(&self) -> Option<u8> {
+        self.country_code_extension
+    }
+
+    pub fn terminal_provider_code(&self) -> u16 {
+        self.terminal_provider_code
+    }
+
+    pub(crate) fn size(&self) -> usize {
+        1 + usize::from(self.country_code_extension.is_some()) + 2
+    }
+
+    pub(crate) fn write_to<W: Write + ?Sized>(&self, writer: &mut W) -> std::io::Result<()> {
+        writer.write_u8(self.country_code)?;
+        if let Some(country_code_extension) = self.country_code_extension {
+            writer.write_u8(country_code_extension)?;
+        }
+        writer.write_u16::<BigEndian>(self.terminal_provider_code)
+    }
+}
+
+pub trait ItutT35Write {
+    /// Writes `header` followed by `payload`, i.e. `payload` starts after the terminal provider
+    /// code.
+    fn write_itu_t_t35(&mut self, header: ItutT35Header, payload: &[u8]) -> std::io::Result<()>;
+}
+
+impl<T: SeiWrite + ?Sized> ItutT35Write for T {
+    fn write_itu_t_t35(&mut self, header: ItutT35Header, payload: &[u8]) -> std::io::Result<()> {
+        self.write_sei_message(&SeiMessage::UserDataRegisteredItuTT35 { header, payload })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        ItutT35Header, ItutT35Write, ITU_T_T35_COUNTRY_CODE_USA, ITU_T_T35_PROVIDER_CODE_SAMSUNG,
+    };
+    use crate::{
+        av1::{annex_b::AnnexBOBUWriter, OBUWriter},
+        h265::{H265ByteStreamWrite, H265NalHeader, UnitType},
+        h26x::{NalUnitWrite, RbspWrite},
+        sei::USER_DATA_REGISTERED_ITU_T_T35,
+    };
+
+    #[test]
+    fn country_code_extension() {
+        assert!(ItutT35Header::new(0xff, 0x0031).is_err());
+        let header = ItutT35Header::with_country_code_extension(0x12, 0x3456);
+        let mut output = vec![];
+        header.write_to(&mut output).unwrap();
+        assert!(output == [0xff, 0x12, 0x34, 0x56]);
+        assert!(header.size() == output.len());
+    }
+
+    #[test]
+    fn h265_user_data_registered_sei() {
+        let mut output = vec![];
+        let nal_header =
+            H265NalHeader::from_nal_unit_type_and_nuh_ids(UnitType::PrefixSeiNut, 0, 0).unwrap();
+        let mut writer = (&mut output)
+            .start_write_nal_unit()
+            .unwrap()
+            .write_nal_header(nal_header)
+            .unwrap();
+        // HDR10+ style header
+        let header =
+            ItutT35Header::new(ITU_T_T35_COUNTRY_CODE_USA, ITU_T_T35_PROVIDER_CODE_SAMSUNG)
+                .unwrap();
+        writer.write_itu_t_t35(header, &[0x00, 0x01, 0x04]).unwrap();
+        writer.finish_rbsp().unwrap();
+        assert!(usize::from(output[2]) == USER_DATA_REGISTERED_ITU_T_T35);
+        assert!(output[3] == 6);
+        assert!(output[4..] == [0xb5, 0x00, 0x3c, 0x00, 0x01, 0x04, 0x80]);
+    }
+
+    #[test]
+    fn av1_itu_t_t35_metadata() {
+        let header = ItutT35Header::with_country_code_extension(0x12, 0x3456);
+        let mut writer = OBUWriter::new(vec![]);
+        writer.write_itu_t_t35(header, &[0xaa]).unwrap();
+        // obu_type 5 with size field, obu_size, metadata_type 4, payload, trailing bits
+        assert!(writer.into_inner() == [0x2a, 7, 4, 0xff, 0x12, 0x34, 0x56, 0xaa, 0x80]);
+
+        let mut writer = AnnexBOBUWriter::new(vec![]);
+        writer.write_itu_t_t35(header, &[0xaa]).unwrap();
+        // obu_length, obu_type 5 without size field, then the same metadata
+        assert!(writer.into_inner() == [8, 0x28, 4, 0xff, 0x12, 0x34, 0x56, 0xaa, 0x80]);
+    }
+}
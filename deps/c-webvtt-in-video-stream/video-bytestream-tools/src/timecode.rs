@@ -0,0 +1,504 @@
+//! SMPTE style timecodes, written as AV1 timecode metadata OBUs, H.264 `pic_timing` SEI
+//! messages or H.265/H.266 `time_code` SEI messages.
+//!
+//! The H.264 `pic_timing` syntax depends on the active SPS, so H.264 writers don't implement
+//! [`TimecodeWrite`] themselves, see [`H264PicTimingWriter`]. An SPS that allows a timecode
+//! also requires a `pic_timing` in every access unit, which the encoder usually writes, so
+//! this only fits pipelines that leave `pic_timing` to the caller.
+//!
+//! `time_code` is a prefix SEI message in H.265 and H.266, write it into prefix SEI NAL units
+//! only.
+
+use crate::sei::{SeiMessage, SeiWrite, PIC_TIMING, TIME_CODE};
+use bitstream_io::{BigEndian, BitWrite, BitWriter};
+use h264_reader::nal::sps::{SeqParameterSet, VuiParameters};
+use std::time::Duration;
+use thiserror::Error;
+
+/// `counting_type` without dropped `n_frames` values
+const COUNTING_TYPE_NO_DROP: u8 = 0;
+/// `counting_type` that drops the lowest `n_frames` values at the start of every minute except
+/// every tenth, i.e. NTSC drop frame
+const COUNTING_TYPE_DROP_FRAME: u8 = 4;
+
+#[derive(Error, Debug, Clone, Copy)]
+pub enum TimecodeRateError {
+    #[error("{0} frames per second can't be counted with a 9 bit n_frames")]
+    FrameRateOutOfRange(u16),
+    #[error("drop frame counting needs a multiple of 30 frames per second, got {0}")]
+    InvalidDropFrameRate(u16),
+}
+
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum H264PicTimingError {
+    #[error("pic_timing has no clock timestamps without pic_struct_present_flag")]
+    NoPicStruct,
+    #[error("pic_timing carries the HRD delays of the encoder")]
+    HrdDelays,
+}
+
+/// The `pic_timing` syntax of an H.264 stream, as signalled by its active SPS
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct H264PicTimingSyntax {
+    time_offset_length: u8,
+}
+
+impl H264PicTimingSyntax {
+    /// Fails if `pic_timing` can't carry a timecode with `sps`, or if it carries CPB and DPB
+    /// delays that only the encoder knows
+    pub fn from_sps(sps: &SeqParameterSet) -> Result<Self, H264PicTimingError> {
+        Self::from_vui(sps.vui_parameters.as_ref())
+    }
+
+    fn from_vui(vui: Option<&VuiParameters>) -> Result<Self, H264PicTimingError> {
+        let vui = vui
+            .filter(|vui| vui.pic_struct_present_flag)
+            .ok_or(H264PicTimingError::NoPicStruct)?;
+        if vui.nal_hrd_parameters.is_some() || vui.vcl_hrd_parameters.is_some() {
+            return Err(H264PicTimingError::HrdDelays);
+        }
+        // inferred without HRD parameters
+        Ok(Self {
+            time_offset_length: 24,
+        })
+    }
+}
+
+/// The nominal frame rate the frames of a timecode are counted in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimecodeRate {
+    frames_per_second: u16,
+    drop_frame: bool,
+}
+
+impl TimecodeRate {
+    pub fn new(frames_per_second: u16, drop_frame: bool) -> Result<Self, TimecodeRateError> {
+        if !(1..=512).contains(&frames_per_second) {
+            return Err(TimecodeRateError::FrameRateOutOfRange(frames_per_second));
+        }
+        if drop_frame && !frames_per_second.is_multiple_of(30) {
+            return Err(TimecodeRateError::InvalidDropFrameRate(frames_per_second));
+        }
+        Ok(Self {
+            frames_per_second,
+            drop_frame,
+        })
+    }
+
+    /// The rounded frame rate of `frame_time`, with drop frame counting for the NTSC rates
+    /// like 29.97 and 59.94 frames per second
+    pub fn from_frame_time(frame_time: Duration) -> Self {
+        let frame_rate = 1. / frame_time.as_secs_f64().max(1. / 512.);
+        let frames_per_second = (frame_rate.round() as u16).clamp(1, 512);
+        let drop_frame = frames_per_second.is_multiple_of(30)
+            && f64::from(frames_per_second) - frame_rate > 0.01;
+        Self {
+            frames_per_second,
+            drop_frame,
+        }
+    }
+
+    pub fn frames_per_second(&self) -> u16 {
+        self.frames_per_second
+    }
+
+    pub fn drop_frame(&self) -> bool {
+        self.drop_frame
+    }
+
+    /// `n_frames` values skipped at the start of a minute
+    fn dropped_frames_per_minute(&self) -> u64 {
+        if self.drop_frame {
+            u64::from(self.frames_per_second / 15)
+        } else {
+            0
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timecode {
+    hours: u8,
+    minutes: u8,
+    seconds: u8,
+    frames: u16,
+    drop_frame: bool,
+    /// Set on the first frame after skipped `n_frames` values
+    cnt_dropped: bool,
+    discontinuity: bool,
+}
+
+impl Timecode {
+    /// The timecode of the `frame_count`th frame since midnight, hours wrap around after 24
+    pub fn from_frame_count(frame_count: u64, rate: TimecodeRate) -> Self {
+        let frames_per_second = u64::from(rate.frames_per_second);
+        let dropped = rate.dropped_frames_per_minute();
+        let mut frame_number = frame_count;
+        if dropped > 0 {
+            let frames_per_minute = frames_per_second * 60 - dropped;
+            let frames_per_ten_minutes = frames_per_second * 600 - dropped * 9;
+            let ten_minutes = frame_count / frames_per_ten_minutes;
+            let remainder = frame_count % frames_per_ten_minutes;
+            frame_number += dropped * 9 * ten_minutes;
+            if remainder > dropped {
+                frame_number += dropped * ((remainder - dropped) / frames_per_minute);
+            }
+        }
+        let frames = u16::try_from(frame_number % frames_per_second).unwrap();
+        let total_seconds = frame_number / frames_per_second;
+        let seconds = u8::try_from(total_seconds % 60).unwrap();
+        let minutes = u8::try_from(total_seconds / 60 % 60).unwrap();
+        let hours = u8::try_from(total_seconds / 3600 % 24).unwrap();
+        Self {
+            hours,
+            minutes,
+            seconds,
+            frames,
+            drop_frame: rate.drop_frame,
+            cnt_dropped: dropped > 0
+                && seconds == 0
+                && !minutes.is_multiple_of(10)
+                && u64::from(frames) == dropped,
+            discontinuity: false,
+        }
+    }
+
+    /// The timecode of the frame at `time` since midnight, rounded to the closest frame
+    pub fn from_time_of_day(time: Duration, frame_time: Duration) -> Self {
+        let frame_time = frame_time.as_nanos().max(1);
+        let frame_count = (time.as_nanos() + frame_time / 2) / frame_time;
+        Self::from_frame_count(
+            u64::try_from(frame_count).unwrap_or(u64::MAX),
+            TimecodeRate::from_frame_time(Duration::from_nanos(
+                u64::try_from(frame_time).unwrap_or(u64::MAX),
+            )),
+        )
+    }
+
+    /// Marks this timecode as not following the one of the previous frame
+    pub fn with_discontinuity(self) -> Self {
+        Self {
+            discontinuity: true,
+            ..self
+        }
+    }
+
+    pub fn hours(&self) -> u8 {
+        self.hours
+    }
+
+    pub fn minutes(&self) -> u8 {
+        self.minutes
+    }
+
+    pub fn seconds(&self) -> u8 {
+        self.seconds
+    }
+
+    pub fn frames(&self) -> u16 {
+        self.frames
+    }
+
+    pub fn drop_frame(&self) -> bool {
+        self.drop_frame
+    }
+
+    pub fn discontinuity(&self) -> bool {
+        self.discontinuity
+    }
+
+    /// Writes `counting_type` up to `hours_value` of a clock timestamp with
+    /// `full_timestamp_flag` set
+    fn write_clock_timestamp<W: BitWrite>(
+        &self,
+        writer: &mut W,
+        n_frames_bits: u32,
+    ) -> std::io::Result<()> {
+        writer.write(
+            5,
+            if self.drop_frame {
+                COUNTING_TYPE_DROP_FRAME
+            } else {
+                COUNTING_TYPE_NO_DROP
+            },
+        )?;
+        writer.write_bit(true)?;
+        writer.write_bit(self.discontinuity)?;
+        writer.write_bit(self.cnt_dropped)?;
+        writer.write(n_frames_bits, self.frames)?;
+        writer.write(6, self.seconds)?;
+        writer.write(6, self.minutes)?;
+        writer.write(5, self.hours)
+    }
+
+    /// `pic_timing` for a single progressive frame
+    fn h264_pic_timing_payload(&self, syntax: H264PicTimingSyntax) -> std::io::Result<Vec<u8>> {
+        let mut writer = BitWriter::endian(vec![], BigEndian);
+        // pic_struct: frame
+        writer.write(4, 0)?;
+        // clock_timestamp_flag
+        writer.write_bit(true)?;
+        // ct_type: progressive
+        writer.write(2, 0)?;
+        // nuit_field_based_flag
+        writer.write_bit(false)?;
+        self.write_clock_timestamp(&mut writer, 8)?;
+        // time_offset
+        if syntax.time_offset_length > 0 {
+            writer.write(u32::from(syntax.time_offset_length), 0)?;
+        }
+        // bit_equal_to_one and bit_equal_to_zero up to the byte boundary
+        if !writer.byte_aligned() {
+            writer.write_bit(true)?;
+            writer.byte_align()?;
+        }
+        Ok(writer.into_writer())
+    }
+
+    fn h265_time_code_payload(&self) -> std::io::Result<[u8; 6]> {
+        let mut output = [0u8; 6];
+        let mut writer = BitWriter::endian(&mut output[..], BigEndian);
+        // num_clock_ts
+        writer.write(2, 1)?;
+        // clock_timestamp_flag
+        writer.write_bit(true)?;
+        // units_field_based_flag
+        writer.write_bit(false)?;
+        self.write_clock_timestamp(&mut writer, 9)?;
+        // time_offset_length
+        writer.write(5, 0)?;
+        writer.write_bit(true)?;
+        writer.byte_align()?;
+        assert!(writer.into_unwritten() == (0, 0));
+        Ok(output)
+    }
+
+    /// `metadata_timecode` including the trailing bits of the OBU, which start in its last byte
+    pub(crate) fn av1_metadata_payload(&self) -> std::io::Result<[u8; 5]> {
+        let mut output = [0u8; 5];
+        let mut writer = BitWriter::endian(&mut output[..], BigEndian);
+        self.write_clock_timestamp(&mut writer, 9)?;
+        // time_offset_length
+        writer.write(5, 0)?;
+        writer.write_bit(true)?;
+        writer.byte_align()?;
+        assert!(writer.into_unwritten() == (0, 0));
+        Ok(output)
+    }
+}
+
+pub trait TimecodeWrite {
+    fn write_timecode(&mut self, timecode: &Timecode) -> std::io::Result<()>;
+}
+
+/// Writes timecodes as H.264 `pic_timing` SEI messages with the syntax of the active SPS
+pub struct H264PicTimingWriter<'a, W: SeiWrite + ?Sized> {
+    writer: &'a mut W,
+    syntax: H264PicTimingSyntax,
+}
+
+impl<'a, W: SeiWrite + ?Sized> H264PicTimingWriter<'a, W> {
+    pub fn new(writer: &'a mut W, syntax: H264PicTimingSyntax) -> Self {
+        Self { writer, syntax }
+    }
+}
+
+impl<W: SeiWrite + ?Sized> TimecodeWrite for H264PicTimingWriter<'_, W> {
+    fn write_timecode(&mut self, timecode: &Timecode) -> std::io::Result<()> {
+        self.writer.write_sei_message(&SeiMessage::Raw {
+            payload_type: PIC_TIMING,
+            payload: &timecode.h264_pic_timing_payload(self.syntax)?,
+        })
+    }
+}
+
+/// Writes a `time_code` SEI message, which has the same syntax in H.265 and H.266, into a
+/// prefix SEI NAL unit
+pub(crate) fn write_h265_time_code<W: SeiWrite + ?Sized>(
+    writer: &mut W,
+    timecode: &Timecode,
+) -> std::io::Result<()> {
+    writer.write_sei_message(&SeiMessage::Raw {
+        payload_type: TIME_CODE,
+        payload: &timecode.h265_time_code_payload()?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        H264PicTimingError, H264PicTimingSyntax, H264PicTimingWriter, Timecode, TimecodeRate,
+        TimecodeWrite,
+    };
+    use crate::{
+        av1::OBUWriter,
+        h264::H264NalHeader,
+        h265::{H265NalHeader, UnitType},
+        h26x::{NalUnitWrite, RbspWrite},
+        sei::{PIC_TIMING, TIME_CODE},
+    };
+    use h264_reader::nal::{
+        pps::ParamSetId,
+        sei::{
+            pic_timing::{PicTiming, SecMinHour},
+            HeaderType, SeiMessage,
+        },
+        sps::{
+            ChromaInfo, FrameMbsFlags, HrdParameters, PicOrderCntType, SeqParameterSet,
+            VuiParameters,
+        },
+    };
+    use std::time::Duration;
+
+    fn hmsf(timecode: Timecode) -> (u8, u8, u8, u16) {
+        (
+            timecode.hours(),
+            timecode.minutes(),
+            timecode.seconds(),
+            timecode.frames(),
+        )
+    }
+
+    #[test]
+    fn rate_from_frame_time() {
+        let ntsc = TimecodeRate::from_frame_time(Duration::from_nanos(33_366_667));
+        assert!(ntsc == TimecodeRate::new(30, true).unwrap());
+        let pal = TimecodeRate::from_frame_time(Duration::from_millis(40));
+        assert!(pal == TimecodeRate::new(25, false).unwrap());
+        let sixty = TimecodeRate::from_frame_time(Duration::from_secs(1) / 60);
+        assert!(sixty == TimecodeRate::new(60, false).unwrap());
+        assert!(TimecodeRate::new(25, true).is_err());
+        assert!(TimecodeRate::new(0, false).is_err());
+    }
+
+    #[test]
+    fn drop_frame_counting() {
+        let rate = TimecodeRate::new(30, true).unwrap();
+        let timecode = Timecode::from_frame_count(1799, rate);
+        assert!(hmsf(timecode) == (0, 0, 59, 29));
+        let timecode = Timecode::from_frame_count(1800, rate);
+        assert!(hmsf(timecode) == (0, 1, 0, 2));
+        assert!(timecode.cnt_dropped);
+        // every tenth minute keeps its first two frame numbers
+        let timecode = Timecode::from_frame_count(17982, rate);
+        assert!(hmsf(timecode) == (0, 10, 0, 0));
+        assert!(!timecode.cnt_dropped);
+        // about an hour of 29.97 frames per second is exactly an hour of drop frame timecode
+        let timecode = Timecode::from_frame_count(107892, rate);
+        assert!(hmsf(timecode) == (1, 0, 0, 0));
+
+        let timecode =
+            Timecode::from_time_of_day(Duration::from_secs(3723), Duration::from_millis(40));
+        assert!(hmsf(timecode) == (1, 2, 3, 0));
+        assert!(!timecode.drop_frame());
+    }
+
+    fn sps_with_vui(vui_parameters: VuiParameters) -> SeqParameterSet {
+        SeqParameterSet {
+            profile_idc: 100.into(),
+            constraint_flags: 0.into(),
+            level_idc: 41,
+            seq_parameter_set_id: ParamSetId::from_u32(0).unwrap(),
+            chroma_info: ChromaInfo::default(),
+            log2_max_frame_num_minus4: 0,
+            pic_order_cnt: PicOrderCntType::TypeTwo,
+            max_num_ref_frames: 1,
+            gaps_in_frame_num_value_allowed_flag: false,
+            pic_width_in_mbs_minus1: 119,
+            pic_height_in_map_units_minus1: 67,
+            frame_mbs_flags: FrameMbsFlags::Frames,
+            direct_8x8_inference_flag: true,
+            frame_cropping: None,
+            vui_parameters: Some(vui_parameters),
+        }
+    }
+
+    #[test]
+    fn h264_pic_timing_syntax() {
+        let no_pic_struct = sps_with_vui(VuiParameters::default());
+        assert!(
+            H264PicTimingSyntax::from_sps(&no_pic_struct) == Err(H264PicTimingError::NoPicStruct)
+        );
+        let hrd = sps_with_vui(VuiParameters {
+            pic_struct_present_flag: true,
+            nal_hrd_parameters: Some(HrdParameters::default()),
+            ..VuiParameters::default()
+        });
+        assert!(H264PicTimingSyntax::from_sps(&hrd) == Err(H264PicTimingError::HrdDelays));
+    }
+
+    #[test]
+    fn h264_pic_timing() {
+        use crate::h264::H264ByteStreamWrite;
+
+        let timecode =
+            Timecode::from_frame_count(3723 * 25 + 4, TimecodeRate::new(25, false).unwrap());
+        let sps = sps_with_vui(VuiParameters {
+            pic_struct_present_flag: true,
+            ..VuiParameters::default()
+        });
+        let syntax = H264PicTimingSyntax::from_sps(&sps).unwrap();
+        let nal_header =
+            H264NalHeader::from_nal_unit_type_and_nal_ref_idc(h264_reader::nal::UnitType::SEI, 0)
+                .unwrap();
+        let mut output = vec![];
+        let mut writer = (&mut output)
+            .start_write_nal_unit()
+            .unwrap()
+            .write_nal_header(nal_header)
+            .unwrap();
+        H264PicTimingWriter::new(&mut writer, syntax)
+            .write_timecode(&timecode)
+            .unwrap();
+        writer.finish_rbsp().unwrap();
+        assert!(usize::from(output[1]) == PIC_TIMING);
+        assert!(output[2] == 9);
+        // the 24 bit time_offset inferred without HRD parameters follows the hours
+        let payload = [0x08, 0x04, 0x04, 0x0c, 0x20, 0x80, 0x00, 0x00, 0x40];
+        assert!(output[3..] == [&payload[..], &[0x80]].concat());
+
+        let message = SeiMessage {
+            payload_type: HeaderType::PicTiming,
+            payload: &payload,
+        };
+        let pic_timing = PicTiming::read(&sps, &message).unwrap();
+        let pic_struct = pic_timing.pic_struct.unwrap();
+        let clock_timestamp = pic_struct.clock_timestamps[0].as_ref().unwrap();
+        assert!(clock_timestamp.n_frames == 4);
+        assert!(clock_timestamp.smh == SecMinHour::SMH(3, 2, 1));
+        assert!(clock_timestamp.time_offset == Some(0));
+    }
+
+    #[test]
+    fn h265_time_code() {
+        use crate::h265::H265ByteStreamWrite;
+
+        let timecode =
+            Timecode::from_frame_count(3723 * 25 + 4, TimecodeRate::new(25, false).unwrap());
+        let nal_header =
+            H265NalHeader::from_nal_unit_type_and_nuh_ids(UnitType::PrefixSeiNut, 0, 0).unwrap();
+        let mut output = vec![];
+        let mut writer = (&mut output)
+            .start_write_nal_unit()
+            .unwrap()
+            .write_nal_header(nal_header)
+            .unwrap();
+        writer.write_timecode(&timecode).unwrap();
+        writer.finish_rbsp().unwrap();
+        assert!(usize::from(output[2]) == TIME_CODE);
+        assert!(output[3] == 6);
+        assert!(output[4..] == [0x60, 0x40, 0x20, 0x61, 0x04, 0x10, 0x80]);
+    }
+
+    #[test]
+    fn av1_timecode_metadata() {
+        let rate = TimecodeRate::new(30, true).unwrap();
+        // 10:01:00;02, right after two dropped frame numbers
+        let timecode = Timecode::from_frame_count(10 * 107892 + 1800, rate);
+        assert!(hmsf(timecode) == (10, 1, 0, 2));
+        let mut writer = OBUWriter::new(vec![]);
+        writer.write_timecode(&timecode).unwrap();
+        // obu_type 5 with size field, obu_size, metadata_type 5, payload ending in trailing bits
+        assert!(writer.into_inner() == [0x2a, 6, 5, 0x25, 0x01, 0x00, 0x0a, 0x81]);
+    }
+}
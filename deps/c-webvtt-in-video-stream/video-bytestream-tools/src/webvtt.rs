@@ -1,11 +1,12 @@
-use crate::h26x::Result;
 use byteorder::{BigEndian, WriteBytesExt};
 use std::{io::Write, time::Duration};
 use uuid::{uuid, Uuid};
 
-pub const USER_DATA_UNREGISTERED: usize = 5;
+pub use crate::sei::USER_DATA_UNREGISTERED;
 pub const HEADER_GUID: Uuid = uuid!("cc7124bd-5f1c-4592-b27a-e2d9d218ef9e");
 pub const PAYLOAD_GUID: Uuid = uuid!("a0cb4dd1-9db2-4635-a76b-1c9fefd6c37b");
+/// Video offsets are sent as 16 bits of milliseconds, they have to be less than this.
+pub const MAX_VIDEO_OFFSET: Duration = Duration::from_millis(1 << 16);
 
 trait WriteCStrExt: Write {
     fn write_c_str(&mut self, string: &str) -> std::io::Result<()> {
@@ -17,29 +18,8 @@ trait WriteCStrExt: Write {
 
 impl<W: Write + ?Sized> WriteCStrExt for W {}
 
-pub(crate) struct CountingSink {
-    count: usize,
-}
-
-impl CountingSink {
-    pub fn new() -> Self {
-        Self { count: 0 }
-    }
-
-    pub fn count(&self) -> usize {
-        self.count
-    }
-}
-
-impl Write for CountingSink {
-    fn write(&mut self, buf: &[u8]) -> Result<usize> {
-        self.count += buf.len();
-        Ok(buf.len())
-    }
-
-    fn flush(&mut self) -> Result<()> {
-        Ok(())
-    }
+fn c_str_size(string: &str) -> usize {
+    string.len() + 1
 }
 
 pub struct WebvttTrack<'a> {
@@ -52,64 +32,71 @@ pub struct WebvttTrack<'a> {
     pub characteristics: Option<&'a str>,
 }
 
-pub(crate) fn write_webvtt_header<W: Write + ?Sized>(
+fn webvtt_header_payload_size(subtitle_tracks: &[WebvttTrack]) -> usize {
+    let tracks_size: usize = subtitle_tracks
+        .iter()
+        .map(|track| {
+            1 + c_str_size(track.name)
+                + c_str_size(track.language)
+                + track.assoc_language.map_or(0, c_str_size)
+                + track.characteristics.map_or(0, c_str_size)
+        })
+        .sum();
+    HEADER_GUID.as_bytes().len() + 2 + 1 + 1 + tracks_size
+}
+
+fn write_webvtt_header_payload<W: ?Sized + Write>(
     writer: &mut W,
     max_latency_to_video: Duration,
     send_frequency_hz: u8,
     subtitle_tracks: &[WebvttTrack],
-    write_format_header: impl FnOnce(&mut W, usize) -> std::io::Result<()>,
 ) -> std::io::Result<()> {
-    fn inner<W: ?Sized + Write>(
-        writer: &mut W,
-        max_latency_to_video: Duration,
-        send_frequency_hz: u8,
-        subtitle_tracks: &[WebvttTrack],
-    ) -> std::io::Result<()> {
-        writer.write_all(HEADER_GUID.as_bytes())?;
-        writer.write_u16::<BigEndian>(max_latency_to_video.as_millis().try_into().unwrap())?;
-        writer.write_u8(send_frequency_hz)?;
-        writer.write_u8(subtitle_tracks.len().try_into().unwrap())?;
-        for track in subtitle_tracks {
-            let flags = {
-                let mut flags: u8 = 0;
-                if track.default {
-                    flags |= 0b1000_0000;
-                }
-                if track.autoselect {
-                    flags |= 0b0100_0000;
-                }
-                if track.forced {
-                    flags |= 0b0010_0000;
-                }
-                if track.assoc_language.is_some() {
-                    flags |= 0b0001_0000;
-                }
-                if track.characteristics.is_some() {
-                    flags |= 0b0000_1000;
-                }
-                flags
-            };
-            writer.write_u8(flags)?;
-            writer.write_c_str(track.name)?;
-            writer.write_c_str(track.language)?;
-            if let Some(assoc_language) = track.assoc_language {
-                writer.write_c_str(assoc_language)?;
+    writer.write_all(HEADER_GUID.as_bytes())?;
+    writer.write_u16::<BigEndian>(max_latency_to_video.as_millis().try_into().unwrap())?;
+    writer.write_u8(send_frequency_hz)?;
+    writer.write_u8(subtitle_tracks.len().try_into().unwrap())?;
+    for track in subtitle_tracks {
+        let flags = {
+            let mut flags: u8 = 0;
+            if track.default {
+                flags |= 0b1000_0000;
+            }
+            if track.autoselect {
+                flags |= 0b0100_0000;
+            }
+            if track.forced {
+                flags |= 0b0010_0000;
+            }
+            if track.assoc_language.is_some() {
+                flags |= 0b0001_0000;
             }
-            if let Some(characteristics) = track.characteristics {
-                writer.write_c_str(characteristics)?;
+            if track.characteristics.is_some() {
+                flags |= 0b0000_1000;
             }
+            flags
+        };
+        writer.write_u8(flags)?;
+        writer.write_c_str(track.name)?;
+        writer.write_c_str(track.language)?;
+        if let Some(assoc_language) = track.assoc_language {
+            writer.write_c_str(assoc_language)?;
+        }
+        if let Some(characteristics) = track.characteristics {
+            writer.write_c_str(characteristics)?;
         }
-        Ok(())
     }
-    let mut count = CountingSink::new();
-    inner(
-        &mut count,
-        max_latency_to_video,
-        send_frequency_hz,
-        subtitle_tracks,
-    )?;
-    write_format_header(writer, count.count())?;
-    inner(
+    Ok(())
+}
+
+pub(crate) fn write_webvtt_header<W: Write + ?Sized>(
+    writer: &mut W,
+    max_latency_to_video: Duration,
+    send_frequency_hz: u8,
+    subtitle_tracks: &[WebvttTrack],
+    write_format_header: impl FnOnce(&mut W, usize) -> std::io::Result<()>,
+) -> std::io::Result<()> {
+    write_format_header(writer, webvtt_header_payload_size(subtitle_tracks))?;
+    write_webvtt_header_payload(
         writer,
         max_latency_to_video,
         send_frequency_hz,
@@ -117,6 +104,53 @@ pub(crate) fn write_webvtt_header<W: Write + ?Sized>(
     )
 }
 
+/// A WebVTT header payload that is serialized once and can then be written repeatedly,
+/// only the codec specific framing is added on every write.
+#[derive(Debug, Clone)]
+pub struct SerializedWebvttHeader(Vec<u8>);
+
+impl SerializedWebvttHeader {
+    pub fn new(
+        max_latency_to_video: Duration,
+        send_frequency_hz: u8,
+        subtitle_tracks: &[WebvttTrack],
+    ) -> Self {
+        let mut payload = vec![];
+        write_webvtt_header_payload(
+            &mut payload,
+            max_latency_to_video,
+            send_frequency_hz,
+            subtitle_tracks,
+        )
+        .expect("writing to a Vec can't fail");
+        Self(payload)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+pub(crate) fn write_serialized_webvtt_header<W: Write + ?Sized>(
+    writer: &mut W,
+    header: &SerializedWebvttHeader,
+    write_format_header: impl FnOnce(&mut W, usize) -> std::io::Result<()>,
+) -> std::io::Result<()> {
+    write_format_header(writer, header.as_bytes().len())?;
+    writer.write_all(header.as_bytes())
+}
+
+fn webvtt_payload_size(webvtt_payload: &str) -> usize {
+    PAYLOAD_GUID.as_bytes().len() + 1 + 8 + 1 + 2 + c_str_size(webvtt_payload)
+}
+
+fn video_offset_error(video_offset: Duration) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidInput,
+        format!("video offset {video_offset:?} doesn't fit into 16 bits of milliseconds"),
+    )
+}
+
 pub(crate) fn write_webvtt_payload<W: Write + ?Sized>(
     writer: &mut W,
     track_index: u8,
@@ -126,41 +160,16 @@ pub(crate) fn write_webvtt_payload<W: Write + ?Sized>(
     webvtt_payload: &str, // TODO: replace with string type that checks for interior NULs
     write_format_header: impl FnOnce(&mut W, usize) -> std::io::Result<()>,
 ) -> std::io::Result<()> {
-    fn inner<W: ?Sized + Write>(
-        writer: &mut W,
-        track_index: u8,
-        chunk_number: u64,
-        chunk_version: u8,
-        video_offset: Duration,
-        webvtt_payload: &str,
-    ) -> std::io::Result<()> {
-        writer.write_all(PAYLOAD_GUID.as_bytes())?;
-        writer.write_u8(track_index)?;
-        writer.write_u64::<BigEndian>(chunk_number)?;
-        writer.write_u8(chunk_version)?;
-        writer.write_u16::<BigEndian>(video_offset.as_millis().try_into().unwrap())?;
-        writer.write_c_str(webvtt_payload)?;
-        Ok(())
-    }
-
-    let mut count = CountingSink::new();
-    inner(
-        &mut count,
-        track_index,
-        chunk_number,
-        chunk_version,
-        video_offset,
-        webvtt_payload,
-    )?;
-    write_format_header(writer, count.count())?;
-    inner(
-        writer,
-        track_index,
-        chunk_number,
-        chunk_version,
-        video_offset,
-        webvtt_payload,
-    )
+    let video_offset =
+        u16::try_from(video_offset.as_millis()).map_err(|_| video_offset_error(video_offset))?;
+    write_format_header(writer, webvtt_payload_size(webvtt_payload))?;
+    writer.write_all(PAYLOAD_GUID.as_bytes())?;
+    writer.write_u8(track_index)?;
+    writer.write_u64::<BigEndian>(chunk_number)?;
+    writer.write_u8(chunk_version)?;
+    writer.write_u16::<BigEndian>(video_offset)?;
+    writer.write_c_str(webvtt_payload)?;
+    Ok(())
 }
 
 pub trait WebvttWrite {
@@ -171,6 +180,13 @@ pub trait WebvttWrite {
         subtitle_tracks: &[WebvttTrack],
     ) -> std::io::Result<()>;
 
+    fn write_serialized_webvtt_header(
+        &mut self,
+        header: &SerializedWebvttHeader,
+    ) -> std::io::Result<()>;
+
+    /// Fails with [`std::io::ErrorKind::InvalidInput`] if `video_offset` is
+    /// [`MAX_VIDEO_OFFSET`] or more, nothing is written then.
     fn write_webvtt_payload(
         &mut self,
         track_index: u8,
@@ -0,0 +1,200 @@
+//! Export cues to subtitle and transcript formats other than WebVTT, for tools that don't
+//! accept it.
+//!
+//! The exporters take the cues of one track, e.g. from [`crate::WebvttMuxer::cue_history`] or
+//! reassembled from a received stream, and write them with their times unchanged. Cues are
+//! written in order of their start time.
+
+use crate::{format_timestamp, Cue};
+
+/// Split WebVTT cue text into the text between tags and the tags, without their `<` and `>`.
+enum CueTextPart<'a> {
+    Text(&'a str),
+    Tag(&'a str),
+}
+
+fn cue_text_parts(mut text: &str) -> impl Iterator<Item = CueTextPart<'_>> {
+    std::iter::from_fn(move || {
+        if text.is_empty() {
+            return None;
+        }
+        if let Some(tag) = text.strip_prefix('<') {
+            let end = tag.find('>').unwrap_or(tag.len());
+            text = tag.get(end + 1..).unwrap_or("");
+            return Some(CueTextPart::Tag(&tag[..end]));
+        }
+        let end = text.find('<').unwrap_or(text.len());
+        let (part, rest) = text.split_at(end);
+        text = rest;
+        Some(CueTextPart::Text(part))
+    })
+}
+
+/// Resolve the character references allowed in WebVTT cue text.
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&nbsp;", "\u{a0}")
+        .replace("&lrm;", "\u{200e}")
+        .replace("&rlm;", "\u{200f}")
+        .replace("&amp;", "&")
+}
+
+/// The cue text without markup, keeping the tags in `keep_tags` (e.g. `b`, `/b`).
+fn strip_markup(text: &str, keep_tags: &[&str]) -> String {
+    cue_text_parts(text)
+        .map(|part| match part {
+            CueTextPart::Text(text) => unescape(text),
+            CueTextPart::Tag(tag) => {
+                // drop classes and annotations, e.g. `<c.yellow>` or `<v Speaker>`
+                let name = tag.split(['.', ' ', '\t']).next().unwrap_or("");
+                if keep_tags.contains(&name) {
+                    format!("<{name}>")
+                } else {
+                    String::new()
+                }
+            }
+        })
+        .collect()
+}
+
+fn sorted(cues: &[Cue]) -> Vec<&Cue> {
+    let mut cues: Vec<&Cue> = cues.iter().collect();
+    cues.sort_by_key(|cue| cue.start_time);
+    cues
+}
+
+/// Write the cues as SubRip (`.srt`), keeping bold, italic and underline markup.
+pub fn write_srt(cues: &[Cue]) -> String {
+    let mut srt = String::new();
+    for (index, cue) in sorted(cues).into_iter().enumerate() {
+        srt.push_str(&format!(
+            "{}\n{} --> {}\n{}\n\n",
+            index + 1,
+            format_timestamp(cue.start_time, ','),
+            format_timestamp(cue.start_time + cue.duration, ','),
+            strip_markup(&cue.text, &["b", "/b", "i", "/i", "u", "/u"])
+        ));
+    }
+    srt
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Write the cues as a TTML document conforming to the IMSC1 Text Profile, with `language`
+/// as `xml:lang`.
+pub fn write_ttml(cues: &[Cue], language: &str) -> String {
+    let mut ttml = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <tt xmlns=\"http://www.w3.org/ns/ttml\" \
+         xmlns:ttp=\"http://www.w3.org/ns/ttml#parameter\" \
+         ttp:profile=\"http://www.w3.org/ns/ttml/profile/imsc1/text\" \
+         xml:lang=\"{}\">\n<body>\n<div>\n",
+        escape_xml(language).replace('"', "&quot;")
+    );
+    for cue in sorted(cues) {
+        let lines: Vec<String> = strip_markup(&cue.text, &[])
+            .lines()
+            .map(escape_xml)
+            .collect();
+        ttml.push_str(&format!(
+            "<p begin=\"{}\" end=\"{}\">{}</p>\n",
+            format_timestamp(cue.start_time, '.'),
+            format_timestamp(cue.start_time + cue.duration, '.'),
+            lines.join("<br/>")
+        ));
+    }
+    ttml.push_str("</div>\n</body>\n</tt>\n");
+    ttml
+}
+
+/// Write the cues as a plain-text transcript, one `[HH:MM:SS.mmm] text` line per cue.
+pub fn write_transcript(cues: &[Cue]) -> String {
+    let mut transcript = String::new();
+    for cue in sorted(cues) {
+        let text = strip_markup(&cue.text, &[]);
+        let lines: Vec<&str> = text.lines().map(str::trim).collect();
+        transcript.push_str(&format!(
+            "[{}] {}\n",
+            format_timestamp(cue.start_time, '.'),
+            lines.join(" ")
+        ));
+    }
+    transcript
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{write_srt, write_transcript, write_ttml};
+    use crate::{
+        tests::{add_cue, muxer_with_history},
+        Cue,
+    };
+    use std::time::Duration;
+
+    /// Two cues out of order, with markup, character references and a line break
+    fn cues() -> Vec<Cue> {
+        vec![
+            Cue {
+                id: 1,
+                start_time: Duration::from_millis(3_723_250),
+                duration: Duration::from_millis(1500),
+                text: "<v Narrator>Fish &amp; <c.yellow>chips</c>\n&lt;3</v>".to_owned(),
+            },
+            Cue {
+                id: 0,
+                start_time: Duration::from_millis(500),
+                duration: Duration::from_secs(2),
+                text: "<b>Bold</b> and <i.loud>italic</i>".to_owned(),
+            },
+        ]
+    }
+
+    #[test]
+    fn srt() {
+        assert!(
+            write_srt(&cues())
+                == "1\n00:00:00,500 --> 00:00:02,500\n<b>Bold</b> and <i>italic</i>\n\n\
+                    2\n01:02:03,250 --> 01:02:04,750\nFish & chips\n<3\n\n"
+        );
+    }
+
+    #[test]
+    fn ttml() {
+        let ttml = write_ttml(&cues(), "en\"US");
+        assert!(ttml.contains(" xml:lang=\"en&quot;US\">\n"));
+        assert!(ttml.ends_with(
+            "<body>\n<div>\n\
+             <p begin=\"00:00:00.500\" end=\"00:00:02.500\">Bold and italic</p>\n\
+             <p begin=\"01:02:03.250\" end=\"01:02:04.750\">Fish &amp; chips<br/>&lt;3</p>\n\
+             </div>\n</body>\n</tt>\n"
+        ));
+    }
+
+    #[test]
+    fn transcript() {
+        assert!(
+            write_transcript(&cues())
+                == "[00:00:00.500] Bold and italic\n[01:02:03.250] Fish & chips <3\n"
+        );
+        assert!(write_transcript(&[]).is_empty());
+    }
+
+    #[test]
+    fn srt_from_cue_history() {
+        let muxer = muxer_with_history();
+        muxer.pause(Duration::ZERO);
+        muxer.resume(Duration::from_secs(10));
+        add_cue(&muxer, 11., 12., "After the pause");
+        let cues = muxer
+            .cue_history(0, Duration::ZERO..Duration::MAX)
+            .ok()
+            .unwrap();
+        // times are on the cue timeline, without the paused time or the latency to video
+        assert!(write_srt(&cues) == "1\n00:00:01,000 --> 00:00:02,000\nAfter the pause\n\n");
+    }
+}
@@ -0,0 +1,262 @@
+//! Fragmented MP4 output of a subtitle track for CMAF and DASH, see [`WvttTrackWriter`].
+
+use crate::{CueHistoryError, InvalidWebvttTrack, WebvttMuxer};
+use std::time::Duration;
+use video_bytestream_tools::isobmff::{
+    write_wvtt_fragment, write_wvtt_init_segment, WvttCue, WvttSample, WvttTrackConfig,
+};
+
+/// Writes the cues of a [`WebvttMuxer`] track as an ISO/IEC 14496-30 wvtt track, cut into
+/// fragments that line up with the fragments of the video.
+///
+/// Times are on the video timeline relative to the first frame passed to the muxer. Cues
+/// show up with the same delay as in the video bytestream, i.e. a cue starting at cue time
+/// zero starts at the latency to video.
+///
+/// The cues are read from [`WebvttMuxer::cue_history`], so the muxer needs a history set with
+/// [`crate::WebvttMuxerBuilder::set_cue_history`] that keeps the cues of at least one fragment
+/// plus the latency to video, otherwise writing fails with [`crate::CueHistoryError::Evicted`].
+pub struct WvttTrackWriter {
+    track: u8,
+    track_id: u32,
+    timescale: u32,
+    next_sequence_number: u32,
+    fragment_start: Duration,
+}
+
+impl WvttTrackWriter {
+    /// `track_id` and `timescale` are those of the wvtt track in the MP4 output.
+    pub fn new(track: u8, track_id: u32, timescale: u32) -> Self {
+        Self {
+            track,
+            track_id,
+            timescale,
+            next_sequence_number: 1,
+            fragment_start: Duration::ZERO,
+        }
+    }
+
+    /// Writes the `ftyp` and `moov` of the track, named and tagged after the muxer track.
+    pub fn write_init_segment(
+        &self,
+        muxer: &WebvttMuxer,
+        output: &mut Vec<u8>,
+    ) -> Result<(), InvalidWebvttTrack> {
+        let metadata = muxer.track_metadata(self.track)?;
+        write_wvtt_init_segment(
+            output,
+            &WvttTrackConfig {
+                track_id: self.track_id,
+                timescale: self.timescale,
+                language: &metadata.language,
+                name: &metadata.name,
+            },
+        );
+        Ok(())
+    }
+
+    fn to_ticks(&self, time: Duration) -> u64 {
+        u64::try_from(time.as_nanos() * u128::from(self.timescale) / 1_000_000_000).unwrap()
+    }
+
+    /// Writes a `moof` and `mdat` covering the time from the end of the previous fragment to
+    /// `fragment_end`, usually the end of the video fragment it goes with. Nothing is written if
+    /// `fragment_end` isn't past the previous fragment.
+    ///
+    /// The history times keep increasing across [`crate::Discontinuity::RestartChunkNumbering`],
+    /// so the track continues where it left off.
+    pub fn write_fragment(
+        &mut self,
+        muxer: &WebvttMuxer,
+        fragment_end: Duration,
+        output: &mut Vec<u8>,
+    ) -> Result<(), CueHistoryError> {
+        let fragment_start = self.fragment_start;
+        if fragment_end <= fragment_start {
+            return Ok(());
+        }
+        let fragment_cues = muxer.video_timeline_cues(self.track, fragment_start..fragment_end)?;
+        let mut boundaries: Vec<Duration> = fragment_cues
+            .iter()
+            .flat_map(|cue| [cue.start_time, cue.start_time + cue.duration])
+            .filter(|time| (fragment_start..fragment_end).contains(time))
+            .chain([fragment_start, fragment_end])
+            .collect();
+        boundaries.sort();
+        boundaries.dedup();
+
+        let ids: Vec<String> = fragment_cues.iter().map(|cue| cue.id.to_string()).collect();
+        let samples: Vec<WvttSample> = boundaries
+            .windows(2)
+            .filter_map(|span| {
+                let [start, end] = [span[0], span[1]];
+                let duration = self.to_ticks(end) - self.to_ticks(start);
+                if duration == 0 {
+                    return None;
+                }
+                let cues = fragment_cues
+                    .iter()
+                    .zip(&ids)
+                    .filter(|(cue, _)| {
+                        cue.start_time < end && cue.start_time + cue.duration > start
+                    })
+                    .map(|(cue, id)| WvttCue {
+                        id: Some(id),
+                        settings: None,
+                        payload: &cue.text,
+                    })
+                    .collect();
+                Some(WvttSample {
+                    duration: u32::try_from(duration).unwrap(),
+                    cues,
+                })
+            })
+            .collect();
+        write_wvtt_fragment(
+            output,
+            self.track_id,
+            self.next_sequence_number,
+            self.to_ticks(fragment_start),
+            &samples,
+        );
+
+        self.next_sequence_number += 1;
+        self.fragment_start = fragment_end;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WvttTrackWriter;
+    use crate::{
+        tests::{add_cue, mux_frames, muxer_with_history, RecordingWriter},
+        WebvttMuxer,
+    };
+    use std::time::Duration;
+
+    /// Splits `data` into `(type, content)` of the boxes it contains
+    fn boxes(mut data: &[u8]) -> Vec<(&[u8], &[u8])> {
+        let mut boxes = vec![];
+        while !data.is_empty() {
+            let size = usize::try_from(u32::from_be_bytes(data[..4].try_into().unwrap())).unwrap();
+            boxes.push((&data[4..8], &data[8..size]));
+            data = &data[size..];
+        }
+        boxes
+    }
+
+    fn find<'a>(data: &'a [u8], box_type: &[u8]) -> &'a [u8] {
+        boxes(data)
+            .into_iter()
+            .find(|(t, _)| *t == box_type)
+            .unwrap()
+            .1
+    }
+
+    struct Fragment {
+        sequence_number: u32,
+        base_media_decode_time: u64,
+        /// Duration and `(iden, payl)` of the cues of every sample
+        samples: Vec<(u32, Vec<(String, String)>)>,
+    }
+
+    fn parse_fragment(fragment: &[u8]) -> Fragment {
+        let u32_at = |data: &[u8], offset: usize| {
+            u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+        };
+        let moof = find(fragment, b"moof");
+        let traf = find(moof, b"traf");
+        let trun = find(traf, b"trun");
+        let mut samples = find(fragment, b"mdat");
+        let sample_count = u32_at(trun, 4);
+        let samples = (0..sample_count)
+            .map(|sample| {
+                let offset = 12 + 8 * usize::try_from(sample).unwrap();
+                let size = usize::try_from(u32_at(trun, offset + 4)).unwrap();
+                let (sample_data, rest) = samples.split_at(size);
+                samples = rest;
+                let cues = boxes(sample_data)
+                    .into_iter()
+                    .filter(|(box_type, _)| *box_type == b"vttc")
+                    .map(|(_, vttc)| {
+                        let text =
+                            |box_type| String::from_utf8(find(vttc, box_type).to_vec()).unwrap();
+                        (text(b"iden"), text(b"payl"))
+                    })
+                    .collect();
+                (u32_at(trun, offset), cues)
+            })
+            .collect();
+        Fragment {
+            sequence_number: u32_at(find(moof, b"mfhd"), 4),
+            base_media_decode_time: u64::from_be_bytes(
+                find(traf, b"tfdt")[4..12].try_into().unwrap(),
+            ),
+            samples,
+        }
+    }
+
+    fn write_fragment(
+        track_writer: &mut WvttTrackWriter,
+        muxer: &WebvttMuxer,
+        fragment_end: f64,
+    ) -> Vec<u8> {
+        let mut output = vec![];
+        track_writer
+            .write_fragment(muxer, Duration::from_secs_f64(fragment_end), &mut output)
+            .ok()
+            .unwrap();
+        output
+    }
+
+    fn cue(id: &str, text: &str) -> (String, String) {
+        (id.to_owned(), text.to_owned())
+    }
+
+    #[test]
+    fn fragments_split_at_cue_boundaries() {
+        let muxer = muxer_with_history();
+        add_cue(&muxer, 0., 1., "First");
+        add_cue(&muxer, 0.5, 2., "Second");
+        // the cues were sent before the fragments are written
+        mux_frames(&muxer, &mut RecordingWriter::default(), Duration::ZERO, 75);
+        let mut track_writer = WvttTrackWriter::new(0, 2, 1000);
+
+        let fragment = parse_fragment(&write_fragment(&mut track_writer, &muxer, 2.));
+        assert!(fragment.sequence_number == 1);
+        assert!(fragment.base_media_decode_time == 0);
+        // delayed by the latency to video
+        assert!(
+            fragment.samples
+                == [
+                    (500, vec![]),
+                    (500, vec![cue("0", "First")]),
+                    (500, vec![cue("0", "First"), cue("1", "Second")]),
+                    (500, vec![cue("1", "Second")]),
+                ]
+        );
+
+        let fragment = parse_fragment(&write_fragment(&mut track_writer, &muxer, 3.));
+        assert!(fragment.sequence_number == 2);
+        assert!(fragment.base_media_decode_time == 2000);
+        assert!(fragment.samples == [(500, vec![cue("1", "Second")]), (500, vec![])]);
+
+        assert!(write_fragment(&mut track_writer, &muxer, 3.).is_empty());
+    }
+
+    #[test]
+    fn init_segment_from_track() {
+        let muxer = muxer_with_history();
+        let mut output = vec![];
+        WvttTrackWriter::new(0, 2, 1000)
+            .write_init_segment(&muxer, &mut output)
+            .ok()
+            .unwrap();
+        let mdia = find(find(find(&output, b"moov"), b"trak"), b"mdia");
+        assert!(find(mdia, b"elng") == b"\0\0\0\0en\0");
+        assert!(WvttTrackWriter::new(1, 2, 1000)
+            .write_init_segment(&muxer, &mut output)
+            .is_err());
+    }
+}
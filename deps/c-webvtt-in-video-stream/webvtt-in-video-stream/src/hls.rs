@@ -0,0 +1,277 @@
+//! Segmented WebVTT sidecar output for HLS, see [`HlsSubtitleWriter`].
+
+use crate::{push_webvtt_cue, CueHistoryError, TrackMetadata, WebvttMuxer};
+use std::time::Duration;
+
+/// A finished `.vtt` segment, see [`HlsSubtitleWriter::write_segments`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HlsSegment {
+    pub sequence_number: u64,
+    pub duration: Duration,
+    /// The complete WebVTT file.
+    pub content: String,
+}
+
+/// Writes the cues of a [`WebvttMuxer`] track as `.vtt` segments of an HLS subtitle
+/// rendition, instead of (or in addition to) muxing them into the video as SEI.
+///
+/// Times are on the video timeline relative to the first frame passed to the muxer, with
+/// cues delayed by the latency to video as in the video bytestream. The `X-TIMESTAMP-MAP` of
+/// every segment maps the start of that timeline to the MPEG-TS timestamp of the first frame.
+///
+/// The cues are read from [`WebvttMuxer::cue_history`], see [`crate::fmp4::WvttTrackWriter`]
+/// for the history needed. If the muxer isn't used for SEI output its queued cues are never
+/// consumed, call [`WebvttMuxer::expire_cues`] after writing segments.
+pub struct HlsSubtitleWriter {
+    track: u8,
+    segment_duration: Duration,
+    first_frame_mpegts: u64,
+    segment_durations: Vec<Duration>,
+    ended: bool,
+}
+
+impl HlsSubtitleWriter {
+    /// `first_frame_mpegts` is the 90 kHz presentation timestamp of the first frame in the
+    /// video segments.
+    pub fn new(track: u8, segment_duration: Duration, first_frame_mpegts: u64) -> Self {
+        Self {
+            track,
+            segment_duration,
+            first_frame_mpegts,
+            segment_durations: vec![],
+            ended: false,
+        }
+    }
+
+    fn segments_start(&self) -> Duration {
+        self.segment_durations.iter().sum()
+    }
+
+    fn write_segment(
+        &mut self,
+        muxer: &WebvttMuxer,
+        end: Duration,
+    ) -> Result<HlsSegment, CueHistoryError> {
+        let start = self.segments_start();
+        let cues = muxer.video_timeline_cues(self.track, start..end)?;
+        let mut content = format!(
+            "WEBVTT\nX-TIMESTAMP-MAP=MPEGTS:{},LOCAL:00:00:00.000\n\n",
+            self.first_frame_mpegts
+        );
+        // cues spanning several segments are repeated with their full timing, players merge them
+        for cue in &cues {
+            push_webvtt_cue(
+                &mut content,
+                cue.start_time,
+                cue.start_time + cue.duration,
+                &cue.text,
+            );
+        }
+        self.segment_durations.push(end - start);
+        Ok(HlsSegment {
+            sequence_number: u64::try_from(self.segment_durations.len() - 1).unwrap(),
+            duration: end - start,
+            content,
+        })
+    }
+
+    /// Writes the segments that are complete at `time`.
+    pub fn write_segments(
+        &mut self,
+        muxer: &WebvttMuxer,
+        time: Duration,
+    ) -> Result<Vec<HlsSegment>, CueHistoryError> {
+        let mut segments = vec![];
+        while !self.ended && self.segments_start() + self.segment_duration <= time {
+            let end = self.segments_start() + self.segment_duration;
+            segments.push(self.write_segment(muxer, end)?);
+        }
+        Ok(segments)
+    }
+
+    /// Writes the remaining segments up to `end_time` and ends the playlist.
+    pub fn finish(
+        &mut self,
+        muxer: &WebvttMuxer,
+        end_time: Duration,
+    ) -> Result<Vec<HlsSegment>, CueHistoryError> {
+        let mut segments = self.write_segments(muxer, end_time)?;
+        if !self.ended && end_time > self.segments_start() {
+            segments.push(self.write_segment(muxer, end_time)?);
+        }
+        self.ended = true;
+        Ok(segments)
+    }
+
+    /// The media playlist of the segments written so far, `segment_uri` maps a sequence number
+    /// to the URI of its segment.
+    pub fn media_playlist(&self, segment_uri: impl Fn(u64) -> String) -> String {
+        let target_duration =
+            self.segment_duration.as_secs() + u64::from(self.segment_duration.subsec_nanos() > 0);
+        let mut playlist = format!(
+            "#EXTM3U\n#EXT-X-VERSION:3\n#EXT-X-TARGETDURATION:{target_duration}\n\
+             #EXT-X-MEDIA-SEQUENCE:0\n"
+        );
+        for (sequence_number, duration) in (0..).zip(&self.segment_durations) {
+            playlist.push_str(&format!(
+                "#EXTINF:{:.3},\n{}\n",
+                duration.as_secs_f64(),
+                segment_uri(sequence_number)
+            ));
+        }
+        if self.ended {
+            playlist.push_str("#EXT-X-ENDLIST\n");
+        }
+        playlist
+    }
+}
+
+/// Quoted-string attribute values can't contain double quotes or line breaks.
+fn quoted(value: &str) -> String {
+    let value: String = value
+        .chars()
+        .filter(|c| !matches!(c, '"' | '\r' | '\n'))
+        .collect();
+    format!("\"{value}\"")
+}
+
+fn yes_no(value: bool) -> &'static str {
+    if value {
+        "YES"
+    } else {
+        "NO"
+    }
+}
+
+impl TrackMetadata {
+    /// The `EXT-X-MEDIA` tag of the track for a multivariant playlist, with the media playlist
+    /// at `uri` and belonging to the subtitle group `group_id`.
+    pub fn hls_media_tag(&self, group_id: &str, uri: &str) -> String {
+        let mut tag = format!(
+            "#EXT-X-MEDIA:TYPE=SUBTITLES,GROUP-ID={},NAME={}",
+            quoted(group_id),
+            quoted(&self.name)
+        );
+        if !self.language.is_empty() {
+            tag.push_str(&format!(",LANGUAGE={}", quoted(&self.language)));
+        }
+        if let Some(assoc_language) = &self.assoc_language {
+            tag.push_str(&format!(",ASSOC-LANGUAGE={}", quoted(assoc_language)));
+        }
+        tag.push_str(&format!(
+            ",DEFAULT={},AUTOSELECT={},FORCED={}",
+            yes_no(self.default),
+            yes_no(self.autoselect),
+            yes_no(self.forced)
+        ));
+        if let Some(characteristics) = &self.characteristics {
+            tag.push_str(&format!(",CHARACTERISTICS={}", quoted(characteristics)));
+        }
+        tag.push_str(&format!(",URI={}", quoted(uri)));
+        tag
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HlsSubtitleWriter;
+    use crate::{
+        tests::{add_cue, mux_frames, muxer_with_history, RecordingWriter},
+        TrackMetadata,
+    };
+    use std::time::Duration;
+
+    #[test]
+    fn segments_include_sent_cues() {
+        let muxer = muxer_with_history();
+        add_cue(&muxer, 0., 1., "Hello");
+        let mut writer = RecordingWriter::default();
+        mux_frames(&muxer, &mut writer, Duration::ZERO, 50);
+        assert!(muxer.cues(0).ok().unwrap().is_empty());
+
+        let mut hls = HlsSubtitleWriter::new(0, Duration::from_secs(2), 0);
+        let segments = hls
+            .write_segments(&muxer, Duration::from_secs(2))
+            .ok()
+            .unwrap();
+        assert!(segments.len() == 1);
+        assert!(segments[0]
+            .content
+            .ends_with("\n\n00:00:00.500 --> 00:00:01.500\nHello\n\n"));
+    }
+
+    #[test]
+    fn segments_and_playlist() {
+        let muxer = muxer_with_history();
+        add_cue(&muxer, 1., 2.5, "Spanning");
+        let mut hls = HlsSubtitleWriter::new(0, Duration::from_millis(2500), 900);
+        let segments = hls
+            .write_segments(&muxer, Duration::from_secs(4))
+            .ok()
+            .unwrap();
+        assert!(segments.len() == 1);
+        let segments = [
+            segments,
+            hls.finish(&muxer, Duration::from_secs(4)).ok().unwrap(),
+        ]
+        .concat();
+        assert!(
+            segments
+                .iter()
+                .map(|segment| (segment.sequence_number, segment.duration))
+                .collect::<Vec<_>>()
+                == [
+                    (0, Duration::from_millis(2500)),
+                    (1, Duration::from_millis(1500))
+                ]
+        );
+        // a cue spanning both segments is repeated with its full timing
+        for segment in &segments {
+            assert!(
+                segment.content
+                    == "WEBVTT\nX-TIMESTAMP-MAP=MPEGTS:900,LOCAL:00:00:00.000\n\n\
+                        00:00:01.500 --> 00:00:03.000\nSpanning\n\n"
+            );
+        }
+        // nothing is written after the playlist ended
+        assert!(hls
+            .write_segments(&muxer, Duration::from_secs(10))
+            .ok()
+            .unwrap()
+            .is_empty());
+        assert!(
+            hls.media_playlist(|sequence_number| format!("{sequence_number}.vtt"))
+                == "#EXTM3U\n#EXT-X-VERSION:3\n#EXT-X-TARGETDURATION:3\n\
+                    #EXT-X-MEDIA-SEQUENCE:0\n#EXTINF:2.500,\n0.vtt\n#EXTINF:1.500,\n1.vtt\n\
+                    #EXT-X-ENDLIST\n"
+        );
+    }
+
+    #[test]
+    fn media_tag() {
+        let muxer = muxer_with_history();
+        let metadata = muxer.track_metadata(0).ok().unwrap();
+        assert!(
+            metadata.hls_media_tag("subs", "en.m3u8")
+                == "#EXT-X-MEDIA:TYPE=SUBTITLES,GROUP-ID=\"subs\",NAME=\"English\",\
+                    LANGUAGE=\"en\",DEFAULT=YES,AUTOSELECT=YES,FORCED=NO,URI=\"en.m3u8\""
+        );
+        let metadata = TrackMetadata {
+            default: false,
+            autoselect: false,
+            forced: true,
+            name: "Say \"hi\"\n".to_owned(),
+            language: String::new(),
+            assoc_language: Some("de".to_owned()),
+            characteristics: Some("public.accessibility.describes-music-and-sound".to_owned()),
+        };
+        // quotes and line breaks are dropped, an empty language is left out
+        assert!(
+            metadata.hls_media_tag("subs", "forced.m3u8")
+                == "#EXT-X-MEDIA:TYPE=SUBTITLES,GROUP-ID=\"subs\",NAME=\"Say hi\",\
+                    ASSOC-LANGUAGE=\"de\",DEFAULT=NO,AUTOSELECT=NO,FORCED=YES,\
+                    CHARACTERISTICS=\"public.accessibility.describes-music-and-sound\",\
+                    URI=\"forced.m3u8\""
+        );
+    }
+}
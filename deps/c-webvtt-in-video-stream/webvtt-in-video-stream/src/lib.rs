@@ -1,15 +1,42 @@
-use std::{collections::VecDeque, sync::Mutex, time::Duration};
-use video_bytestream_tools::webvtt::WebvttWrite;
+use std::{
+    collections::{BTreeMap, VecDeque},
+    ops::Range,
+    sync::Mutex,
+    time::Duration,
+};
+use video_bytestream_tools::{
+    cea708::{cc_count_for_frame_time, CaptionEncoder, CcData, CcDataWrite},
+    timecode::{Timecode, TimecodeWrite},
+    webvtt::{SerializedWebvttHeader, WebvttTrack, WebvttWrite, MAX_VIDEO_OFFSET},
+};
+
+pub use video_bytestream_tools::cea708::{CaptionMode, RollUpRows};
+
+pub mod export;
+pub mod fmp4;
+pub mod hls;
+pub mod mpeg_ts;
 
 pub struct WebvttMuxerBuilder {
     latency_to_video: Duration,
     send_frequency_hz: u8,
     video_frame_time: Duration,
+    catch_up_policy: CatchUpPolicy,
+    header_policy: HeaderPolicy,
     tracks: Vec<WebvttMuxerTrack>,
+    caption_track: Option<(u8, CaptionMode)>,
+    timecode_start: Option<Duration>,
+    cue_history: Option<CueHistory>,
 }
 
 struct WebvttMuxerTrack {
     cues: VecDeque<WebvttCue>,
+    /// Every cue added to the track, if enabled, see [`WebvttMuxer::cue_history`].
+    history: VecDeque<Cue>,
+    /// Longest cue in `history`, bounds how far before a time range overlapping cues start.
+    history_max_duration: Duration,
+    /// End of the latest cue dropped from a [`CueHistory::Bounded`] history.
+    history_evicted_until: Option<Duration>,
     default: bool,
     autoselect: bool,
     forced: bool,
@@ -23,6 +50,9 @@ pub struct WebvttMuxer {
     latency_to_video: Duration,
     send_frequency_hz: u8,
     video_frame_time: Duration,
+    catch_up_policy: CatchUpPolicy,
+    header_policy: HeaderPolicy,
+    cue_history: Option<CueHistory>,
     inner: Mutex<WebvttMuxerInner>,
 }
 
@@ -30,18 +60,259 @@ struct WebvttMuxerInner {
     tracks: Vec<WebvttMuxerTrack>,
     webvtt_buffer: String,
     next_chunk_number: u64,
-    first_video_timestamp: Option<Duration>,
+    first_video_timestamps: Option<VideoTimestamps>,
+    /// Timestamps of the most recent packet muxed outside of a pause.
+    last_video_timestamps: Option<VideoTimestamps>,
+    /// Set on resume, the video timeline skips the gap to the next packet.
+    resumed: bool,
+    /// Chunk number the video timeline was (re-)anchored at.
+    anchor_chunk_number: u64,
+    /// Subtracted from incoming cue times after the chunk numbering was restarted.
+    cue_time_base: Duration,
+    /// Forces a header with the next packet, regardless of the header policy.
+    header_pending: bool,
+    tracks_changed: bool,
+    last_header_decode_timestamp: Option<Duration>,
+    /// Serialized header for the current tracks, cleared when tracks change.
+    cached_header: Option<SerializedWebvttHeader>,
+    /// Finished pauses as `(start, end)` on the cue clock.
+    pauses: Vec<(Duration, Duration)>,
+    paused_at: Option<Duration>,
+    captions: Option<CaptionState>,
+    timecode: Option<TimecodeState>,
+    next_cue_id: u64,
+}
+
+/// CEA-608/708 output for the cues of a single track, see [`WebvttMuxer::try_mux_captions`].
+struct CaptionState {
+    track: u8,
+    encoder: CaptionEncoder,
+    /// Copies of the track's cues, the WebVTT chunks consume theirs on a different schedule.
+    cues: VecDeque<CaptionCue>,
+    next_cue_id: u64,
+    displayed_cue_id: Option<u64>,
+    /// Presentation timestamp of frame number zero, frames are numbered in display order.
+    frame_anchor: Option<Duration>,
+    /// The next frame number in display order without generated `cc_data`.
+    next_frame: Option<u64>,
+    /// `cc_data` generated ahead for frames that come later in decode order.
+    reordered_frames: BTreeMap<u64, Option<CcData>>,
+    /// Set once a frame arrived after a frame that is presented later.
+    reordered: bool,
+}
+
+/// Frames that can come later in decode order than a frame presented after them, the
+/// largest decoded picture buffer of H.264 and H.265.
+const MAX_REORDERED_FRAMES: u64 = 16;
+
+struct CaptionCue {
+    id: u64,
+    start_time: Duration,
+    end_time: Duration,
+    text: String,
+}
+
+impl CaptionState {
+    /// Queue the caption changes for a frame at `cue_time` with the encoder.
+    fn update(&mut self, cue_time: Duration) {
+        while self
+            .cues
+            .front()
+            .is_some_and(|cue| cue.end_time <= cue_time)
+        {
+            self.cues.pop_front();
+        }
+        // the most recently started cue wins if cues overlap
+        let active_cue = self
+            .cues
+            .iter()
+            .take_while(|cue| cue.start_time <= cue_time)
+            .last();
+        if active_cue.map(|cue| cue.id) == self.displayed_cue_id {
+            return;
+        }
+        match active_cue {
+            Some(cue) => self.encoder.show(&cue.text),
+            None => self.encoder.clear(),
+        }
+        self.displayed_cue_id = active_cue.map(|cue| cue.id);
+    }
+
+    fn next_cc_data(&mut self, cue_time: Option<Duration>, cc_count: u8) -> Option<CcData> {
+        self.update(cue_time?);
+        if self.encoder.is_idle() {
+            return None;
+        }
+        Some(self.encoder.next_cc_data(cc_count))
+    }
+
+    /// The `cc_data` for `frame` in display order, `cue_time` maps frame numbers to the cue
+    /// timeline.
+    ///
+    /// Packets arrive in decode order, so with reordered frames the `cc_data` of the frames
+    /// that are skipped is generated ahead and kept until they arrive. Before any reordering
+    /// was seen, or for large gaps, skipped frames are assumed to be dropped instead.
+    fn cc_data_for_frame(
+        &mut self,
+        frame: u64,
+        cue_time: impl Fn(u64) -> Option<Duration>,
+        cc_count: u8,
+    ) -> Option<CcData> {
+        let next_frame = self.next_frame.unwrap_or(frame);
+        if frame < next_frame {
+            self.reordered = true;
+            return self.reordered_frames.remove(&frame).flatten();
+        }
+        let first_frame = if self.reordered && frame - next_frame <= MAX_REORDERED_FRAMES {
+            next_frame
+        } else {
+            frame
+        };
+        for skipped_frame in first_frame..frame {
+            let cc_data = self.next_cc_data(cue_time(skipped_frame), cc_count);
+            self.reordered_frames.insert(skipped_frame, cc_data);
+        }
+        self.reordered_frames
+            .retain(|&reordered_frame, _| reordered_frame + MAX_REORDERED_FRAMES >= frame);
+        self.next_frame = Some(frame + 1);
+        self.next_cc_data(cue_time(frame), cc_count)
+    }
+}
+
+/// Timecodes for the frames on the video timeline, see [`WebvttMuxer::try_mux_timecode`].
+struct TimecodeState {
+    /// Time of day of the first frame.
+    start: Duration,
+    /// Presentation timestamp the video timeline was anchored at, and the time since `start`
+    /// of that frame.
+    anchor: Option<(Duration, Duration)>,
+    /// Time since `start` of the most recent frame.
+    last_elapsed: Option<Duration>,
+    /// Number of finished pauses that were already skipped on the timecode.
+    pauses_seen: usize,
+}
+
+/// When to repeat the header that describes the subtitle tracks, see
+/// [`WebvttMuxer::try_mux_into_bytestream_with_header_policy`].
+///
+/// Independent of the policy, a header is always written with the first packet and after
+/// a discontinuity.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderPolicy {
+    /// With every keyframe, so receivers joining at a random access point find the tracks.
+    #[default]
+    EveryKeyframe,
+    /// Whenever the given time has passed since the last header, regardless of keyframes.
+    Interval(Duration),
+    /// With every packet that carries a chunk.
+    EveryChunk,
+    /// Only when tracks were added to the muxer.
+    OnTrackChanges,
+    /// Never repeat the header.
+    FirstPacketOnly,
+}
+
+/// How many cues to keep per track for [`WebvttMuxer::cue_history`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CueHistory {
+    Unbounded,
+    /// Keep the `max_cues` cues with the latest start times, older cues are dropped.
+    Bounded {
+        max_cues: usize,
+    },
+}
+
+/// What to do when more than one chunk is due for a single packet, e.g. because of a low
+/// frame rate or dropped frames.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum CatchUpPolicy {
+    /// Emit one chunk per packet, overdue chunks are emitted with later packets.
+    #[default]
+    OneChunkPerPacket,
+    /// Emit up to `max_chunks` due chunks with a single packet.
+    EmitMultiple { max_chunks: u32 },
+    /// Merge all due chunks into a single chunk, numbered like the most recent one.
+    Coalesce,
+    /// Only emit the most recent due chunk, the skipped chunks are reported as a gap with
+    /// [`CatchUp::Skipped`].
+    Skip,
+}
+
+/// How the muxer dealt with overdue chunks while muxing a packet.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum CatchUp {
+    /// At most one chunk was due.
+    #[default]
+    None,
+    /// One chunk was emitted, `overdue_chunks` are still pending.
+    Lagging { overdue_chunks: u64 },
+    /// `chunks` chunks were emitted, `overdue_chunks` are still pending because of
+    /// [`CatchUpPolicy::EmitMultiple`]'s `max_chunks`.
+    Emitted { chunks: u64, overdue_chunks: u64 },
+    /// `chunks` chunks were merged into a single chunk.
+    Coalesced { chunks: u64 },
+    /// `chunks` chunks were skipped, i.e. the chunk numbers right before the emitted chunk
+    /// are missing from the output.
+    Skipped { chunks: u64 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MuxOutcome {
+    pub data_written: bool,
+    pub catch_up: CatchUp,
+    /// Due chunks that were dropped because they lag further behind the video than the
+    /// wire format's [`MAX_VIDEO_OFFSET`] can express, before applying the [`CatchUpPolicy`].
+    pub expired_chunks: u64,
+}
+
+/// Who decides whether a packet carries the header
+#[derive(Clone, Copy)]
+enum HeaderRequest {
+    /// The caller, see [`WebvttMuxer::try_mux_into_bytestream`]
+    Caller { add_header: bool },
+    /// The [`HeaderPolicy`], see [`WebvttMuxer::try_mux_into_bytestream_with_header_policy`]
+    Policy { keyframe: bool },
+}
+
+#[derive(Clone, Copy)]
+struct VideoTimestamps {
+    presentation: Duration,
+    decode: Duration,
 }
 
 // TODO: this should probably be moved into video-bytestream-tools instead
 pub struct WebvttString(String);
 
 struct WebvttCue {
+    id: u64,
     start_time: Duration,
     duration: Duration,
     text: WebvttString,
 }
 
+/// A cue queued on a track, see [`WebvttMuxer::cues`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cue {
+    /// Unique within a muxer, increasing in the order the cues were added.
+    pub id: u64,
+    /// On the cue timeline, i.e. with paused time removed.
+    pub start_time: Duration,
+    pub duration: Duration,
+    pub text: String,
+}
+
+/// The properties a track was added with, see [`WebvttMuxer::track_metadata`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrackMetadata {
+    pub default: bool,
+    pub autoselect: bool,
+    pub forced: bool,
+    pub name: String,
+    pub language: String,
+    pub assoc_language: Option<String>,
+    pub characteristics: Option<String>,
+}
+
 pub struct NulError {
     pub string: String,
     pub nul_position: usize,
@@ -84,10 +355,45 @@ impl WebvttMuxerBuilder {
             latency_to_video,
             send_frequency_hz,
             video_frame_time,
+            catch_up_policy: CatchUpPolicy::default(),
+            header_policy: HeaderPolicy::default(),
             tracks: vec![],
+            caption_track: None,
+            timecode_start: None,
+            cue_history: None,
         }
     }
 
+    pub fn set_header_policy(&mut self, header_policy: HeaderPolicy) -> &mut Self {
+        self.header_policy = header_policy;
+        self
+    }
+
+    pub fn set_catch_up_policy(&mut self, catch_up_policy: CatchUpPolicy) -> &mut Self {
+        self.catch_up_policy = catch_up_policy;
+        self
+    }
+
+    /// Also emit the cues of `track` as CEA-608/708 captions, see
+    /// [`WebvttMuxer::try_mux_captions`].
+    pub fn set_caption_track(&mut self, track: u8, mode: CaptionMode) -> &mut Self {
+        self.caption_track = Some((track, mode));
+        self
+    }
+
+    /// Also emit a timecode for every frame, counting from `time_of_day` (e.g. the wall clock
+    /// time the recording started) at the first frame, see [`WebvttMuxer::try_mux_timecode`].
+    pub fn set_timecode_start(&mut self, time_of_day: Duration) -> &mut Self {
+        self.timecode_start = Some(time_of_day);
+        self
+    }
+
+    /// Keep the cues of every track after they were sent, see [`WebvttMuxer::cue_history`].
+    pub fn set_cue_history(&mut self, cue_history: CueHistory) -> &mut Self {
+        self.cue_history = Some(cue_history);
+        self
+    }
+
     // FIXME: split these arguments somehow?
     #[allow(clippy::too_many_arguments)]
     pub fn add_track(
@@ -100,7 +406,78 @@ impl WebvttMuxerBuilder {
         assoc_language: Option<WebvttString>,
         characteristics: Option<WebvttString>,
     ) -> Result<&mut Self, TooManySubtitleTracksError> {
-        if self.tracks.len() == 0xff {
+        WebvttMuxerTrack::push_onto(
+            &mut self.tracks,
+            default,
+            autoselect,
+            forced,
+            name,
+            language,
+            assoc_language,
+            characteristics,
+        )?;
+        Ok(self)
+    }
+
+    pub fn create_muxer(self) -> WebvttMuxer {
+        WebvttMuxer {
+            latency_to_video: self.latency_to_video,
+            send_frequency_hz: self.send_frequency_hz,
+            video_frame_time: self.video_frame_time,
+            catch_up_policy: self.catch_up_policy,
+            header_policy: self.header_policy,
+            cue_history: self.cue_history,
+            inner: Mutex::new(WebvttMuxerInner {
+                tracks: self.tracks,
+                webvtt_buffer: String::new(),
+                next_chunk_number: 0,
+                first_video_timestamps: None,
+                last_video_timestamps: None,
+                resumed: false,
+                anchor_chunk_number: 0,
+                cue_time_base: Duration::ZERO,
+                header_pending: true,
+                tracks_changed: false,
+                last_header_decode_timestamp: None,
+                cached_header: None,
+                pauses: vec![],
+                paused_at: None,
+                captions: self.caption_track.map(|(track, mode)| CaptionState {
+                    track,
+                    encoder: CaptionEncoder::new(mode),
+                    cues: VecDeque::new(),
+                    next_cue_id: 0,
+                    displayed_cue_id: None,
+                    frame_anchor: None,
+                    next_frame: None,
+                    reordered_frames: BTreeMap::new(),
+                    reordered: false,
+                }),
+                timecode: self.timecode_start.map(|start| TimecodeState {
+                    start,
+                    anchor: None,
+                    last_elapsed: None,
+                    pauses_seen: 0,
+                }),
+                next_cue_id: 0,
+            }),
+        }
+    }
+}
+
+impl WebvttMuxerTrack {
+    #[allow(clippy::too_many_arguments)]
+    fn push_onto(
+        tracks: &mut Vec<WebvttMuxerTrack>,
+        default: bool,
+        autoselect: bool,
+        forced: bool,
+        name: WebvttString,
+        language: WebvttString,
+        assoc_language: Option<WebvttString>,
+        characteristics: Option<WebvttString>,
+    ) -> Result<u8, TooManySubtitleTracksError> {
+        if tracks.len() == 0xff {
             return Err(TooManySubtitleTracksError {
                 name,
                 language,
@@ -108,8 +485,12 @@ impl WebvttMuxerBuilder {
                 characteristics,
             });
         }
-        self.tracks.push(WebvttMuxerTrack {
+        let track_index = u8::try_from(tracks.len()).unwrap();
+        tracks.push(WebvttMuxerTrack {
             cues: VecDeque::new(),
+            history: VecDeque::new(),
+            history_max_duration: Duration::ZERO,
+            history_evicted_until: None,
             default,
             autoselect,
             forced,
@@ -118,26 +499,77 @@ impl WebvttMuxerBuilder {
             assoc_language: assoc_language.map(|a| a.0),
             characteristics: characteristics.map(|c| c.0),
         });
-        Ok(self)
+        Ok(track_index)
     }
+}
 
-    pub fn create_muxer(self) -> WebvttMuxer {
-        WebvttMuxer {
-            latency_to_video: self.latency_to_video,
-            send_frequency_hz: self.send_frequency_hz,
-            video_frame_time: self.video_frame_time,
-            inner: Mutex::new(WebvttMuxerInner {
-                tracks: self.tracks,
-                webvtt_buffer: String::new(),
-                next_chunk_number: 0,
-                first_video_timestamp: None,
-            }),
-        }
+/// `HH:MM:SS.mmm`, with `fraction_separator` instead of the `.` for formats that differ.
+fn format_timestamp(time: Duration, fraction_separator: char) -> String {
+    format!(
+        "{:0>2}:{:0>2}:{:0>2}{}{:0>3}",
+        time.as_secs() / 3600,
+        time.as_secs() % 3600 / 60,
+        time.as_secs() % 60,
+        fraction_separator,
+        time.as_millis() % 1000,
+    )
+}
+
+/// Append a cue in the WebVTT file format.
+fn push_webvtt_cue(buffer: &mut String, start: Duration, end: Duration, text: &str) {
+    buffer.push_str(&format!(
+        "{} --> {}\n{}\n\n",
+        format_timestamp(start, '.'),
+        format_timestamp(end, '.'),
+        text
+    ))
+}
+
+impl WebvttMuxerInner {
+    /// Map a time on the cue clock to the recorded timeline, times within a pause are
+    /// mapped to the start of that pause.
+    fn remove_paused_time(&self, time: Duration) -> Duration {
+        let open_pause = self.paused_at.map(|paused_at| (paused_at, Duration::MAX));
+        let paused: Duration = self
+            .pauses
+            .iter()
+            .chain(&open_pause)
+            .filter(|(start, _)| time > *start)
+            .map(|(start, end)| time.min(*end) - *start)
+            .sum();
+        time - paused
     }
 }
 
 pub struct InvalidWebvttTrack(pub u8);
 
+/// Why [`WebvttMuxer::cue_history`] can't return the cues of a time range.
+pub enum CueHistoryError {
+    InvalidTrack(InvalidWebvttTrack),
+    /// No history was enabled with [`WebvttMuxerBuilder::set_cue_history`].
+    Disabled,
+    /// A [`CueHistory::Bounded`] history already dropped cues that may overlap the time range.
+    Evicted,
+}
+
+impl From<InvalidWebvttTrack> for CueHistoryError {
+    fn from(error: InvalidWebvttTrack) -> Self {
+        Self::InvalidTrack(error)
+    }
+}
+
+/// How to recover from a break in the video timestamps, e.g. after an encoder restart,
+/// a timestamp wrap or a reconnect.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Discontinuity {
+    /// Keep the chunk numbering and cue timeline, and re-anchor the video timeline on the
+    /// next packet.
+    Rebase,
+    /// Restart the chunk numbering at zero; the cue timeline restarts at the position of the
+    /// next chunk.
+    RestartChunkNumbering,
+}
+
 impl WebvttMuxer {
     pub fn add_cue(
         &self,
@@ -147,11 +579,75 @@ impl WebvttMuxer {
         text: WebvttString,
     ) -> Result<(), InvalidWebvttTrack> {
         let mut inner = self.inner.lock().unwrap();
-        let tracks = &mut inner.tracks;
-        let track = tracks
-            .get_mut(usize::from(track))
-            .ok_or(InvalidWebvttTrack(track))?;
-        let cues = &mut track.cues;
+        let end_time = inner.remove_paused_time(start_time + duration);
+        let start_time = inner.remove_paused_time(start_time);
+        let cue_time_base = inner.cue_time_base;
+        if usize::from(track) >= inner.tracks.len() {
+            return Err(InvalidWebvttTrack(track));
+        }
+        if end_time == start_time && !duration.is_zero() {
+            // the cue lies entirely within a pause
+            return Ok(());
+        }
+        let Some((start_time, duration)) =
+            Self::rebase_cue(start_time, end_time - start_time, cue_time_base)
+        else {
+            return Ok(());
+        };
+        if let Some(captions) = inner
+            .captions
+            .as_mut()
+            .filter(|captions| captions.track == track)
+        {
+            let index = captions
+                .cues
+                .iter()
+                .position(|c| c.start_time > start_time)
+                .unwrap_or(captions.cues.len());
+            captions.cues.insert(
+                index,
+                CaptionCue {
+                    id: captions.next_cue_id,
+                    start_time,
+                    end_time: start_time + duration,
+                    text: text.0.clone(),
+                },
+            );
+            captions.next_cue_id += 1;
+        }
+        let id = inner.next_cue_id;
+        inner.next_cue_id += 1;
+        if let Some(cue_history) = self.cue_history {
+            let track = &mut inner.tracks[usize::from(track)];
+            let history_start_time = start_time + cue_time_base;
+            let index = track
+                .history
+                .partition_point(|c| c.start_time <= history_start_time);
+            track.history.insert(
+                index,
+                Cue {
+                    id,
+                    start_time: history_start_time,
+                    duration,
+                    text: text.0.clone(),
+                },
+            );
+            track.history_max_duration = track.history_max_duration.max(duration);
+            if let CueHistory::Bounded { max_cues } = cue_history {
+                while track.history.len() > max_cues {
+                    let Some(evicted) = track.history.pop_front() else {
+                        break;
+                    };
+                    let evicted_end = evicted.start_time + evicted.duration;
+                    track.history_evicted_until = Some(
+                        track
+                            .history_evicted_until
+                            .map_or(evicted_end, |until| until.max(evicted_end)),
+                    );
+                }
+            }
+        }
+        let cues = &mut inner.tracks[usize::from(track)].cues;
         let index = cues
             .iter()
             .position(|c| c.start_time > start_time)
@@ -159,6 +655,7 @@ impl WebvttMuxer {
         cues.insert(
             index,
             WebvttCue {
+                id,
                 start_time,
                 duration,
                 text,
@@ -167,6 +664,302 @@ impl WebvttMuxer {
         Ok(())
     }
 
+    /// Add a track to a running muxer and return its index.
+    ///
+    /// The header announcing the new track is written with the next packet, unless the
+    /// header policy is [`HeaderPolicy::FirstPacketOnly`] when muxing with
+    /// [`Self::try_mux_into_bytestream_with_header_policy`].
+    // FIXME: split these arguments somehow?
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_track(
+        &self,
+        default: bool,
+        autoselect: bool,
+        forced: bool,
+        name: WebvttString,
+        language: WebvttString,
+        assoc_language: Option<WebvttString>,
+        characteristics: Option<WebvttString>,
+    ) -> Result<u8, TooManySubtitleTracksError> {
+        let mut inner = self.inner.lock().unwrap();
+        let track_index = WebvttMuxerTrack::push_onto(
+            &mut inner.tracks,
+            default,
+            autoselect,
+            forced,
+            name,
+            language,
+            assoc_language,
+            characteristics,
+        )?;
+        inner.tracks_changed = true;
+        inner.cached_header = None;
+        Ok(track_index)
+    }
+
+    /// Move a cue onto a timeline starting at `base`, returns `None` if the cue ended
+    /// before `base`.
+    fn rebase_cue(
+        start_time: Duration,
+        duration: Duration,
+        base: Duration,
+    ) -> Option<(Duration, Duration)> {
+        let end_time = (start_time + duration).checked_sub(base)?;
+        let start_time = start_time.saturating_sub(base);
+        Some((start_time, end_time - start_time))
+    }
+
+    /// Signal a discontinuity in the video timestamps.
+    ///
+    /// A fresh header is written with the next packet. Cues that haven't expired yet are
+    /// kept (and re-timed for [`Discontinuity::RestartChunkNumbering`]) if `retain_cues`
+    /// is set, otherwise they are dropped.
+    pub fn signal_discontinuity(&self, discontinuity: Discontinuity, retain_cues: bool) {
+        let mut inner = self.inner.lock().unwrap();
+        let inner = &mut *inner;
+        inner.first_video_timestamps = None;
+        inner.last_video_timestamps = None;
+        inner.header_pending = true;
+        if let Some(timecode) = &mut inner.timecode {
+            timecode.anchor = None;
+        }
+        match discontinuity {
+            Discontinuity::Rebase => {
+                inner.anchor_chunk_number = inner.next_chunk_number;
+                if !retain_cues {
+                    for track in &mut inner.tracks {
+                        track.cues.clear();
+                    }
+                    if let Some(captions) = &mut inner.captions {
+                        captions.cues.clear();
+                    }
+                }
+            }
+            Discontinuity::RestartChunkNumbering => {
+                let restart_at =
+                    u32::try_from(inner.next_chunk_number).unwrap() * self.duration_between_sends();
+                inner.cue_time_base += restart_at;
+                inner.next_chunk_number = 0;
+                inner.anchor_chunk_number = 0;
+                for track in &mut inner.tracks {
+                    if retain_cues {
+                        track.cues = track
+                            .cues
+                            .drain(..)
+                            .filter_map(|cue| {
+                                let (start_time, duration) =
+                                    Self::rebase_cue(cue.start_time, cue.duration, restart_at)?;
+                                Some(WebvttCue {
+                                    id: cue.id,
+                                    start_time,
+                                    duration,
+                                    text: cue.text,
+                                })
+                            })
+                            .collect();
+                    } else {
+                        track.cues.clear();
+                    }
+                }
+                if let Some(captions) = &mut inner.captions {
+                    if retain_cues {
+                        captions.cues = captions
+                            .cues
+                            .drain(..)
+                            .filter_map(|cue| {
+                                let (start_time, duration) = Self::rebase_cue(
+                                    cue.start_time,
+                                    cue.end_time - cue.start_time,
+                                    restart_at,
+                                )?;
+                                Some(CaptionCue {
+                                    start_time,
+                                    end_time: start_time + duration,
+                                    ..cue
+                                })
+                            })
+                            .collect();
+                    } else {
+                        captions.cues.clear();
+                    }
+                }
+            }
+        }
+    }
+
+    /// Pause the muxer, e.g. because the recording was paused.
+    ///
+    /// No chunks are emitted until [`Self::resume`] is called, and cue times after
+    /// `cue_timestamp` (on the same clock as the cues passed to [`Self::add_cue`])
+    /// are shifted back by the paused duration.
+    pub fn pause(&self, cue_timestamp: Duration) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.paused_at.is_none() {
+            inner.paused_at = Some(cue_timestamp);
+        }
+    }
+
+    /// Resume after [`Self::pause`], `cue_timestamp` is on the same clock as the cues
+    /// passed to [`Self::add_cue`].
+    ///
+    /// The video timestamps may keep running through the pause, the gap between the last
+    /// packet before the pause and the first packet after it is skipped on the video timeline.
+    pub fn resume(&self, cue_timestamp: Duration) {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(paused_at) = inner.paused_at.take() {
+            inner.pauses.push((paused_at, cue_timestamp.max(paused_at)));
+            inner.resumed = true;
+        }
+    }
+
+    /// The cues of `track` that weren't completely sent yet, without consuming them.
+    pub fn cues(&self, track: u8) -> Result<Vec<Cue>, InvalidWebvttTrack> {
+        let inner = self.inner.lock().unwrap();
+        let track = inner
+            .tracks
+            .get(usize::from(track))
+            .ok_or(InvalidWebvttTrack(track))?;
+        Ok(track
+            .cues
+            .iter()
+            .map(|cue| Cue {
+                id: cue.id,
+                start_time: cue.start_time,
+                duration: cue.duration,
+                text: cue.text.0.clone(),
+            })
+            .collect())
+    }
+
+    /// The cues of `track` that overlap `time_range`, in order of their start times. Fails
+    /// unless a history was enabled with [`WebvttMuxerBuilder::set_cue_history`], or if a
+    /// bounded history already dropped cues that may overlap `time_range`.
+    ///
+    /// Unlike the queued cues, the history keeps cues after they were sent, e.g. for exporting
+    /// a recording with [`crate::export`]. Its times are on the cue timeline without the
+    /// restarts of [`Discontinuity::RestartChunkNumbering`], i.e. they keep increasing.
+    pub fn cue_history(
+        &self,
+        track: u8,
+        time_range: Range<Duration>,
+    ) -> Result<Vec<Cue>, CueHistoryError> {
+        let inner = self.inner.lock().unwrap();
+        let track = inner
+            .tracks
+            .get(usize::from(track))
+            .ok_or(InvalidWebvttTrack(track))?;
+        if self.cue_history.is_none() {
+            return Err(CueHistoryError::Disabled);
+        }
+        if track
+            .history_evicted_until
+            .is_some_and(|until| until > time_range.start)
+        {
+            return Err(CueHistoryError::Evicted);
+        }
+        // no cue lasts longer than `history_max_duration`, so cues starting earlier than that
+        // before the range can't overlap it
+        let first = track
+            .history
+            .partition_point(|cue| cue.start_time + track.history_max_duration <= time_range.start);
+        let end = track
+            .history
+            .partition_point(|cue| cue.start_time < time_range.end);
+        Ok(track
+            .history
+            .range(first..end.max(first))
+            .filter(|cue| cue.start_time + cue.duration > time_range.start)
+            .cloned()
+            .collect())
+    }
+
+    /// Queue the cues from the history of `track` that overlap `time_range` again with their
+    /// original times, e.g. to retransmit them after a header change. Cues that are still
+    /// queued and cues from before a restart of the chunk numbering aren't queued again.
+    ///
+    /// Only the part of the cues after the next chunk is sent, to replay a section at a later
+    /// time pass the cues from [`Self::cue_history`] to [`Self::add_cue`] instead.
+    pub fn replay_cues(
+        &self,
+        track: u8,
+        time_range: Range<Duration>,
+    ) -> Result<(), CueHistoryError> {
+        let history = self.cue_history(track, time_range)?;
+        let mut inner = self.inner.lock().unwrap();
+        let cue_time_base = inner.cue_time_base;
+        let cues = &mut inner.tracks[usize::from(track)].cues;
+        for cue in history {
+            if cues.iter().any(|queued| queued.id == cue.id) {
+                continue;
+            }
+            if cue.start_time < cue_time_base {
+                continue;
+            }
+            let start_time = cue.start_time - cue_time_base;
+            let index = cues
+                .iter()
+                .position(|c| c.start_time > start_time)
+                .unwrap_or(cues.len());
+            cues.insert(
+                index,
+                WebvttCue {
+                    id: cue.id,
+                    start_time,
+                    duration: cue.duration,
+                    text: WebvttString(cue.text),
+                },
+            );
+        }
+        Ok(())
+    }
+
+    /// The cues from the history of `track` that overlap `time_range` on the video timeline,
+    /// i.e. delayed by the latency to video, with their start times on that timeline.
+    pub(crate) fn video_timeline_cues(
+        &self,
+        track: u8,
+        time_range: Range<Duration>,
+    ) -> Result<Vec<Cue>, CueHistoryError> {
+        let latency_to_video = self.latency_to_video;
+        let start = time_range.start.saturating_sub(latency_to_video);
+        let end = time_range.end.saturating_sub(latency_to_video);
+        Ok(self
+            .cue_history(track, start..end)?
+            .into_iter()
+            .map(|cue| Cue {
+                start_time: cue.start_time + latency_to_video,
+                ..cue
+            })
+            .collect())
+    }
+
+    /// How long cues are delayed in the video bytestream, as passed to
+    /// [`WebvttMuxerBuilder::new`].
+    pub fn latency_to_video(&self) -> Duration {
+        self.latency_to_video
+    }
+
+    pub fn track_metadata(&self, track: u8) -> Result<TrackMetadata, InvalidWebvttTrack> {
+        let inner = self.inner.lock().unwrap();
+        let track = inner
+            .tracks
+            .get(usize::from(track))
+            .ok_or(InvalidWebvttTrack(track))?;
+        Ok(TrackMetadata {
+            default: track.default,
+            autoselect: track.autoselect,
+            forced: track.forced,
+            name: track.name.clone(),
+            language: track.language.clone(),
+            assoc_language: track.assoc_language.clone(),
+            characteristics: track.characteristics.clone(),
+        })
+    }
+
+    fn duration_between_sends(&self) -> Duration {
+        Duration::from_secs_f64(1. / f64::from(self.send_frequency_hz))
+    }
+
     fn consume_cues_into_chunk<'a>(
         cues: &mut VecDeque<WebvttCue>,
         timestamp: Duration,
@@ -193,85 +986,1054 @@ impl WebvttMuxer {
                 timestamp
             };
             let cue_end = (cue.start_time + cue.duration).min(timestamp + duration);
-            buffer.push_str(&format!(
-                "{:0>2}:{:0>2}:{:0>2}.{:0>3} --> {:0>2}:{:0>2}:{:0>2}.{:0>3}\n{}\n\n",
-                cue_start.as_secs() / 3600,
-                cue_start.as_secs() % 3600 / 60,
-                cue_start.as_secs() % 60,
-                cue_start.as_millis() % 1000,
-                cue_end.as_secs() / 3600,
-                cue_end.as_secs() % 3600 / 60,
-                cue_end.as_secs() % 60,
-                cue_end.as_millis() % 1000,
-                cue.text.0
-            ))
+            push_webvtt_cue(buffer, cue_start, cue_end, &cue.text.0);
         }
         buffer.as_str()
     }
 
+    /// Drop the cues that ended before `cue_timestamp`, for muxers that aren't used for SEI
+    /// output and so never consume their cues.
+    pub fn expire_cues(&self, cue_timestamp: Duration) {
+        let mut inner = self.inner.lock().unwrap();
+        for track in &mut inner.tracks {
+            track
+                .cues
+                .retain(|cue| cue.start_time + cue.duration >= cue_timestamp);
+        }
+    }
+
+    /// Write the CEA-608/708 captions for the frame at `presentation_timestamp` if a caption
+    /// track was set with [`WebvttMuxerBuilder::set_caption_track`], returns whether data was
+    /// written.
+    ///
+    /// Call this for every frame after muxing the WebVTT data for it. A cue starts showing on
+    /// the frame its WebVTT chunk is sent with, i.e. delayed by the latency to video. `cc_data`
+    /// is only written while there are caption changes to send, and follows the display order
+    /// of the frames if they arrive in decode order.
+    pub fn try_mux_captions(
+        &self,
+        presentation_timestamp: Duration,
+        writer: &mut impl CcDataWrite,
+    ) -> std::io::Result<bool> {
+        let mut inner = self.inner.lock().unwrap();
+        let inner = &mut *inner;
+        let Some(captions) = &mut inner.captions else {
+            return Ok(false);
+        };
+        let Some(first_video_timestamps) = inner.first_video_timestamps else {
+            return Ok(false);
+        };
+        if inner.paused_at.is_some() {
+            return Ok(false);
+        }
+        let anchor_time =
+            u32::try_from(inner.anchor_chunk_number).unwrap() * self.duration_between_sends();
+        if captions.frame_anchor != Some(first_video_timestamps.presentation) {
+            captions.frame_anchor = Some(first_video_timestamps.presentation);
+            captions.next_frame = None;
+            captions.reordered_frames.clear();
+        }
+        let frame_time = self.video_frame_time;
+        let frame = (presentation_timestamp
+            .saturating_sub(first_video_timestamps.presentation)
+            .as_nanos()
+            + frame_time.as_nanos() / 2)
+            / frame_time.as_nanos().max(1);
+        let cue_time = |frame: u64| {
+            (u32::try_from(frame).unwrap() * frame_time + anchor_time)
+                .checked_sub(self.latency_to_video)
+        };
+        let Some(cc_data) = captions.cc_data_for_frame(
+            u64::try_from(frame).unwrap(),
+            cue_time,
+            cc_count_for_frame_time(frame_time),
+        ) else {
+            return Ok(false);
+        };
+        writer.write_cc_data(&cc_data)?;
+        Ok(true)
+    }
+
+    /// Write the timecode of the frame at `presentation_timestamp` if a start time was set with
+    /// [`WebvttMuxerBuilder::set_timecode_start`], returns whether data was written.
+    ///
+    /// Call this for every frame. Timecodes follow the video timeline from the start time,
+    /// counted at the video frame rate (drop frame for 29.97 and 59.94 frames per second).
+    /// Paused time is skipped so that they stay aligned with the wall clock, and the first
+    /// frame after a pause or a discontinuity is flagged as discontinuous.
+    pub fn try_mux_timecode(
+        &self,
+        presentation_timestamp: Duration,
+        writer: &mut impl TimecodeWrite,
+    ) -> std::io::Result<bool> {
+        let mut inner = self.inner.lock().unwrap();
+        let inner = &mut *inner;
+        let Some(timecode) = &mut inner.timecode else {
+            return Ok(false);
+        };
+        if inner.paused_at.is_some() {
+            return Ok(false);
+        }
+        if timecode.pauses_seen != inner.pauses.len() {
+            let paused: Duration = inner.pauses[timecode.pauses_seen..]
+                .iter()
+                .map(|(start, end)| *end - *start)
+                .sum();
+            timecode.pauses_seen = inner.pauses.len();
+            if let Some(last_elapsed) = &mut timecode.last_elapsed {
+                *last_elapsed += paused;
+                timecode.anchor = None;
+            }
+        }
+        let (anchor_timestamp, anchor_elapsed, discontinuity) = match timecode.anchor {
+            Some((anchor_timestamp, anchor_elapsed)) => (anchor_timestamp, anchor_elapsed, false),
+            None => {
+                // continue right after the last frame
+                let anchor_elapsed = timecode
+                    .last_elapsed
+                    .map_or(Duration::ZERO, |last| last + self.video_frame_time);
+                timecode.anchor = Some((presentation_timestamp, anchor_elapsed));
+                (
+                    presentation_timestamp,
+                    anchor_elapsed,
+                    timecode.last_elapsed.is_some(),
+                )
+            }
+        };
+        let elapsed = anchor_elapsed + presentation_timestamp.saturating_sub(anchor_timestamp);
+        timecode.last_elapsed = Some(elapsed);
+        let mut frame_timecode =
+            Timecode::from_time_of_day(timecode.start + elapsed, self.video_frame_time);
+        if discontinuity {
+            frame_timecode = frame_timecode.with_discontinuity();
+        }
+        writer.write_timecode(&frame_timecode)?;
+        Ok(true)
+    }
+
+    /// Writes the header if `add_header` is set, and regardless of it with the first packet,
+    /// after a discontinuity and after tracks were added. The [`HeaderPolicy`] only applies
+    /// to [`Self::try_mux_into_bytestream_with_header_policy`].
     pub fn try_mux_into_bytestream(
         &self,
         video_timestamp: Duration,
         add_header: bool,
         writer: &mut impl WebvttWrite,
-    ) -> std::io::Result<bool> {
+    ) -> std::io::Result<MuxOutcome> {
+        self.try_mux_into_bytestream_with_decode_timestamp(
+            video_timestamp,
+            video_timestamp,
+            add_header,
+            writer,
+        )
+    }
+
+    /// Mux into a stream whose packets arrive in decode order, e.g. because of B-frames.
+    ///
+    /// Chunks are scheduled by `decode_timestamp`, which has to be monotonically increasing,
+    /// while the video offset of each chunk is computed against `presentation_timestamp`.
+    /// Both timestamps are anchored separately on the first packet, so they don't need to
+    /// share the same clock. `add_header` is handled like in [`Self::try_mux_into_bytestream`].
+    pub fn try_mux_into_bytestream_with_decode_timestamp(
+        &self,
+        presentation_timestamp: Duration,
+        decode_timestamp: Duration,
+        add_header: bool,
+        writer: &mut impl WebvttWrite,
+    ) -> std::io::Result<MuxOutcome> {
+        self.mux_into_bytestream(
+            presentation_timestamp,
+            decode_timestamp,
+            HeaderRequest::Caller { add_header },
+            writer,
+        )
+    }
+
+    /// Like [`Self::try_mux_into_bytestream_with_decode_timestamp`], but the header is
+    /// written as the [`HeaderPolicy`] of the muxer decides, given whether the packet is a
+    /// `keyframe`.
+    pub fn try_mux_into_bytestream_with_header_policy(
+        &self,
+        presentation_timestamp: Duration,
+        decode_timestamp: Duration,
+        keyframe: bool,
+        writer: &mut impl WebvttWrite,
+    ) -> std::io::Result<MuxOutcome> {
+        self.mux_into_bytestream(
+            presentation_timestamp,
+            decode_timestamp,
+            HeaderRequest::Policy { keyframe },
+            writer,
+        )
+    }
+
+    fn mux_into_bytestream(
+        &self,
+        presentation_timestamp: Duration,
+        decode_timestamp: Duration,
+        header_request: HeaderRequest,
+        writer: &mut impl WebvttWrite,
+    ) -> std::io::Result<MuxOutcome> {
         let mut inner = self.inner.lock().unwrap();
         let WebvttMuxerInner {
             tracks,
             webvtt_buffer,
             next_chunk_number,
-            first_video_timestamp,
+            first_video_timestamps,
+            last_video_timestamps,
+            resumed,
+            anchor_chunk_number,
+            cue_time_base: _,
+            header_pending,
+            tracks_changed,
+            last_header_decode_timestamp,
+            cached_header,
+            pauses: _,
+            paused_at,
+            captions: _,
+            timecode: _,
+            next_cue_id: _,
         } = &mut *inner;
 
+        if paused_at.is_some() {
+            return Ok(MuxOutcome {
+                data_written: false,
+                catch_up: CatchUp::None,
+                expired_chunks: 0,
+            });
+        }
+
+        if std::mem::take(resumed) {
+            if let (Some(first), Some(last)) =
+                (first_video_timestamps.as_mut(), *last_video_timestamps)
+            {
+                let gap = decode_timestamp.saturating_sub(last.decode + self.video_frame_time);
+                first.presentation += gap;
+                first.decode += gap;
+            }
+        }
+        *last_video_timestamps = Some(VideoTimestamps {
+            presentation: presentation_timestamp,
+            decode: decode_timestamp,
+        });
+
+        let duration_between_sends = self.duration_between_sends();
+        let first_video_timestamps = *first_video_timestamps.get_or_insert(VideoTimestamps {
+            presentation: presentation_timestamp,
+            decode: decode_timestamp,
+        });
+        let video_timeline_offset = |chunk_number: u64| {
+            u32::try_from(chunk_number - *anchor_chunk_number).unwrap() * duration_between_sends
+        };
+        let next_chunk_decode_timestamp = first_video_timestamps.decode
+            + self.latency_to_video
+            + video_timeline_offset(*next_chunk_number);
+        let latest_decode_timestamp = decode_timestamp + self.video_frame_time * 2;
+        let chunk_due = next_chunk_decode_timestamp <= latest_decode_timestamp;
+
+        let tracks_changed = std::mem::take(tracks_changed);
+        let add_header = std::mem::take(header_pending)
+            || match header_request {
+                HeaderRequest::Caller { add_header } => add_header || tracks_changed,
+                HeaderRequest::Policy { keyframe } => match self.header_policy {
+                    HeaderPolicy::EveryKeyframe => keyframe || tracks_changed,
+                    HeaderPolicy::Interval(interval) => {
+                        tracks_changed
+                            || last_header_decode_timestamp.is_none_or(|last_header| {
+                                decode_timestamp.saturating_sub(last_header) >= interval
+                            })
+                    }
+                    HeaderPolicy::EveryChunk => chunk_due || tracks_changed,
+                    HeaderPolicy::OnTrackChanges => tracks_changed,
+                    HeaderPolicy::FirstPacketOnly => false,
+                },
+            };
         if add_header {
-            // TODO: cache this? forward iter instead?
-            let webvtt_tracks = tracks
-                .iter()
-                .map(|track| video_bytestream_tools::webvtt::WebvttTrack {
-                    default: track.default,
-                    autoselect: track.autoselect,
-                    forced: track.forced,
-                    language: &track.language,
-                    name: &track.name,
-                    assoc_language: track.assoc_language.as_deref(),
-                    characteristics: track.characteristics.as_deref(),
-                })
-                .collect::<Vec<_>>();
-            writer.write_webvtt_header(
-                self.latency_to_video,
-                self.send_frequency_hz,
-                &webvtt_tracks,
-            )?;
-        }
-
-        let duration_between_sends =
-            Duration::from_secs_f64(1. / f64::from(self.send_frequency_hz));
-        let first_video_timestamp = &*first_video_timestamp.get_or_insert(video_timestamp);
-        let next_chunk_webvtt_timestamp =
-            u32::try_from(*next_chunk_number).unwrap() * duration_between_sends;
-        let next_chunk_video_timestamp =
-            *first_video_timestamp + self.latency_to_video + next_chunk_webvtt_timestamp;
-        if next_chunk_video_timestamp > video_timestamp + self.video_frame_time * 2 {
-            return Ok(add_header);
-        }
-        let chunk_number = *next_chunk_number;
-        // TODO: return an error type that allows skipping chunks if the writer fails?
-        for (track_index, track) in tracks.iter_mut().enumerate() {
-            let webvtt_payload = Self::consume_cues_into_chunk(
-                &mut track.cues,
-                next_chunk_webvtt_timestamp,
-                duration_between_sends,
-                webvtt_buffer,
+            let header = cached_header.get_or_insert_with(|| {
+                let webvtt_tracks = tracks
+                    .iter()
+                    .map(|track| WebvttTrack {
+                        default: track.default,
+                        autoselect: track.autoselect,
+                        forced: track.forced,
+                        language: &track.language,
+                        name: &track.name,
+                        assoc_language: track.assoc_language.as_deref(),
+                        characteristics: track.characteristics.as_deref(),
+                    })
+                    .collect::<Vec<_>>();
+                SerializedWebvttHeader::new(
+                    self.latency_to_video,
+                    self.send_frequency_hz,
+                    &webvtt_tracks,
+                )
+            });
+            writer.write_serialized_webvtt_header(header)?;
+            *last_header_decode_timestamp = Some(decode_timestamp);
+        }
+
+        if !chunk_due {
+            return Ok(MuxOutcome {
+                data_written: add_header,
+                catch_up: CatchUp::None,
+                expired_chunks: 0,
+            });
+        }
+        let mut due_chunks = 1 + u64::try_from(
+            (latest_decode_timestamp - next_chunk_decode_timestamp).as_nanos()
+                / duration_between_sends.as_nanos(),
+        )
+        .unwrap();
+
+        // Chunks starting at or before this point of the video timeline would need a video
+        // offset of `MAX_VIDEO_OFFSET` or more, so they are dropped with their cues.
+        let expired_chunks = presentation_timestamp
+            .saturating_sub(first_video_timestamps.presentation)
+            .checked_sub(MAX_VIDEO_OFFSET)
+            .map_or(0, |expired_until| {
+                let last_expired_chunk_number = *anchor_chunk_number
+                    + u64::try_from(expired_until.as_nanos() / duration_between_sends.as_nanos())
+                        .unwrap();
+                (last_expired_chunk_number + 1)
+                    .saturating_sub(*next_chunk_number)
+                    .min(due_chunks)
+            });
+        *next_chunk_number += expired_chunks;
+        due_chunks -= expired_chunks;
+        if due_chunks == 0 {
+            return Ok(MuxOutcome {
+                data_written: add_header,
+                catch_up: CatchUp::None,
+                expired_chunks,
+            });
+        }
+
+        let first_chunk_number = *next_chunk_number;
+        let last_chunk_number = first_chunk_number + due_chunks - 1;
+        // The payload of the first emitted chunk covers everything from `payload_start` on,
+        // which differs from the chunk number when coalescing.
+        let (chunk_numbers, mut payload_start, catch_up) = match self.catch_up_policy {
+            _ if due_chunks == 1 => (
+                first_chunk_number..first_chunk_number + 1,
+                first_chunk_number,
+                CatchUp::None,
+            ),
+            CatchUpPolicy::OneChunkPerPacket => (
+                first_chunk_number..first_chunk_number + 1,
+                first_chunk_number,
+                CatchUp::Lagging {
+                    overdue_chunks: due_chunks - 1,
+                },
+            ),
+            CatchUpPolicy::EmitMultiple { max_chunks } => {
+                let chunks = due_chunks.min(max_chunks.max(1).into());
+                (
+                    first_chunk_number..first_chunk_number + chunks,
+                    first_chunk_number,
+                    CatchUp::Emitted {
+                        chunks,
+                        overdue_chunks: due_chunks - chunks,
+                    },
+                )
+            }
+            CatchUpPolicy::Coalesce => (
+                last_chunk_number..last_chunk_number + 1,
+                first_chunk_number,
+                CatchUp::Coalesced { chunks: due_chunks },
+            ),
+            CatchUpPolicy::Skip => (
+                last_chunk_number..last_chunk_number + 1,
+                last_chunk_number,
+                CatchUp::Skipped {
+                    chunks: due_chunks - 1,
+                },
+            ),
+        };
+
+        for chunk_number in chunk_numbers.clone() {
+            let payload_timestamp = u32::try_from(payload_start).unwrap() * duration_between_sends;
+            let payload_duration =
+                u32::try_from(chunk_number + 1 - payload_start).unwrap() * duration_between_sends;
+            // Frames that are presented before the chunk start (possible with reordered frames
+            // or a latency shorter than two frame times) get attached with an offset of zero
+            // instead of underflowing.
+            let video_offset = presentation_timestamp.saturating_sub(
+                first_video_timestamps.presentation + video_timeline_offset(chunk_number),
             );
-            writer.write_webvtt_payload(
-                u8::try_from(track_index).unwrap(),
+            // TODO: return an error type that allows skipping chunks if the writer fails?
+            for (track_index, track) in tracks.iter_mut().enumerate() {
+                let webvtt_payload = Self::consume_cues_into_chunk(
+                    &mut track.cues,
+                    payload_timestamp,
+                    payload_duration,
+                    webvtt_buffer,
+                );
+                writer.write_webvtt_payload(
+                    u8::try_from(track_index).unwrap(),
+                    chunk_number,
+                    0,
+                    video_offset,
+                    webvtt_payload,
+                )?;
+            }
+            payload_start = chunk_number + 1;
+        }
+        *next_chunk_number = chunk_numbers.end;
+        Ok(MuxOutcome {
+            data_written: true,
+            catch_up,
+            expired_chunks,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        CaptionMode, CatchUp, CatchUpPolicy, CueHistory, CueHistoryError, Discontinuity,
+        HeaderPolicy, MuxOutcome, WebvttMuxer, WebvttMuxerBuilder, WebvttString,
+    };
+    use std::{collections::BTreeMap, time::Duration};
+    use video_bytestream_tools::{
+        cea708::{CcData, CcDataWrite},
+        webvtt::{SerializedWebvttHeader, WebvttTrack, WebvttWrite, MAX_VIDEO_OFFSET},
+    };
+
+    pub(crate) const FRAME_TIME: Duration = Duration::from_millis(40);
+    pub(crate) const LATENCY: Duration = Duration::from_millis(500);
+
+    pub(crate) struct Payload {
+        pub(crate) track_index: u8,
+        pub(crate) chunk_number: u64,
+        pub(crate) video_offset: Duration,
+        pub(crate) text: String,
+    }
+
+    /// Records what the muxer writes instead of serializing it.
+    #[derive(Default)]
+    pub(crate) struct RecordingWriter {
+        pub(crate) headers: Vec<Vec<u8>>,
+        pub(crate) payloads: Vec<Payload>,
+        pub(crate) cc_data: Vec<CcData>,
+    }
+
+    impl CcDataWrite for RecordingWriter {
+        fn write_cc_data(&mut self, cc_data: &CcData) -> std::io::Result<()> {
+            self.cc_data.push(cc_data.clone());
+            Ok(())
+        }
+    }
+
+    impl WebvttWrite for RecordingWriter {
+        fn write_webvtt_header(
+            &mut self,
+            max_latency_to_video: Duration,
+            send_frequency_hz: u8,
+            subtitle_tracks: &[WebvttTrack],
+        ) -> std::io::Result<()> {
+            let header = SerializedWebvttHeader::new(
+                max_latency_to_video,
+                send_frequency_hz,
+                subtitle_tracks,
+            );
+            self.write_serialized_webvtt_header(&header)
+        }
+
+        fn write_serialized_webvtt_header(
+            &mut self,
+            header: &SerializedWebvttHeader,
+        ) -> std::io::Result<()> {
+            self.headers.push(header.as_bytes().to_vec());
+            Ok(())
+        }
+
+        fn write_webvtt_payload(
+            &mut self,
+            track_index: u8,
+            chunk_number: u64,
+            _chunk_version: u8,
+            video_offset: Duration,
+            webvtt_payload: &str,
+        ) -> std::io::Result<()> {
+            assert!(video_offset < MAX_VIDEO_OFFSET);
+            self.payloads.push(Payload {
+                track_index,
                 chunk_number,
+                video_offset,
+                text: webvtt_payload.to_owned(),
+            });
+            Ok(())
+        }
+    }
+
+    pub(crate) fn string(string: &str) -> WebvttString {
+        WebvttString::from_string(string.to_owned()).ok().unwrap()
+    }
+
+    /// 25 frames per second, two chunks per second, with a single track.
+    pub(crate) fn builder() -> WebvttMuxerBuilder {
+        let mut builder = WebvttMuxerBuilder::new(LATENCY, 2, FRAME_TIME);
+        builder
+            .add_track(
+                true,
+                true,
+                false,
+                string("English"),
+                string("en"),
+                None,
+                None,
+            )
+            .ok()
+            .unwrap();
+        builder
+    }
+
+    /// [`builder`] with an unbounded cue history
+    pub(crate) fn muxer_with_history() -> WebvttMuxer {
+        let mut builder = builder();
+        builder.set_cue_history(CueHistory::Unbounded);
+        builder.create_muxer()
+    }
+
+    /// Mux `frames` frames in presentation order, starting at `start`.
+    pub(crate) fn mux_frames(
+        muxer: &WebvttMuxer,
+        writer: &mut RecordingWriter,
+        start: Duration,
+        frames: u32,
+    ) {
+        for frame in 0..frames {
+            let timestamp = start + frame * FRAME_TIME;
+            muxer
+                .try_mux_into_bytestream(timestamp, frame == 0, writer)
+                .unwrap();
+        }
+    }
+
+    /// Display order frame numbers in decode order, for an I-frame followed by P B B groups.
+    fn decode_order(frames: u32) -> Vec<u32> {
+        [0].into_iter()
+            .chain((0..).flat_map(|group| [3 * group + 3, 3 * group + 1, 3 * group + 2]))
+            .take(usize::try_from(frames).unwrap())
+            .collect()
+    }
+
+    /// Add a cue from `start` to `end` seconds.
+    pub(crate) fn add_cue(muxer: &WebvttMuxer, start: f64, end: f64, text: &str) {
+        muxer
+            .add_cue(
+                0,
+                Duration::from_secs_f64(start),
+                Duration::from_secs_f64(end - start),
+                string(text),
+            )
+            .ok()
+            .unwrap();
+    }
+
+    fn history_texts(muxer: &WebvttMuxer, start: f64, end: f64) -> Vec<String> {
+        let time_range = Duration::from_secs_f64(start)..Duration::from_secs_f64(end);
+        muxer
+            .cue_history(0, time_range)
+            .ok()
+            .unwrap()
+            .into_iter()
+            .map(|cue| cue.text)
+            .collect()
+    }
+
+    fn chunk_numbers(writer: &RecordingWriter) -> Vec<u64> {
+        writer
+            .payloads
+            .iter()
+            .map(|payload| payload.chunk_number)
+            .collect()
+    }
+
+    #[test]
+    fn pause_skips_video_gap() {
+        let muxer = builder().create_muxer();
+        let mut writer = RecordingWriter::default();
+        mux_frames(&muxer, &mut writer, Duration::ZERO, 50);
+        muxer.pause(Duration::from_secs(2));
+        // the video timestamps keep running while paused, no frames are muxed
+        muxer.resume(Duration::from_secs(72));
+        muxer
+            .add_cue(
                 0,
-                video_timestamp - (*first_video_timestamp + next_chunk_webvtt_timestamp),
-                webvtt_payload,
-            )?;
+                Duration::from_millis(72500),
+                Duration::from_millis(500),
+                string("After the pause"),
+            )
+            .ok()
+            .unwrap();
+        mux_frames(&muxer, &mut writer, Duration::from_secs(72), 50);
+
+        assert!(chunk_numbers(&writer) == (0..8).collect::<Vec<_>>());
+        // the cue is on the cue timeline with the pause removed
+        let payload = &writer.payloads[5];
+        assert!(payload.track_index == 0);
+        assert!(payload.text == "00:00:02.500 --> 00:00:03.000\nAfter the pause\n\n");
+        assert!(writer
+            .payloads
+            .iter()
+            .all(|payload| payload.video_offset < LATENCY));
+    }
+
+    #[test]
+    fn schedule_by_decode_timestamp() {
+        let muxer = builder().create_muxer();
+        let mut writer = RecordingWriter::default();
+        let chunk_start = |chunk_number: u64| u32::try_from(chunk_number).unwrap() * 500;
+        for (packet, display) in (0..).zip(decode_order(100)) {
+            let presentation_timestamp = display * FRAME_TIME;
+            let decode_timestamp = packet * FRAME_TIME;
+            let payloads = writer.payloads.len();
+            muxer
+                .try_mux_into_bytestream_with_decode_timestamp(
+                    presentation_timestamp,
+                    decode_timestamp,
+                    packet == 0,
+                    &mut writer,
+                )
+                .unwrap();
+            for payload in &writer.payloads[payloads..] {
+                // emitted with the first packet in decode order that the chunk is due for
+                let due = LATENCY + Duration::from_millis(chunk_start(payload.chunk_number).into());
+                assert!(decode_timestamp + 2 * FRAME_TIME >= due);
+                assert!(decode_timestamp + FRAME_TIME < due);
+                assert!(
+                    payload.video_offset
+                        == presentation_timestamp.saturating_sub(Duration::from_millis(
+                            chunk_start(payload.chunk_number).into()
+                        ))
+                );
+            }
         }
-        *next_chunk_number += 1;
-        Ok(true)
+        assert!(chunk_numbers(&writer) == (0..8).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn captions_follow_display_order() {
+        // the cc_data written for every frame, by display order frame number
+        let cc_data_by_frame = |frames: Vec<u32>| {
+            let mut builder = builder();
+            builder.set_caption_track(0, CaptionMode::PopOn);
+            let muxer = builder.create_muxer();
+            for (start, text) in [(0, "Hello world"), (2, "Second cue")] {
+                muxer
+                    .add_cue(
+                        0,
+                        Duration::from_secs(start),
+                        Duration::from_secs(2),
+                        string(text),
+                    )
+                    .ok()
+                    .unwrap();
+            }
+            let mut writer = RecordingWriter::default();
+            let mut cc_data_by_frame = BTreeMap::new();
+            for (packet, display) in (0..).zip(frames) {
+                let presentation_timestamp = display * FRAME_TIME;
+                muxer
+                    .try_mux_into_bytestream_with_decode_timestamp(
+                        presentation_timestamp,
+                        packet * FRAME_TIME,
+                        packet == 0,
+                        &mut writer,
+                    )
+                    .unwrap();
+                let cc_data = writer.cc_data.len();
+                muxer
+                    .try_mux_captions(presentation_timestamp, &mut writer)
+                    .unwrap();
+                cc_data_by_frame.insert(display, writer.cc_data[cc_data..].to_vec());
+            }
+            cc_data_by_frame
+        };
+        let in_display_order = cc_data_by_frame((0..148).collect());
+        let frames_with_cc_data = in_display_order
+            .values()
+            .filter(|cc_data| !cc_data.is_empty())
+            .count();
+        assert!(frames_with_cc_data > 20);
+        assert!(cc_data_by_frame(decode_order(148)) == in_display_order);
+    }
+
+    #[test]
+    fn cue_history_keeps_sent_cues() {
+        let muxer = muxer_with_history();
+        add_cue(&muxer, 2., 3., "Third");
+        add_cue(&muxer, 0., 1., "First");
+        add_cue(&muxer, 1., 2., "Second");
+        let mut writer = RecordingWriter::default();
+        mux_frames(&muxer, &mut writer, Duration::ZERO, 125);
+        assert!(muxer.cues(0).ok().unwrap().is_empty());
+
+        assert!(history_texts(&muxer, 0., 10.) == ["First", "Second", "Third"]);
+        // the range is half-open and cues overlapping it are included
+        assert!(history_texts(&muxer, 1., 2.) == ["Second"]);
+        assert!(history_texts(&muxer, 1.5, 2.5) == ["Second", "Third"]);
+        assert!(history_texts(&muxer, 3., 10.).is_empty());
+        assert!(muxer.cue_history(1, Duration::ZERO..Duration::MAX).is_err());
+    }
+
+    #[test]
+    fn bounded_cue_history_keeps_latest_cues() {
+        let muxer = {
+            let mut builder = builder();
+            builder.set_cue_history(CueHistory::Bounded { max_cues: 2 });
+            builder.create_muxer()
+        };
+        add_cue(&muxer, 2., 3., "Third");
+        add_cue(&muxer, 0., 1., "First");
+        add_cue(&muxer, 1., 2., "Second");
+        assert!(history_texts(&muxer, 1., 10.) == ["Second", "Third"]);
+        // the dropped cue overlaps the range
+        assert!(matches!(
+            muxer.cue_history(0, Duration::from_millis(500)..Duration::from_secs(10)),
+            Err(CueHistoryError::Evicted)
+        ));
+
+        // without a history only the queued cues are kept
+        let muxer = builder().create_muxer();
+        add_cue(&muxer, 0., 1., "First");
+        assert!(matches!(
+            muxer.cue_history(0, Duration::ZERO..Duration::MAX),
+            Err(CueHistoryError::Disabled)
+        ));
+        assert!(muxer.cues(0).ok().unwrap().len() == 1);
+    }
+
+    #[test]
+    fn cue_history_finds_long_cues() {
+        let muxer = muxer_with_history();
+        add_cue(&muxer, 1., 2., "Short");
+        add_cue(&muxer, 0., 10., "Long");
+        add_cue(&muxer, 5., 6., "Later");
+        assert!(history_texts(&muxer, 2., 3.) == ["Long"]);
+        assert!(history_texts(&muxer, 5.5, 7.) == ["Long", "Later"]);
+        assert!(history_texts(&muxer, 0., 1.5) == ["Long", "Short"]);
+    }
+
+    /// Mux two seconds, add a cue from 3 to 4 s and signal `discontinuity`, then mux another
+    /// three seconds with the video timestamps jumping to 100 s.
+    fn mux_across_discontinuity(
+        muxer: &WebvttMuxer,
+        discontinuity: Discontinuity,
+        retain_cues: bool,
+    ) -> RecordingWriter {
+        let mut writer = RecordingWriter::default();
+        mux_frames(muxer, &mut writer, Duration::ZERO, 50);
+        add_cue(muxer, 3., 4., "Across");
+        assert!(chunk_numbers(&writer) == [0, 1, 2, 3]);
+        muxer.signal_discontinuity(discontinuity, retain_cues);
+        mux_frames(muxer, &mut writer, Duration::from_secs(100), 75);
+        writer
+    }
+
+    fn payload_texts(writer: &RecordingWriter) -> Vec<&str> {
+        writer
+            .payloads
+            .iter()
+            .map(|payload| payload.text.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn rebase_keeps_chunk_numbering() {
+        let muxer = builder().create_muxer();
+        let writer = mux_across_discontinuity(&muxer, Discontinuity::Rebase, true);
+        assert!(chunk_numbers(&writer) == (0..10).collect::<Vec<_>>());
+        assert!(writer.headers.len() == 2);
+        assert!(
+            payload_texts(&writer)[6..8]
+                == [
+                    "00:00:03.000 --> 00:00:03.500\nAcross\n\n",
+                    "00:00:03.500 --> 00:00:04.000\nAcross\n\n"
+                ]
+        );
+        // the video timeline is anchored on the first packet after the discontinuity
+        assert!(writer.payloads[4].video_offset == writer.payloads[0].video_offset);
+
+        let muxer = builder().create_muxer();
+        let writer = mux_across_discontinuity(&muxer, Discontinuity::Rebase, false);
+        assert!(payload_texts(&writer).iter().all(|text| text.is_empty()));
+    }
+
+    #[test]
+    fn restart_chunk_numbering_retimes_cues() {
+        let muxer = builder().create_muxer();
+        let writer = mux_across_discontinuity(&muxer, Discontinuity::RestartChunkNumbering, true);
+        assert!(chunk_numbers(&writer) == [0, 1, 2, 3, 0, 1, 2, 3, 4, 5]);
+        assert!(writer.headers.len() == 2);
+        // the cue timeline restarted at 2 s
+        assert!(
+            payload_texts(&writer)[6..8]
+                == [
+                    "00:00:01.000 --> 00:00:01.500\nAcross\n\n",
+                    "00:00:01.500 --> 00:00:02.000\nAcross\n\n"
+                ]
+        );
+
+        let muxer = builder().create_muxer();
+        let writer = mux_across_discontinuity(&muxer, Discontinuity::RestartChunkNumbering, false);
+        assert!(payload_texts(&writer).iter().all(|text| text.is_empty()));
+    }
+
+    #[test]
+    fn restart_chunk_numbering_handles_captions() {
+        let frames_with_cc_data = |retain_cues: bool| {
+            let mut builder = builder();
+            builder.set_caption_track(0, CaptionMode::PopOn);
+            let muxer = builder.create_muxer();
+            let mut writer = RecordingWriter::default();
+            mux_frames(&muxer, &mut writer, Duration::ZERO, 50);
+            add_cue(&muxer, 3., 4., "Across");
+            muxer.signal_discontinuity(Discontinuity::RestartChunkNumbering, retain_cues);
+            (0..75)
+                .map(|frame| {
+                    let timestamp = Duration::from_secs(100) + frame * FRAME_TIME;
+                    muxer
+                        .try_mux_into_bytestream(timestamp, frame == 0, &mut writer)
+                        .unwrap();
+                    muxer.try_mux_captions(timestamp, &mut writer).unwrap()
+                })
+                .filter(|data_written| *data_written)
+                .count()
+        };
+        assert!(frames_with_cc_data(true) > 0);
+        assert!(frames_with_cc_data(false) == 0);
+    }
+
+    /// Mux ten frames, then a frame at 3 s with the frames in between dropped, so chunks 0 to 5
+    /// are due at once. A cue covers the first second.
+    fn mux_after_dropped_frames(
+        catch_up_policy: CatchUpPolicy,
+    ) -> (WebvttMuxer, RecordingWriter, MuxOutcome) {
+        let mut builder = builder();
+        builder.set_catch_up_policy(catch_up_policy);
+        let muxer = builder.create_muxer();
+        add_cue(&muxer, 0., 1., "Dropped");
+        let mut writer = RecordingWriter::default();
+        mux_frames(&muxer, &mut writer, Duration::ZERO, 10);
+        assert!(writer.payloads.is_empty());
+        let outcome = muxer
+            .try_mux_into_bytestream(Duration::from_secs(3), false, &mut writer)
+            .unwrap();
+        (muxer, writer, outcome)
+    }
+
+    #[test]
+    fn catch_up_one_chunk_per_packet() {
+        let (muxer, mut writer, outcome) =
+            mux_after_dropped_frames(CatchUpPolicy::OneChunkPerPacket);
+        assert!(outcome.catch_up == CatchUp::Lagging { overdue_chunks: 5 });
+        assert!(chunk_numbers(&writer) == [0]);
+        mux_frames(&muxer, &mut writer, Duration::from_secs(3) + FRAME_TIME, 5);
+        assert!(chunk_numbers(&writer) == (0..6).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn catch_up_emit_multiple() {
+        let (muxer, mut writer, outcome) =
+            mux_after_dropped_frames(CatchUpPolicy::EmitMultiple { max_chunks: 4 });
+        assert!(
+            outcome.catch_up
+                == CatchUp::Emitted {
+                    chunks: 4,
+                    overdue_chunks: 2
+                }
+        );
+        assert!(chunk_numbers(&writer) == [0, 1, 2, 3]);
+        let outcome = muxer
+            .try_mux_into_bytestream(Duration::from_secs(3) + FRAME_TIME, false, &mut writer)
+            .unwrap();
+        assert!(
+            outcome.catch_up
+                == CatchUp::Emitted {
+                    chunks: 2,
+                    overdue_chunks: 0
+                }
+        );
+        assert!(chunk_numbers(&writer) == (0..6).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn catch_up_coalesce() {
+        let (_, writer, outcome) = mux_after_dropped_frames(CatchUpPolicy::Coalesce);
+        assert!(outcome.catch_up == CatchUp::Coalesced { chunks: 6 });
+        assert!(chunk_numbers(&writer) == [5]);
+        // the merged chunk covers the cue times of all due chunks
+        assert!(writer.payloads[0].text == "00:00:00.000 --> 00:00:01.000\nDropped\n\n");
+    }
+
+    #[test]
+    fn catch_up_skip() {
+        let (muxer, mut writer, outcome) = mux_after_dropped_frames(CatchUpPolicy::Skip);
+        assert!(outcome.catch_up == CatchUp::Skipped { chunks: 5 });
+        assert!(chunk_numbers(&writer) == [5]);
+        assert!(writer.payloads[0].text.is_empty());
+        // the chunks after the gap follow the schedule again
+        mux_frames(&muxer, &mut writer, Duration::from_secs(3) + FRAME_TIME, 15);
+        assert!(chunk_numbers(&writer) == [5, 6]);
+    }
+
+    #[test]
+    fn expired_chunks_are_dropped() {
+        let muxer = builder().create_muxer();
+        add_cue(&muxer, 10., 11., "Expired");
+        add_cue(&muxer, 34.6, 35., "Kept");
+        let mut writer = RecordingWriter::default();
+        mux_frames(&muxer, &mut writer, Duration::ZERO, 20);
+        assert!(chunk_numbers(&writer) == [0]);
+        // chunks 1 to 198 are due, up to chunk 68 they start more than `MAX_VIDEO_OFFSET`
+        // before the frame
+        let outcome = muxer
+            .try_mux_into_bytestream(Duration::from_secs(100), false, &mut writer)
+            .unwrap();
+        assert!(outcome.expired_chunks == 68);
+        assert!(
+            outcome.catch_up
+                == CatchUp::Lagging {
+                    overdue_chunks: 130
+                }
+        );
+        assert!(chunk_numbers(&writer) == [0, 69]);
+        assert!(writer.payloads[1].video_offset == Duration::from_millis(65500));
+        assert!(payload_texts(&writer) == ["", "00:00:34.600 --> 00:00:35.000\nKept\n\n"]);
+    }
+
+    fn add_german_track(muxer: &WebvttMuxer) {
+        muxer
+            .add_track(
+                false,
+                false,
+                false,
+                string("Deutsch"),
+                string("de"),
+                None,
+                None,
+            )
+            .ok()
+            .unwrap();
+    }
+
+    /// The frames a header was written with over four seconds, with a keyframe every two
+    /// seconds and a track added with frame `add_track_at`.
+    fn header_frames(header_policy: HeaderPolicy, add_track_at: Option<u32>) -> Vec<u32> {
+        let mut builder = builder();
+        builder.set_header_policy(header_policy);
+        let muxer = builder.create_muxer();
+        let mut writer = RecordingWriter::default();
+        (0..100)
+            .filter(|frame| {
+                if add_track_at == Some(*frame) {
+                    add_german_track(&muxer);
+                }
+                let headers = writer.headers.len();
+                muxer
+                    .try_mux_into_bytestream_with_header_policy(
+                        *frame * FRAME_TIME,
+                        *frame * FRAME_TIME,
+                        frame % 50 == 0,
+                        &mut writer,
+                    )
+                    .unwrap();
+                writer.headers.len() > headers
+            })
+            .collect()
+    }
+
+    #[test]
+    fn header_policies() {
+        assert!(header_frames(HeaderPolicy::EveryKeyframe, None) == [0, 50]);
+        assert!(header_frames(HeaderPolicy::EveryKeyframe, Some(60)) == [0, 50, 60]);
+        assert!(
+            header_frames(HeaderPolicy::Interval(Duration::from_secs(1)), None) == [0, 25, 50, 75]
+        );
+        // the interval restarts with a header for added tracks
+        assert!(
+            header_frames(HeaderPolicy::Interval(Duration::from_secs(1)), Some(60))
+                == [0, 25, 50, 60, 85]
+        );
+        // with every chunk, chunk n is due with the first frame at or past 420 + n * 500 ms
+        assert!(
+            header_frames(HeaderPolicy::EveryChunk, None) == [0, 11, 23, 36, 48, 61, 73, 86, 98]
+        );
+        assert!(header_frames(HeaderPolicy::OnTrackChanges, None) == [0]);
+        assert!(header_frames(HeaderPolicy::OnTrackChanges, Some(60)) == [0, 60]);
+        assert!(header_frames(HeaderPolicy::FirstPacketOnly, Some(60)) == [0]);
+    }
+
+    #[test]
+    fn add_header_ignores_policy() {
+        let mut builder = builder();
+        builder.set_header_policy(HeaderPolicy::FirstPacketOnly);
+        let muxer = builder.create_muxer();
+        let mut writer = RecordingWriter::default();
+        let header_frames: Vec<u32> = (0..100)
+            .filter(|frame| {
+                if *frame == 60 {
+                    add_german_track(&muxer);
+                }
+                let headers = writer.headers.len();
+                muxer
+                    .try_mux_into_bytestream(*frame * FRAME_TIME, frame % 30 == 0, &mut writer)
+                    .unwrap();
+                writer.headers.len() > headers
+            })
+            .collect();
+        assert!(header_frames == [0, 30, 60, 90]);
+    }
+
+    #[test]
+    fn header_after_discontinuity() {
+        let mut builder = builder();
+        builder.set_header_policy(HeaderPolicy::FirstPacketOnly);
+        let muxer = builder.create_muxer();
+        let mut writer = RecordingWriter::default();
+        mux_frames(&muxer, &mut writer, Duration::ZERO, 50);
+        muxer.signal_discontinuity(Discontinuity::Rebase, true);
+        mux_frames(&muxer, &mut writer, Duration::from_secs(100), 50);
+        assert!(writer.headers.len() == 2);
+    }
+
+    #[test]
+    fn header_cache_reuse_and_invalidation() {
+        let mut builder = builder();
+        builder.set_header_policy(HeaderPolicy::EveryKeyframe);
+        let muxer = builder.create_muxer();
+        let mut writer = RecordingWriter::default();
+        let cached = |muxer: &WebvttMuxer| muxer.inner.lock().unwrap().cached_header.is_some();
+        assert!(!cached(&muxer));
+        for keyframe in 0..2 {
+            mux_frames(&muxer, &mut writer, keyframe * 50 * FRAME_TIME, 50);
+        }
+        assert!(cached(&muxer));
+        add_german_track(&muxer);
+        assert!(!cached(&muxer));
+        for keyframe in 2..4 {
+            mux_frames(&muxer, &mut writer, keyframe * 50 * FRAME_TIME, 50);
+        }
+
+        let track = |name, language, default| WebvttTrack {
+            default,
+            autoselect: default,
+            forced: false,
+            language,
+            name,
+            assoc_language: None,
+            characteristics: None,
+        };
+        let header = |tracks: &[WebvttTrack]| {
+            SerializedWebvttHeader::new(LATENCY, 2, tracks)
+                .as_bytes()
+                .to_vec()
+        };
+        let english = header(&[track("English", "en", true)]);
+        let both = header(&[track("English", "en", true), track("Deutsch", "de", false)]);
+        // the header is serialized again with the added track and then reused
+        assert!(writer.headers == [english.clone(), english, both.clone(), both]);
     }
 }
@@ -0,0 +1,312 @@
+//! Offline captioning of MPEG-TS recordings, see [`TransportStreamMuxer`].
+
+use crate::{Discontinuity, WebvttMuxer};
+use std::time::Duration;
+use video_bytestream_tools::{
+    cea708::CcDataWrite,
+    h264::{self, H264ByteStreamWrite, H264NalHeader},
+    h265::{self, H265ByteStreamWrite, H265NalHeader},
+    h266::{self, H266ByteStreamWrite, H266NalHeader},
+    h26x::{access_unit::VideoCodec, NalUnitWrite, RbspWrite},
+    mpeg_ts::{timestamp_to_duration, TsRemuxer, VideoAccessUnit},
+    webvtt::WebvttWrite,
+};
+
+pub use video_bytestream_tools::mpeg_ts::TsError;
+
+/// Inserts the SEI of a [`WebvttMuxer`] into every H.264, H.265 or H.266 access unit of an
+/// MPEG transport stream.
+///
+/// The muxer is driven with the PES timestamps and the `random_access_indicator` as keyframe
+/// flag. A decode timestamp that goes backwards, e.g. because the 33 bit timestamps wrapped,
+/// is signalled as a [`Discontinuity::Rebase`] that retains cues.
+pub struct TransportStreamMuxer<'a> {
+    muxer: &'a WebvttMuxer,
+    remuxer: TsRemuxer,
+    last_decode_timestamp: Option<u64>,
+}
+
+impl<'a> TransportStreamMuxer<'a> {
+    pub fn new(muxer: &'a WebvttMuxer) -> Self {
+        Self {
+            muxer,
+            remuxer: TsRemuxer::new(),
+            last_decode_timestamp: None,
+        }
+    }
+
+    /// Remuxes `input` into `output`, see [`TsRemuxer::push`].
+    pub fn push(&mut self, input: &[u8], output: &mut Vec<u8>) -> Result<(), TsError> {
+        let Self {
+            muxer,
+            remuxer,
+            last_decode_timestamp,
+        } = self;
+        remuxer.push(input, output, &mut |access_unit, sei| {
+            mux_access_unit(muxer, last_decode_timestamp, access_unit, sei)
+        })
+    }
+
+    /// Writes the rest of the stream, see [`TsRemuxer::finish`].
+    pub fn finish(self, output: &mut Vec<u8>) -> Result<(), TsError> {
+        let Self {
+            muxer,
+            remuxer,
+            mut last_decode_timestamp,
+        } = self;
+        remuxer.finish(output, &mut |access_unit, sei| {
+            mux_access_unit(muxer, &mut last_decode_timestamp, access_unit, sei)
+        })
+    }
+}
+
+fn mux_access_unit(
+    muxer: &WebvttMuxer,
+    last_decode_timestamp: &mut Option<u64>,
+    access_unit: &VideoAccessUnit,
+    sei: &mut Vec<u8>,
+) -> std::io::Result<()> {
+    let Some(presentation_timestamp) = access_unit.pts else {
+        return Ok(());
+    };
+    let decode_timestamp = access_unit.dts.unwrap_or(presentation_timestamp);
+    if last_decode_timestamp.is_some_and(|last| decode_timestamp < last) {
+        muxer.signal_discontinuity(Discontinuity::Rebase, true);
+    }
+    *last_decode_timestamp = Some(decode_timestamp);
+    let presentation_timestamp = timestamp_to_duration(presentation_timestamp);
+    let decode_timestamp = timestamp_to_duration(decode_timestamp);
+    let keyframe = access_unit.random_access;
+    let data_written = match access_unit.codec {
+        VideoCodec::H264 => {
+            let nal_header = H264NalHeader::from_nal_unit_type_and_nal_ref_idc(
+                h264_reader::nal::UnitType::SEI,
+                0,
+            )
+            .unwrap();
+            let writer = h264::annex_b::AnnexBWriter::new(&mut *sei)
+                .start_write_nal_unit()?
+                .write_nal_header(nal_header)?;
+            // the encoder writes the pic_timing that would carry a timecode itself
+            mux_into_sei(
+                muxer,
+                presentation_timestamp,
+                decode_timestamp,
+                keyframe,
+                writer,
+                |_| Ok(false),
+            )?
+        }
+        VideoCodec::H265 => {
+            let nal_header =
+                H265NalHeader::from_nal_unit_type_and_nuh_ids(h265::UnitType::PrefixSeiNut, 0, 0)
+                    .unwrap();
+            let writer = h265::annex_b::AnnexBWriter::new(&mut *sei)
+                .start_write_nal_unit()?
+                .write_nal_header(nal_header)?;
+            mux_into_sei(
+                muxer,
+                presentation_timestamp,
+                decode_timestamp,
+                keyframe,
+                writer,
+                |writer| muxer.try_mux_timecode(presentation_timestamp, writer),
+            )?
+        }
+        VideoCodec::H266 => {
+            let nal_header =
+                H266NalHeader::from_nal_unit_type_and_nuh_ids(h266::UnitType::PrefixSeiNut, 0, 0)
+                    .unwrap();
+            let writer = h266::annex_b::AnnexBWriter::new(&mut *sei)
+                .start_write_nal_unit()?
+                .write_nal_header(nal_header)?;
+            mux_into_sei(
+                muxer,
+                presentation_timestamp,
+                decode_timestamp,
+                keyframe,
+                writer,
+                |writer| muxer.try_mux_timecode(presentation_timestamp, writer),
+            )?
+        }
+    };
+    if !data_written {
+        // drop the start code and NAL header of the unused SEI NAL unit
+        sei.clear();
+    }
+    Ok(())
+}
+
+/// Writes everything the muxer has for this access unit into a single SEI NAL unit, with the
+/// timecode written by `mux_timecode`
+fn mux_into_sei<'a, W>(
+    muxer: &WebvttMuxer,
+    presentation_timestamp: Duration,
+    decode_timestamp: Duration,
+    keyframe: bool,
+    mut writer: W,
+    mux_timecode: impl FnOnce(&mut W) -> std::io::Result<bool>,
+) -> std::io::Result<bool>
+where
+    W: WebvttWrite + CcDataWrite + RbspWrite<&'a mut Vec<u8>>,
+{
+    let mut data_written = muxer
+        .try_mux_into_bytestream_with_header_policy(
+            presentation_timestamp,
+            decode_timestamp,
+            keyframe,
+            &mut writer,
+        )?
+        .data_written;
+    data_written |= muxer.try_mux_captions(presentation_timestamp, &mut writer)?;
+    data_written |= mux_timecode(&mut writer)?;
+    if data_written {
+        writer.finish_rbsp()?;
+    }
+    Ok(data_written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TransportStreamMuxer;
+    use crate::tests::{add_cue, builder};
+    use video_bytestream_tools::mpeg_ts::TS_PACKET_SIZE;
+
+    const PMT_PID: u16 = 0x1000;
+    const VIDEO_PID: u16 = 0x100;
+    const FRAME_TICKS: u64 = 3600;
+
+    /// A packet with `payload` at its end, stuffed with an adaptation field that carries the
+    /// `random_access_indicator`
+    fn packet(pid: u16, payload_unit_start: bool, random_access: bool, payload: &[u8]) -> Vec<u8> {
+        let [pid_high, pid_low] = pid.to_be_bytes();
+        let mut packet = vec![
+            0x47,
+            pid_high | u8::from(payload_unit_start) << 6,
+            pid_low,
+            0x30,
+        ];
+        packet.push(u8::try_from(TS_PACKET_SIZE - 5 - payload.len()).unwrap());
+        packet.push(if random_access { 0x40 } else { 0 });
+        packet.resize(TS_PACKET_SIZE - payload.len(), 0xff);
+        packet.extend_from_slice(payload);
+        packet
+    }
+
+    fn section_packet(pid: u16, section: &[u8]) -> Vec<u8> {
+        let mut payload = vec![0];
+        payload.extend_from_slice(section);
+        // CRC_32, which isn't checked
+        payload.extend_from_slice(&[0; 4]);
+        packet(pid, true, false, &payload)
+    }
+
+    /// A PAT and a PMT with H.264 video, then a frame of one packet for each of the 90 kHz
+    /// `timestamps`, the first one a keyframe
+    fn transport_stream(timestamps: impl Iterator<Item = u64>) -> Vec<u8> {
+        let mut stream = section_packet(0, &[0x00, 0xb0, 13, 0, 1, 0xc1, 0, 0, 0, 1, 0xf0, 0x00]);
+        stream.extend(section_packet(
+            PMT_PID,
+            &[
+                0x02, 0xb0, 18, 0, 1, 0xc1, 0, 0, 0xe1, 0x00, 0xf0, 0, 0x1b, 0xe1, 0x00, 0xf0, 0,
+            ],
+        ));
+        for (frame, pts) in timestamps.enumerate() {
+            let mut pes = vec![0, 0, 1, 0xe0, 0, 0, 0x80, 0x80, 5];
+            pes.extend_from_slice(&[
+                0x21 | (pts >> 29) as u8 & 0x0e,
+                (pts >> 22) as u8,
+                (pts >> 14) as u8 | 1,
+                (pts >> 7) as u8,
+                (pts << 1) as u8 | 1,
+            ]);
+            let slice = if frame == 0 { 0x65 } else { 0x41 };
+            pes.extend_from_slice(&[0, 0, 0, 1, 0x09, 0xf0, 0, 0, 1, slice, 0x88, 0x84, 0x21]);
+            let packet_length = u16::try_from(pes.len() - 6).unwrap();
+            pes[4..6].copy_from_slice(&packet_length.to_be_bytes());
+            stream.extend(packet(VIDEO_PID, true, frame == 0, &pes));
+        }
+        stream
+    }
+
+    /// The video PES packets of `stream`, checking the continuity counters of the video PID
+    fn video_pes_packets(stream: &[u8]) -> Vec<Vec<u8>> {
+        let mut pes_packets: Vec<Vec<u8>> = vec![];
+        let mut continuity_counter = 0;
+        for packet in stream.chunks_exact(TS_PACKET_SIZE) {
+            if u16::from_be_bytes([packet[1], packet[2]]) & 0x1fff != VIDEO_PID {
+                continue;
+            }
+            assert!(packet[3] & 0x0f == continuity_counter);
+            continuity_counter = (continuity_counter + 1) & 0x0f;
+            let payload_start = if packet[3] & 0x20 != 0 {
+                5 + usize::from(packet[4])
+            } else {
+                4
+            };
+            if packet[1] & 0x40 != 0 {
+                pes_packets.push(vec![]);
+            }
+            pes_packets
+                .last_mut()
+                .unwrap()
+                .extend_from_slice(&packet[payload_start..]);
+        }
+        pes_packets
+    }
+
+    fn remux(input: &[u8]) -> Vec<Vec<u8>> {
+        let muxer = builder().create_muxer();
+        add_cue(&muxer, 0., 2., "Hello");
+        let mut ts_muxer = TransportStreamMuxer::new(&muxer);
+        let mut output = vec![];
+        for chunk in input.chunks(1000) {
+            ts_muxer.push(chunk, &mut output).unwrap();
+        }
+        ts_muxer.finish(&mut output).unwrap();
+        assert!(output.len().is_multiple_of(TS_PACKET_SIZE));
+        let pes_packets = video_pes_packets(&output);
+        for pes in &pes_packets {
+            let packet_length = usize::from(u16::from_be_bytes([pes[4], pes[5]]));
+            assert!(packet_length == pes.len() - 6);
+        }
+        pes_packets
+    }
+
+    /// Indices of the PES packets containing `needle`
+    fn containing(pes_packets: &[Vec<u8>], needle: &[u8]) -> Vec<usize> {
+        (0..pes_packets.len())
+            .filter(|index| {
+                pes_packets[*index]
+                    .windows(needle.len())
+                    .any(|window| window == needle)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sei_inserted_into_access_units() {
+        let pes_packets = remux(&transport_stream(
+            (0..50).map(|frame| 90000 + frame * FRAME_TICKS),
+        ));
+        assert!(pes_packets.len() == 50);
+        // the header goes with the keyframe, chunks with the frames they're due for
+        assert!(containing(&pes_packets, b"English") == [0]);
+        assert!(containing(&pes_packets, b"Hello") == [11, 23, 36, 48]);
+        // the SEI NAL unit goes after the access unit delimiter
+        assert!(pes_packets[11][14..24] == [0, 0, 0, 1, 0x09, 0xf0, 0, 0, 0, 1]);
+        assert!(pes_packets[11][24] == 0x06);
+        assert!(pes_packets[10][14..] == [0, 0, 0, 1, 0x09, 0xf0, 0, 0, 1, 0x41, 0x88, 0x84, 0x21]);
+    }
+
+    #[test]
+    fn timestamp_wrap_rebases() {
+        let start = (1 << 33) - 25 * FRAME_TICKS;
+        let pes_packets = remux(&transport_stream(
+            (0..50).map(|frame| (start + frame * FRAME_TICKS) % (1 << 33)),
+        ));
+        // a fresh header after the wrap, the chunk numbering and cues carry on
+        assert!(containing(&pes_packets, b"English") == [0, 25]);
+        assert!(containing(&pes_packets, b"Hello") == [11, 23, 36, 48]);
+    }
+}
@@ -490,6 +490,14 @@ void set_text_callback(uint64_t possible_end_ts, struct transcription_filter_dat
 };
 
 #ifdef ENABLE_WEBVTT
+static uint8_t *append_to_darray(void *context, size_t length)
+{
+	auto array = static_cast<struct darray *>(context);
+	auto offset = array->num;
+	darray_resize(sizeof(uint8_t), array, offset + length);
+	return static_cast<uint8_t *>(array->array) + offset;
+}
+
 void output_packet_added_callback(obs_output_t *output, struct encoder_packet *pkt,
 				  struct encoder_packet_time *pkt_time, void *param)
 {
@@ -516,16 +524,18 @@ void output_packet_added_callback(obs_output_t *output, struct encoder_packet *p
 			if (!encoder)
 				continue;
 
-			auto &codec_flavor = it->codec_flavor[i];
-			if (strcmp(obs_encoder_get_codec(encoder), "h264") == 0) {
-				codec_flavor = H264AnnexB;
-			} else if (strcmp(obs_encoder_get_codec(encoder), "av1") == 0) {
-				codec_flavor = AV1OBUs;
-			} else if (strcmp(obs_encoder_get_codec(encoder), "hevc") == 0) {
-				codec_flavor = H265AnnexB;
-			} else {
-				continue;
+			uint8_t *extra_data = nullptr;
+			size_t extra_data_size = 0;
+			if (!obs_encoder_get_extra_data(encoder, &extra_data, &extra_data_size)) {
+				extra_data = nullptr;
+				extra_data_size = 0;
 			}
+			uint8_t codec_flavor = 0;
+			if (!webvtt_codec_flavor_from_extradata(obs_encoder_get_codec(encoder),
+								extra_data, extra_data_size,
+								&codec_flavor))
+				continue;
+			it->codec_flavor[i] = static_cast<CodecFlavor>(codec_flavor);
 
 			auto video = obs_encoder_video(encoder);
 			auto voi = video_output_get_info(video);
@@ -557,24 +567,38 @@ void output_packet_added_callback(obs_output_t *output, struct encoder_packet *p
 	if (!muxer)
 		return;
 
-	std::unique_ptr<WebvttBuffer, webvtt_buffer_deleter> buffer{
-		webvtt_muxer_try_mux_into_bytestream(muxer.get(), pkt_time->cts, pkt->keyframe,
-						     it->codec_flavor[pkt->track_idx])};
-
-	if (!buffer)
-		return;
-
 	long ref = 1;
 
 	DARRAY(uint8_t) out_data;
 	da_init(out_data);
-	da_reserve(out_data, sizeof(ref) + pkt->size + webvtt_buffer_length(buffer.get()));
-
-	// Copy the original packet
 	da_push_back_array(out_data, (uint8_t *)&ref, sizeof(ref));
-	da_push_back_array(out_data, pkt->data, pkt->size);
-	da_push_back_array(out_data, webvtt_buffer_data(buffer.get()),
-			   webvtt_buffer_length(buffer.get()));
+
+	// cts is the presentation time of the frame, packets arrive in decode order though (e.g.
+	// with B-frames), so move it back by the packet's PTS to DTS distance for the decode time
+	uint64_t decode_timestamp = pkt_time->cts;
+	if (pkt->pts > pkt->dts) {
+		uint64_t pts_to_dts_ns = util_mul_div64((uint64_t)(pkt->pts - pkt->dts),
+							1000000000ULL * pkt->timebase_num,
+							pkt->timebase_den);
+		decode_timestamp -= std::min(decode_timestamp, pts_to_dts_ns);
+	}
+
+	// Appends the original packet with the WebVTT data inserted before the first VCL NAL unit
+	WebvttBuffer *unappended = nullptr;
+	auto outcome = webvtt_muxer_try_mux_packet_into_sink(
+		muxer.get(), pkt_time->cts, decode_timestamp, pkt->keyframe,
+		it->codec_flavor[pkt->track_idx], pkt->data, pkt->size, append_to_darray,
+		&out_data.da, &unappended);
+
+	if (!outcome.data_written) {
+		if (outcome.append_failed) {
+			// the captions were consumed by the muxer but couldn't be inserted
+			obs_log(LOG_WARNING, "Failed to insert WebVTT data into packet, dropping it");
+			webvtt_buffer_free(unappended);
+		}
+		da_free(out_data);
+		return;
+	}
 
 	auto old_pkt = *pkt;
 	obs_encoder_packet_release(pkt);
@@ -625,6 +649,29 @@ void remove_webvtt_output(transcription_filter_data &gf, obs_output_t *output)
 	}
 }
 
+void pause_webvtt_output(transcription_filter_data &gf, obs_output_t *output, bool paused)
+{
+	auto now = now_ms();
+
+	auto lock = std::unique_lock(gf.active_outputs_mutex);
+	for (auto &webvtt_output : gf.active_outputs) {
+		if (!obs_weak_output_references_output(webvtt_output.output, output))
+			continue;
+
+		auto timestamp_ms = now - webvtt_output.start_timestamp_ms;
+		for (auto &muxer : webvtt_output.webvtt_muxer) {
+			if (!muxer)
+				continue;
+
+			if (paused)
+				webvtt_muxer_pause(muxer.get(), timestamp_ms);
+			else
+				webvtt_muxer_resume(muxer.get(), timestamp_ms);
+		}
+		return;
+	}
+}
+
 void remove_all_webvtt_outputs(std::unique_lock<std::mutex> & /*active_outputs_lock*/,
 			       transcription_filter_data &gf)
 {
@@ -680,6 +727,16 @@ void recording_state_callback(enum obs_frontend_event event, void *data)
 #ifdef ENABLE_WEBVTT
 		remove_webvtt_output(*gf_,
 				     OBSOutputAutoRelease{obs_frontend_get_recording_output()});
+#endif
+	} else if (event == OBS_FRONTEND_EVENT_RECORDING_PAUSED) {
+#ifdef ENABLE_WEBVTT
+		pause_webvtt_output(*gf_, OBSOutputAutoRelease{obs_frontend_get_recording_output()},
+				    true);
+#endif
+	} else if (event == OBS_FRONTEND_EVENT_RECORDING_UNPAUSED) {
+#ifdef ENABLE_WEBVTT
+		pause_webvtt_output(*gf_, OBSOutputAutoRelease{obs_frontend_get_recording_output()},
+				    false);
 #endif
 	} else if (event == OBS_FRONTEND_EVENT_RECORDING_STOPPED) {
 		if (!gf_->save_only_while_recording || !gf_->rename_file_to_match_recording) {